@@ -0,0 +1,49 @@
+//! Embed the interpreter as a library: run a ROM with no window or audio device, then print the
+//! display as text.
+//!
+//! ```text
+//! cargo run --example headless_run --features test-support -- [ROM] [FRAMES]
+//! ```
+//!
+//! Without a ROM, a built-in program that draws the digits 0-7 is run.
+
+use chip8::{Chip8, Config};
+use std::env;
+use std::error::Error;
+use std::fs;
+
+// Draw the font digits 0-7 in a row, then halt
+const DIGITS: [u8; 20] = [
+    0x60, 0x00, // 200: LD V0, 0
+    0x61, 0x00, // 202: LD V1, 0
+    0x62, 0x00, // 204: LD V2, 0
+    0xF0, 0x29, // 206: LD F, V0
+    0xD1, 0x25, // 208: DRW V1, V2, 5
+    0x71, 0x05, // 20A: ADD V1, 5
+    0x70, 0x01, // 20C: ADD V0, 1
+    0x30, 0x08, // 20E: SE V0, 8
+    0x12, 0x06, // 210: JP 206
+    0x12, 0x12, // 212: JP 212
+];
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let rom = match args.next() {
+        Some(path) => fs::read(path)?,
+        None => DIGITS.to_vec(),
+    };
+    let frames: usize = match args.next() {
+        Some(frames) => frames.parse()?,
+        None => 120,
+    };
+
+    let mut chip8 = Chip8::headless(Config::default());
+    chip8.load(&rom)?;
+    for _ in 0..frames {
+        chip8.run_frame()?;
+    }
+
+    print!("{}", chip8.framebuffer().text());
+    println!("{}", chip8.cpu());
+    Ok(())
+}
@@ -1,57 +1,138 @@
+use clap::ValueEnum;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 use std::error::Error;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// The shape of the beep's wave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    /// The wave's value, from -1.0 to 1.0, at `phase` through a cycle, from 0.0 to 1.0.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// The tone played while the sound timer is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    /// Pitch of the beep in hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            frequency: 587.33,
+            waveform: Waveform::default(),
+        }
+    }
+}
 
 #[cfg_attr(test, automock)]
 pub trait Audio {
     fn play(&mut self);
     fn pause(&mut self);
+
+    /// Set the loudness from 0.0, silent, to 1.0. Values outside that range are clamped.
+    fn set_volume(&mut self, volume: f32);
+}
+
+/// Audio that makes no sound, for running without an output device.
+#[derive(Debug, Default)]
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn play(&mut self) {}
+    fn pause(&mut self) {}
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+/// `volume` limited to 0.0 to 1.0, with NaN treated as silence.
+fn clamp_volume(volume: f32) -> f32 {
+    if volume.is_nan() {
+        0.0
+    } else {
+        volume.clamp(0.0, 1.0)
+    }
 }
 
 pub struct Chip8Audio {
     stream: cpal::Stream,
     is_paused: bool,
+    // The f32 volume's bits, shared with the stream's callback thread
+    volume: Arc<AtomicU32>,
 }
 
 impl Chip8Audio {
-    pub fn new() -> Result<Chip8Audio, Box<dyn Error>> {
+    /// Open the default output device. Fails if there is none or it can't be played to.
+    pub fn new(audio_config: AudioConfig) -> Result<Chip8Audio, Box<dyn Error>> {
         let host = cpal::default_host();
         let device = host
             .default_output_device()
-            .expect("no output device detected");
+            .ok_or("no output device detected")?;
         let config = device.default_output_config()?;
+        let volume = Arc::new(AtomicU32::new(1f32.to_bits()));
 
         let stream = match config.sample_format() {
-            cpal::SampleFormat::F32 => Self::build_stream::<f32>(&device, &config.into()),
-            cpal::SampleFormat::I16 => Self::build_stream::<i16>(&device, &config.into()),
-            cpal::SampleFormat::U16 => Self::build_stream::<u16>(&device, &config.into()),
+            cpal::SampleFormat::F32 => {
+                Self::build_stream::<f32>(&device, &config.into(), audio_config, volume.clone())
+            }
+            cpal::SampleFormat::I16 => {
+                Self::build_stream::<i16>(&device, &config.into(), audio_config, volume.clone())
+            }
+            cpal::SampleFormat::U16 => {
+                Self::build_stream::<u16>(&device, &config.into(), audio_config, volume.clone())
+            }
         }?;
         Ok(Chip8Audio {
             stream,
             is_paused: true,
+            volume,
         })
     }
 
     fn build_stream<T>(
         device: &cpal::Device,
         config: &cpal::StreamConfig,
-    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+        audio_config: AudioConfig,
+        volume: Arc<AtomicU32>,
+    ) -> Result<cpal::Stream, Box<dyn Error>>
     where
         T: cpal::Sample,
     {
         let sample_rate = config.sample_rate.0 as f32;
         let channels = config.channels as usize;
 
-        // Produce a square wave at half amplitude.
-        let scale = 0.5f32;
-        let mut sample_clock = 0f32;
+        // Produce the configured wave, at half amplitude when at full volume.
+        let step = audio_config.frequency / sample_rate;
+        let mut phase = 0f32;
         let mut next_value = move || {
-            sample_clock = (sample_clock + 1.0) % sample_rate;
-            (sample_clock * 587.33 * 2.0 * std::f32::consts::PI / sample_rate)
-                .sin()
-                .signum()
-                * scale
+            phase = (phase + step) % 1.0;
+            let scale = 0.5 * f32::from_bits(volume.load(Ordering::Relaxed));
+            audio_config.waveform.sample(phase) * scale
         };
 
         let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
@@ -63,7 +144,7 @@ impl Chip8Audio {
             },
             err_fn,
         )?;
-        stream.pause().expect("failed to pause audio");
+        stream.pause()?;
         Ok(stream)
     }
 
@@ -94,4 +175,37 @@ impl Audio for Chip8Audio {
             self.is_paused = true;
         }
     }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.volume
+            .store(clamp_volume(volume).to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms_span_full_range() {
+        let samples = |waveform: Waveform| -> Vec<f32> {
+            [0.0, 0.25, 0.5, 0.75]
+                .iter()
+                .map(|phase| (waveform.sample(*phase) * 100.0).round() / 100.0)
+                .collect()
+        };
+
+        assert_eq!(vec![1.0, 1.0, -1.0, -1.0], samples(Waveform::Square));
+        assert_eq!(vec![0.0, 1.0, 0.0, -1.0], samples(Waveform::Sine));
+        assert_eq!(vec![-1.0, 0.0, 1.0, 0.0], samples(Waveform::Triangle));
+        assert_eq!(vec![-1.0, -0.5, 0.0, 0.5], samples(Waveform::Sawtooth));
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        assert_eq!(0.25, clamp_volume(0.25));
+        assert_eq!(1.0, clamp_volume(3.0));
+        assert_eq!(0.0, clamp_volume(-1.0));
+        assert_eq!(0.0, clamp_volume(f32::NAN));
+    }
 }
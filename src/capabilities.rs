@@ -0,0 +1,52 @@
+use crate::platform::Platform;
+use clap::ValueEnum;
+
+/// What this build of the interpreter supports, for frontends and bug reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub platforms: Vec<Platform>,
+    /// Configurable behaviours that differ between interpreters
+    pub quirks: Vec<&'static str>,
+    /// Window, audio and input backends compiled in
+    pub backends: Vec<&'static str>,
+}
+
+/// Report what this build supports.
+pub fn capabilities() -> Capabilities {
+    let mut backends = vec![
+        "minifb",
+        "cpal",
+        "headless",
+        "null-audio",
+        "png-frames",
+        "metrics",
+    ];
+    if cfg!(unix) {
+        backends.push("remote-input");
+    }
+
+    Capabilities {
+        version: crate::VERSION,
+        platforms: Platform::value_variants().to_vec(),
+        quirks: vec!["coordinates", "memory-overflow", "shift", "unknown-opcodes"],
+        backends,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_all_platforms() {
+        let capabilities = capabilities();
+
+        assert_eq!(
+            vec![Platform::Chip8, Platform::SuperChip, Platform::XoChip],
+            capabilities.platforms
+        );
+        assert_eq!(env!("CARGO_PKG_VERSION"), capabilities.version);
+        assert!(capabilities.backends.contains(&"headless"));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::framebuffer::FrameBuffer;
+use crate::window::{DisplayColors, Window};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Encode `frame` as an RGB PNG in `colors`, with each logical pixel as a `scale` by `scale`
+/// block, as the window paints it.
+pub fn write_png<W: Write>(
+    writer: W,
+    frame: &FrameBuffer,
+    colors: &DisplayColors,
+    scale: usize,
+) -> io::Result<()> {
+    let (width, height) = (frame.width() * scale, frame.height() * scale);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = colors
+        .paint(frame, scale)
+        .into_iter()
+        .flat_map(|color| [(color >> 16) as u8, (color >> 8) as u8, color as u8])
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+/// Write `frame` to `dir` as `screenshot_<milliseconds since the epoch>.png`, painted as with
+/// `write_png`, and return the path written.
+pub fn save_screenshot(
+    dir: &Path,
+    frame: &FrameBuffer,
+    colors: &DisplayColors,
+    scale: usize,
+) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(io::Error::other)?
+        .as_millis();
+    let path = dir.join(format!("screenshot_{}.png", timestamp));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    write_png(&mut writer, frame, colors, scale)?;
+    writer.flush()?;
+    Ok(path)
+}
+
+/// Save a `width` by `height` region of the display to `path` as raw sprite bytes, see
+/// `Window::export_sprite`.
+pub fn save_sprite(
+    path: &Path,
+    window: &dyn Window,
+    x: u8,
+    y: u8,
+    width: u8,
+    height: u8,
+) -> io::Result<()> {
+    fs::write(path, window.export_sprite(x, y, width, height))
+}
+
+/// Draw the sprite bytes in `path` at `x`, `y`, as saved by `save_sprite` with the same `width`.
+/// Return true if a collision has occurred.
+pub fn load_sprite(
+    path: &Path,
+    window: &mut dyn Window,
+    x: u8,
+    y: u8,
+    width: u8,
+) -> io::Result<bool> {
+    Ok(window.draw(x, y, width, fs::read(path)?))
+}
+
+/// Writes frames to a directory as `frame_00001.png`, `frame_00002.png`, ... on a background
+/// thread so that encoding doesn't hold up emulation.
+pub struct FrameRecorder {
+    frames: Sender<FrameBuffer>,
+}
+
+impl FrameRecorder {
+    /// Create `dir` if needed and start the writer thread. Frames are painted in `colors` at
+    /// logical resolution.
+    pub fn new(dir: &Path, colors: DisplayColors) -> io::Result<FrameRecorder> {
+        fs::create_dir_all(dir)?;
+        let dir = dir.to_path_buf();
+        let (frames, received) = mpsc::channel::<FrameBuffer>();
+
+        thread::spawn(move || {
+            for (number, frame) in received.into_iter().enumerate() {
+                let path = Self::frame_path(&dir, number + 1);
+                let result = File::create(&path).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    write_png(&mut writer, &frame, &colors, 1)?;
+                    writer.flush()
+                });
+                if let Err(err) = result {
+                    eprintln!("Failed to write {}: {}", path.display(), err);
+                    return;
+                }
+            }
+        });
+
+        Ok(FrameRecorder { frames })
+    }
+
+    /// Queue a frame to be written.
+    pub fn record(&self, frame: &FrameBuffer) {
+        // The writer thread only stops after reporting an error, so there is nothing to add here
+        let _ = self.frames.send(frame.clone());
+    }
+
+    fn frame_path(dir: &Path, number: usize) -> PathBuf {
+        dir.join(format!("frame_{:05}.png", number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Palette;
+    use crate::window::HeadlessWindow;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn screenshot_is_scaled_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut frame = FrameBuffer::new();
+        frame.draw(1, 0, 8, &[0x80]);
+
+        let colors = DisplayColors::new(Palette::from_colors(0x00FFFFFF, 0x00000000), None, 0.0);
+
+        let path = save_screenshot(dir.path(), &frame, &colors, 2).unwrap();
+
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("screenshot_") && name.ends_with(".png"));
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!(
+            (
+                FrameBuffer::WIDTH as u32 * 2,
+                FrameBuffer::HEIGHT as u32 * 2
+            ),
+            (info.width, info.height)
+        );
+        // Logical pixel (1, 0) covers output pixels 2 and 3 of the first two rows
+        let row = info.line_size;
+        for offset in [0, row] {
+            assert_eq!(image[offset + 3..offset + 6], [0x00; 3]);
+            assert_eq!(image[offset + 6..offset + 12], [0xFF; 6]);
+            assert_eq!(image[offset + 12..offset + 15], [0x00; 3]);
+        }
+    }
+
+    #[test]
+    fn png_round_trips_pixels() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(1, 0, 8, &[0x80]);
+        let mut data = Vec::new();
+
+        let colors = DisplayColors::new(Palette::from_colors(0x00FFBF00, 0x00000010), None, 0.0);
+
+        write_png(&mut data, &frame, &colors, 1).unwrap();
+
+        let decoder = png::Decoder::new(data.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!(
+            (FrameBuffer::WIDTH as u32, FrameBuffer::HEIGHT as u32),
+            (info.width, info.height)
+        );
+        assert_eq!([0x00, 0x00, 0x10, 0xFF, 0xBF, 0x00], image[..6]);
+    }
+
+    #[test]
+    fn png_shows_both_planes_clear_color_and_scanlines() {
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b11);
+        // Pixel 0 lit on plane 2 only, pixel 1 on both planes, pixel 2 never drawn
+        frame.draw(0, 0, 8, &[0b0100_0000, 0b1100_0000]);
+        let palette = Palette::new([0x000000, 0xFF0000, 0x00FF00, 0x0000FF]);
+        let colors = DisplayColors::new(palette, Some(0x102030), 1.0);
+        let mut data = Vec::new();
+
+        write_png(&mut data, &frame, &colors, 2).unwrap();
+
+        let decoder = png::Decoder::new(data.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!(
+            [0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0x10, 0x20, 0x30],
+            [&image[0..3], &image[6..9], &image[12..15]].concat()[..]
+        );
+        // Every other output row is a scanline, darkened to black
+        let row = info.line_size;
+        assert!(image[row..row * 2].iter().all(|channel| *channel == 0));
+    }
+
+    #[test]
+    fn sprite_round_trips_through_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sprite.bin");
+        let mut window = HeadlessWindow::new();
+        window.draw(3, 1, 16, vec![0xC0, 0x03, 0x81, 0x18]);
+
+        save_sprite(&path, &window, 3, 1, 16, 2).unwrap();
+        let mut copy = HeadlessWindow::new();
+        let collision = load_sprite(&path, &mut copy, 3, 1, 16).unwrap();
+
+        assert!(!collision);
+        assert_eq!(vec![0xC0, 0x03, 0x81, 0x18], fs::read(&path).unwrap());
+        assert_eq!(window.frame(), copy.frame());
+    }
+
+    #[test]
+    fn recorder_writes_numbered_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let palette = Palette::new([0x000000, 0xFF0000, 0x00FF00, 0x0000FF]);
+        let recorder =
+            FrameRecorder::new(dir.path(), DisplayColors::new(palette, None, 0.0)).unwrap();
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b10);
+        frame.draw(0, 0, 8, &[0b1000_0000]);
+
+        recorder.record(&frame);
+        recorder.record(&FrameBuffer::new());
+        drop(recorder);
+
+        let second = dir.path().join("frame_00002.png");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while fs::metadata(&second).map(|m| m.len()).unwrap_or(0) == 0 {
+            assert!(Instant::now() < deadline, "frames were never written");
+            thread::sleep(Duration::from_millis(1));
+        }
+        // Frames are written in order, so the first is complete once the second has started
+        let decoder = png::Decoder::new(File::open(dir.path().join("frame_00001.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut image).unwrap();
+        assert_eq!([0x00, 0xFF, 0x00, 0x00, 0x00, 0x00], image[0..6]);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::audio::AudioConfig;
+use crate::cpu::{CoordinateMode, MemoryOverflow, OnError, SpriteReads, UnknownOpcodes};
+use crate::keypad::KeypadLayout;
+use crate::platform::Platform;
+use crate::quirks::{QuirkOverrides, Quirks};
+use crate::theme::{Palette, Theme};
+use crate::window::{DisplayColors, PixelAspect, WindowScale};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Frequency changes to make once the CPU has run a given number of cycles, e.g. to ramp up the
+/// speed of a demo.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrequencySchedule {
+    // (cycle, frequency) pairs, sorted by cycle
+    changes: Vec<(u64, u32)>,
+}
+
+impl FrequencySchedule {
+    /// A schedule that switches to each frequency once its cycle is reached.
+    pub fn new(mut changes: Vec<(u64, u32)>) -> FrequencySchedule {
+        // Stable, so the last of several changes at the same cycle wins
+        changes.sort_by_key(|(cycle, _)| *cycle);
+        FrequencySchedule { changes }
+    }
+
+    /// The frequency to run at once `cycle` cycles have run, given the starting `frequency`.
+    pub fn frequency_at(&self, frequency: u32, cycle: u64) -> u32 {
+        match self.changes.partition_point(|(at, _)| *at <= cycle) {
+            0 => frequency,
+            n => self.changes[n - 1].1,
+        }
+    }
+}
+
+/// Options controlling how the interpreter is run.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// CPU frequency in hz
+    pub frequency: u32,
+    /// Changes to the frequency at given cycle counts
+    pub frequency_schedule: FrequencySchedule,
+    /// Log the number of instructions executed between each 60hz tick
+    pub log_ipf: bool,
+    /// Byte used to pad a ROM with an odd length
+    pub pad_byte: u8,
+    /// Bytes written over memory after the ROM is loaded, before it runs
+    pub pokes: Vec<(arbintrary::uint<12>, u8)>,
+    /// Sleep until the next 60hz tick while the program is busy-waiting on the delay timer
+    pub idle_throttle: bool,
+    /// Display colour preset
+    pub theme: Theme,
+    /// 0RGB colour for lit pixels, or None for the theme's. Also replaces `palette`'s plane 1
+    /// colour
+    pub foreground: Option<u32>,
+    /// 0RGB colour for unlit pixels, or None for the theme's. Also replaces `palette`'s colour
+    /// for no plane
+    pub background: Option<u32>,
+    /// How much to darken alternate rows of output, from 0 (off) to 1
+    pub scanlines: f32,
+    /// 0RGB colour for pixels nothing has drawn on since the last clear, or None for the theme's
+    /// background
+    pub clear_color: Option<u32>,
+    /// Colours for the four XO-CHIP plane combinations, or None for the theme's
+    pub palette: Option<Palette>,
+    /// Shape of each presented pixel, width:height
+    pub pixel_aspect: PixelAspect,
+    /// Screen pixels per CHIP-8 pixel, before the pixel aspect is applied
+    pub scale: WindowScale,
+    /// Which keyboard keys stand in for the hex keypad
+    pub keypad_layout: KeypadLayout,
+    /// CHIP-8 variant to emulate, or None to detect it from the ROM's opcodes
+    pub platform: Option<Platform>,
+    /// Seed for the random number generator, for reproducible runs
+    pub seed: Option<u64>,
+    /// Start with random register and low memory contents instead of zeros
+    pub randomize_startup: bool,
+    /// Show the ROM name, IPS, FPS and program counter in the window title, updated each second
+    pub title_stats: bool,
+    /// How far each second's IPS and FPS move the reported rates, from just above 0 (smoothest)
+    /// to 1 (raw)
+    pub rate_smoothing: f64,
+    /// Log draws that had pixels cut off at the screen edge
+    pub log_clipped_draws: bool,
+    /// What FX55 and FX65 do when I + X runs past the end of memory
+    pub memory_overflow: MemoryOverflow,
+    /// Run without a window, with a warning, if one can't be opened instead of failing
+    pub window_fallback: bool,
+    /// Opcode behaviours that differ between CHIP-8 variants. `None` follows the platform, or
+    /// uses the defaults if the platform is neither given nor detected.
+    pub quirks: Option<Quirks>,
+    /// Quirks to force on or off on top of `quirks`
+    pub quirk_overrides: QuirkOverrides,
+    /// How DXYN treats sprite origins that are off the screen
+    pub coordinate_mode: CoordinateMode,
+    /// What DXYN reads for sprite rows past the end of memory
+    pub sprite_reads: SpriteReads,
+    /// Extra cycles each DXYN consumes, to mimic slow hardware
+    pub draw_cycles: u32,
+    /// What to do with undefined opcodes
+    pub unknown_opcodes: UnknownOpcodes,
+    /// Whether `run` stops or skips the instruction when the CPU fails
+    pub on_error: OnError,
+    /// Non-standard debugging aid: 00E0 does not clear the screen
+    pub ignore_clear: bool,
+    /// Stop cleanly when a 1NNN jumps to itself, the usual way a ROM ends. Ignored when
+    /// `loop_delay` is set, as the program is replayed instead
+    pub halt_on_infinite_loop: bool,
+    /// On a panic, print the instruction that was running and the registers
+    pub dump_state_on_panic: bool,
+    /// Only show the display once per frame, instead of as each change is made
+    pub double_buffer: bool,
+    /// Present at most this many display changes per 60hz frame, coalescing the rest
+    pub max_presents_per_frame: Option<u32>,
+    /// Unix domain socket to accept `down <key>`/`up <key>` keypad messages on
+    pub remote_input: Option<PathBuf>,
+    /// Once the program halts on a self-jump, soft reset it after this many seconds
+    pub loop_delay: Option<f64>,
+    /// Directory to write every rendered frame to as numbered PNGs
+    pub frames_dir: Option<PathBuf>,
+    /// File to load the SCHIP flag registers (FX75/FX85) from at startup and save them to as they
+    /// change, so high scores survive restarts
+    pub flags_file: Option<PathBuf>,
+    /// Serve Prometheus-style metrics over HTTP on this address
+    pub metrics_addr: Option<SocketAddr>,
+    /// The beep's pitch and waveform
+    pub audio: AudioConfig,
+    /// Loudness of the beep from 0.0, silent, to 1.0
+    pub volume: f32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            frequency: 500,
+            frequency_schedule: FrequencySchedule::default(),
+            log_ipf: false,
+            pad_byte: 0x00,
+            pokes: Vec::new(),
+            idle_throttle: false,
+            theme: Theme::default(),
+            foreground: None,
+            background: None,
+            scanlines: 0.0,
+            clear_color: None,
+            palette: None,
+            pixel_aspect: PixelAspect::default(),
+            scale: WindowScale::default(),
+            keypad_layout: KeypadLayout::default(),
+            platform: None,
+            seed: None,
+            randomize_startup: false,
+            title_stats: false,
+            rate_smoothing: 0.3,
+            log_clipped_draws: false,
+            memory_overflow: MemoryOverflow::default(),
+            window_fallback: true,
+            quirks: None,
+            quirk_overrides: QuirkOverrides::default(),
+            coordinate_mode: CoordinateMode::default(),
+            sprite_reads: SpriteReads::default(),
+            draw_cycles: 0,
+            unknown_opcodes: UnknownOpcodes::default(),
+            on_error: OnError::default(),
+            ignore_clear: false,
+            halt_on_infinite_loop: false,
+            dump_state_on_panic: false,
+            double_buffer: false,
+            max_presents_per_frame: None,
+            remote_input: None,
+            loop_delay: None,
+            frames_dir: None,
+            flags_file: None,
+            metrics_addr: None,
+            audio: AudioConfig::default(),
+            volume: 1.0,
+        }
+    }
+}
+
+impl Config {
+    /// CPU cycles to run for each 60hz timer tick at the configured frequency.
+    pub fn cycles_per_frame(&self) -> f64 {
+        cycles_per_frame(self.frequency, 60)
+    }
+
+    /// The (foreground, background) pair: the theme's, with `foreground` and `background`
+    /// replacing either.
+    pub fn colors(&self) -> (u32, u32) {
+        let (foreground, background) = self.theme.colors();
+        (
+            self.foreground.unwrap_or(foreground),
+            self.background.unwrap_or(background),
+        )
+    }
+
+    /// The palette, clear colour and scanlines the display is shown with.
+    pub fn display_colors(&self) -> DisplayColors {
+        DisplayColors::new(self.display_palette(), self.clear_color, self.scanlines)
+    }
+
+    /// The colours to display: `palette` if set, with `foreground` and `background` replacing
+    /// its plane 1 and no plane colours, otherwise one extended from `colors`.
+    pub fn display_palette(&self) -> Palette {
+        match self.palette {
+            Some(palette) => {
+                let palette = match self.foreground {
+                    Some(foreground) => palette.with_color(0b01, foreground),
+                    None => palette,
+                };
+                match self.background {
+                    Some(background) => palette.with_color(0b00, background),
+                    None => palette,
+                }
+            }
+            None => {
+                let (foreground, background) = self.colors();
+                Palette::from_colors(foreground, background)
+            }
+        }
+    }
+}
+
+/// CPU cycles per timer tick at `frequency` hz. The fraction is kept, so 90hz at 60hz is 1.5, and
+/// below `timer_hz` it is under 1: at 30hz an instruction runs every other tick.
+pub fn cycles_per_frame(frequency: u32, timer_hz: u32) -> f64 {
+    frequency as f64 / timer_hz.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn colors_override_theme() {
+        let config = Config {
+            theme: Theme::Lcd,
+            background: Some(0x00123456),
+            ..Config::default()
+        };
+
+        assert_eq!((0x000F380F, 0x00123456), config.colors());
+        assert_eq!(0x00123456, config.display_palette().color(0b00));
+        assert_eq!(0x000F380F, config.display_palette().color(0b01));
+    }
+
+    #[test]
+    fn colors_override_palette() {
+        let palette = Palette::new([0x000000, 0x111111, 0x222222, 0x333333]);
+        let config = Config {
+            palette: Some(palette),
+            foreground: Some(0x00ABCDEF),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            Palette::new([0x000000, 0x00ABCDEF, 0x222222, 0x333333]),
+            config.display_palette()
+        );
+        assert_eq!(
+            Palette::new([0x00123456, 0x111111, 0x222222, 0x333333]),
+            Config {
+                palette: Some(palette),
+                background: Some(0x00123456),
+                ..Config::default()
+            }
+            .display_palette()
+        );
+    }
+
+    #[rstest]
+    #[case(540, 60, 9.0)]
+    #[case(600, 60, 10.0)]
+    #[case(90, 60, 1.5)]
+    #[case(30, 60, 0.5)]
+    #[case(15, 60, 0.25)]
+    #[case(0, 60, 0.0)]
+    fn computes_cycles_per_frame(
+        #[case] frequency: u32,
+        #[case] timer_hz: u32,
+        #[case] cycles: f64,
+    ) {
+        assert_eq!(cycles, cycles_per_frame(frequency, timer_hz));
+    }
+
+    #[test]
+    fn schedule_changes_frequency_at_cycle() {
+        let schedule = FrequencySchedule::new(vec![(1000, 2000), (100, 1000), (100, 800)]);
+
+        assert_eq!(500, schedule.frequency_at(500, 0));
+        assert_eq!(500, schedule.frequency_at(500, 99));
+        assert_eq!(800, schedule.frequency_at(500, 100));
+        assert_eq!(800, schedule.frequency_at(500, 999));
+        assert_eq!(2000, schedule.frequency_at(500, 1000));
+        assert_eq!(2000, schedule.frequency_at(500, u64::MAX));
+        assert_eq!(500, FrequencySchedule::default().frequency_at(500, 1000));
+    }
+
+    #[test]
+    fn config_uses_60hz_timers() {
+        let config = Config {
+            frequency: 690,
+            ..Config::default()
+        };
+
+        assert_eq!(11.5, config.cycles_per_frame());
+    }
+}
@@ -1,9 +1,222 @@
 use super::audio::Audio;
 use super::mmu::Mmu;
 use super::window::Window;
-use crate::mmu::Chip8Mmu;
+use crate::framebuffer::FrameBuffer;
+use crate::instruction::{Instruction, OpcodeFamily};
+use crate::mmu::{Chip8Mmu, LoadError, LoadStatus};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::state::{MachineState, StateError};
 use arbintrary::uint;
-use std::collections::VecDeque;
+use clap::ValueEnum;
+use fastrand::Rng;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+/// What a CPU was doing when it started its latest instruction, kept for reporting panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastInstruction {
+    pub program_counter: uint<12>,
+    pub opcode: u16,
+    pub index: uint<12>,
+    pub registers: [u8; 16],
+}
+
+impl fmt::Display for LastInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC={:#05X} opcode={:04X} I={:#05X} V0-VF=",
+            u16::from(self.program_counter),
+            self.opcode,
+            u16::from(self.index)
+        )?;
+        for (i, register) in self.registers.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{}{:02X}", separator, register)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static LAST_INSTRUCTION: Cell<Option<LastInstruction>> = const { Cell::new(None) };
+}
+
+/// The instruction a CPU on this thread last started, if it is recording them. Safe to call from
+/// a panic hook.
+pub fn last_instruction() -> Option<LastInstruction> {
+    LAST_INSTRUCTION.with(Cell::get)
+}
+
+/// The state an instruction may change, recorded before it runs so it can be undone.
+struct UndoEntry {
+    registers: Vec<u8>,
+    index: uint<12>,
+    program_counter: uint<12>,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: VecDeque<uint<12>>,
+    key_latch: Option<u8>,
+    flags: [u8; Cpu::FLAG_COUNT],
+    hires: bool,
+    selected_planes: u8,
+    // Previous value of each memory byte written, in write order
+    memory: Vec<(uint<12>, u8)>,
+}
+
+/// An instruction that could not be executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// FX55 or FX65 would access memory past 0xFFF
+    MemoryOverflow { index: uint<12>, length: usize },
+    /// The opcode isn't defined for the current platform
+    UnknownOpcode(u16),
+    /// 00EE was executed with nothing on the stack
+    StackUnderflow { program_counter: uint<12> },
+    /// 2NNN was executed with all 16 stack slots in use
+    StackOverflow { program_counter: uint<12> },
+    /// The opcode was 0000, which almost always means the program counter ran past the end of
+    /// the ROM into unused memory
+    ExecutingEmptyMemory { program_counter: uint<12> },
+    /// A 1NNN jumped to its own address, the usual way a ROM ends, with halting enabled
+    Halt { program_counter: uint<12> },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::MemoryOverflow { index, length } => write!(
+                f,
+                "accessing {} bytes at I={:#05X} runs past the end of memory",
+                length,
+                u16::from(*index)
+            ),
+            CpuError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+            CpuError::StackUnderflow { program_counter } => write!(
+                f,
+                "return with an empty stack at PC={:#05X}",
+                u16::from(*program_counter)
+            ),
+            CpuError::StackOverflow { program_counter } => write!(
+                f,
+                "call with a full stack at PC={:#05X}",
+                u16::from(*program_counter)
+            ),
+            CpuError::ExecutingEmptyMemory { program_counter } => write!(
+                f,
+                "executing empty memory (opcode 0000) at PC={:#05X}, past the end of the program?",
+                u16::from(*program_counter)
+            ),
+            CpuError::Halt { program_counter } => write!(
+                f,
+                "halted on a jump to itself at PC={:#05X}",
+                u16::from(*program_counter)
+            ),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+/// What FX55 and FX65 do when I + X runs past the end of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MemoryOverflow {
+    /// Wrap around to address 0x000, overwriting the font
+    #[default]
+    Wrap,
+    /// Stop at 0xFFF. Registers past the end are not stored or loaded.
+    Clamp,
+    /// Fail with `CpuError::MemoryOverflow` without accessing memory
+    Error,
+}
+
+/// What DXYN reads for sprite rows past the end of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SpriteReads {
+    /// Wrap around to address 0x000, drawing the font as sprite data
+    #[default]
+    Wrap,
+    /// Read blank rows, as most modern interpreters do
+    Zero,
+}
+
+/// What to do with an opcode that isn't defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum UnknownOpcodes {
+    /// Fail with `CpuError::UnknownOpcode`
+    #[default]
+    Error,
+    /// Skip it as if it were a no-op
+    Ignore,
+}
+
+/// What the run loop does when the CPU fails with anything but a halt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OnError {
+    /// Stop and report the error
+    #[default]
+    Halt,
+    /// Log the error, skip the failing instruction and keep running
+    Continue,
+}
+
+/// How DXYN turns VX and VY into the sprite's origin before drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum CoordinateMode {
+    /// Use VX and VY as they are. An origin off the screen draws nothing.
+    #[default]
+    Raw,
+    /// Wrap the origin onto the screen (x % 64, y % 32), as modern interpreters do. The rest of
+    /// the sprite is still clipped at the edge unless the `display_clipping` quirk is off
+    Modulo,
+    /// Pull an off-screen origin back to the last column or row
+    Clamp,
+}
+
+impl CoordinateMode {
+    /// The origin to draw a sprite at for registers `x` and `y` on a `width` by `height` screen.
+    pub fn origin(self, x: u8, y: u8, width: u8, height: u8) -> (u8, u8) {
+        match self {
+            CoordinateMode::Raw => (x, y),
+            CoordinateMode::Modulo => (x % width, y % height),
+            CoordinateMode::Clamp => (x.min(width - 1), y.min(height - 1)),
+        }
+    }
+}
+
+// The new program counter if an opcode changed it, otherwise None
+type OpcodeResult = Result<Option<uint<12>>, CpuError>;
+
+/// Replacement behaviour for an opcode. Returning a program counter jumps there instead of running
+/// the built-in implementation; returning None falls through to it.
+pub type OpcodeHook = Box<dyn FnMut(&mut Cpu, u16) -> Option<uint<12>>>;
+
+/// Called with the program counter and opcode of every instruction, before it runs.
+pub type InstructionCallback = Box<dyn FnMut(uint<12>, u16)>;
+
+/// Running totals of what the CPU has done since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub instructions: u64,
+    /// Emulated cycles consumed. Every instruction costs one, plus any extra for draws.
+    pub cycles: u64,
+    /// Draws that turned off at least one lit pixel
+    pub collisions: u64,
+    /// Undefined opcodes that were skipped rather than panicking or failing
+    pub unknown_opcodes: u64,
+}
+
+/// Why `Cpu::run_until` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunResult {
+    /// The program counter reached the requested address.
+    ReachedAddress,
+    /// The cycle cap was hit first.
+    CycleLimit,
+}
 
 pub struct Cpu {
     mmu: Box<dyn Mmu>,
@@ -14,16 +227,44 @@ pub struct Cpu {
     program_counter: uint<12>,
     delay_timer: u8,
     sound_timer: u8,
+    // Whether the sound timer was set nonzero since the last tick, even if it was cleared again
+    sound_this_frame: bool,
     stack: VecDeque<uint<12>>,
     key_latch: Option<u8>,
+    undo_log: VecDeque<UndoEntry>,
+    undo_limit: usize,
+    // The entry for the step being run, logged once it succeeds
+    pending_undo: Option<UndoEntry>,
+    platform: Platform,
+    rng: Rng,
+    memory_overflow: MemoryOverflow,
+    ignore_clear: bool,
+    halt_on_self_jump: bool,
+    record_last_instruction: bool,
+    hooks: HashMap<u16, OpcodeHook>,
+    instruction_callback: Option<InstructionCallback>,
+    unknown_opcodes: UnknownOpcodes,
+    quirks: Quirks,
+    coordinate_mode: CoordinateMode,
+    // SCHIP's 128x64 mode, entered with 00FF
+    hires: bool,
+    // XO-CHIP bit planes that draws, clears and scrolls act on, chosen with FN01
+    selected_planes: u8,
+    // SCHIP's HP48 flag registers, written with FX75 and read with FX85
+    flags: [u8; Cpu::FLAG_COUNT],
+    sprite_reads: SpriteReads,
+    stats: Stats,
+    draw_cycles: u32,
 }
 
 impl Cpu {
     const OPCODE_SIZE: u16 = 2;
     const REGISTER_SIZE: usize = 16;
     const STACK_SIZE: usize = 16;
+    /// How many HP48 flag registers FX75 and FX85 can use.
+    pub const FLAG_COUNT: usize = 8;
     const CARRY_REGISTER: usize = 0xF;
-    const FUNC_MAP: [fn(&mut Self, uint<12>) -> Option<uint<12>>; 16] = [
+    const FUNC_MAP: [fn(&mut Self, uint<12>) -> OpcodeResult; 16] = [
         Self::opcode_0,
         Self::opcode_1,
         Self::opcode_2,
@@ -42,7 +283,12 @@ impl Cpu {
         Self::opcode_f,
     ];
 
-    pub fn new(mmu: Box<dyn Mmu>, window: Box<dyn Window>, audio: Box<dyn Audio>) -> Cpu {
+    pub fn new(
+        mmu: Box<dyn Mmu>,
+        window: Box<dyn Window>,
+        audio: Box<dyn Audio>,
+        quirks: Quirks,
+    ) -> Cpu {
         Cpu {
             mmu,
             window,
@@ -52,24 +298,506 @@ impl Cpu {
             program_counter: uint::<12>::new(0x200),
             delay_timer: 0,
             sound_timer: 0,
+            sound_this_frame: false,
             stack: VecDeque::with_capacity(Cpu::STACK_SIZE),
             key_latch: None,
+            undo_log: VecDeque::new(),
+            undo_limit: 0,
+            pending_undo: None,
+            platform: Platform::default(),
+            rng: Rng::new(),
+            memory_overflow: MemoryOverflow::default(),
+            ignore_clear: false,
+            halt_on_self_jump: false,
+            record_last_instruction: false,
+            hooks: HashMap::new(),
+            instruction_callback: None,
+            unknown_opcodes: UnknownOpcodes::default(),
+            quirks,
+            coordinate_mode: CoordinateMode::default(),
+            hires: false,
+            selected_planes: 0b01,
+            flags: [0; Cpu::FLAG_COUNT],
+            sprite_reads: SpriteReads::default(),
+            stats: Stats::default(),
+            draw_cycles: 0,
+        }
+    }
+
+    /// Like `new`, but with the random number generator seeded with `seed` rather than from the
+    /// clock, so CXNN gives the same stream of numbers every run.
+    pub fn new_with_seed(
+        mmu: Box<dyn Mmu>,
+        window: Box<dyn Window>,
+        audio: Box<dyn Audio>,
+        quirks: Quirks,
+        seed: u64,
+    ) -> Cpu {
+        let mut cpu = Cpu::new(mmu, window, audio, quirks);
+        cpu.set_seed(seed);
+        cpu
+    }
+
+    /// Select the variant whose opcode behaviour to follow.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+
+    /// Choose how FX55 and FX65 handle I + X running past the end of memory.
+    pub fn set_memory_overflow(&mut self, memory_overflow: MemoryOverflow) {
+        self.memory_overflow = memory_overflow;
+    }
+
+    /// Switch to a different set of quirks. Takes effect from the next instruction, apart from
+    /// `display_clipping`: the window does the clipping, so it is up to whoever owns the window to
+    /// call `Window::set_wrap`, as `Chip8::load` does.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Choose how DXYN treats sprite origins that are off the screen.
+    pub fn set_coordinate_mode(&mut self, coordinate_mode: CoordinateMode) {
+        self.coordinate_mode = coordinate_mode;
+    }
+
+    /// Choose what DXYN reads for sprite rows past the end of memory.
+    pub fn set_sprite_reads(&mut self, sprite_reads: SpriteReads) {
+        self.sprite_reads = sprite_reads;
+    }
+
+    /// Make DXYN consume `draw_cycles` cycles on top of the usual one, to mimic slow hardware
+    /// where draws eat into the frame budget.
+    pub fn set_draw_cycles(&mut self, draw_cycles: u32) {
+        self.draw_cycles = draw_cycles;
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Choose what happens when an undefined opcode is executed.
+    pub fn set_unknown_opcodes(&mut self, unknown_opcodes: UnknownOpcodes) {
+        self.unknown_opcodes = unknown_opcodes;
+    }
+
+    /// Debugging aid, not standard behaviour: make 00E0 leave the screen alone so every draw
+    /// stays visible.
+    pub fn set_ignore_clear(&mut self, ignore_clear: bool) {
+        self.ignore_clear = ignore_clear;
+    }
+
+    /// Fail with `CpuError::Halt` on a 1NNN that jumps to its own address, instead of spinning
+    /// there forever.
+    pub fn set_halt_on_self_jump(&mut self, halt_on_self_jump: bool) {
+        self.halt_on_self_jump = halt_on_self_jump;
+    }
+
+    /// Record each instruction before it runs so that `last_instruction` can report it, e.g. from a
+    /// panic hook.
+    pub fn set_record_last_instruction(&mut self, record_last_instruction: bool) {
+        self.record_last_instruction = record_last_instruction;
+    }
+
+    /// Run `hook` whenever `opcode` is executed, before the built-in implementation. Replaces any
+    /// hook already set for `opcode`.
+    pub fn set_opcode_hook(&mut self, opcode: u16, hook: OpcodeHook) {
+        self.hooks.insert(opcode, hook);
+    }
+
+    pub fn clear_opcode_hook(&mut self, opcode: u16) {
+        self.hooks.remove(&opcode);
+    }
+
+    /// Run `callback` before every instruction, e.g. to trace or measure coverage. Replaces any
+    /// callback already set.
+    pub fn set_instruction_callback(&mut self, callback: InstructionCallback) {
+        self.instruction_callback = Some(callback);
+    }
+
+    pub fn clear_instruction_callback(&mut self) {
+        self.instruction_callback = None;
+    }
+
+    /// Seed the random number generator used by CXNN and `randomize_startup`, for reproducible
+    /// runs.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::with_seed(seed);
+    }
+
+    /// Fill the registers and the memory between the font and the program with random bytes, as
+    /// on a real COSMAC VIP at power on.
+    pub fn randomize_startup(&mut self) {
+        for register in self.registers.iter_mut() {
+            *register = self.rng.u8(..);
+        }
+        for address in Chip8Mmu::FONT_END..Chip8Mmu::PROGRAM_START {
+            self.mmu
+                .write_u8(uint::<12>::new(address as u16), self.rng.u8(..));
+        }
+    }
+
+    /// Clear the registers, index, program counter, stack, timers and key latch. Memory and the
+    /// display are left as they are.
+    pub fn reset(&mut self) {
+        self.registers.iter_mut().for_each(|register| *register = 0);
+        self.index = uint::<12>::new(0);
+        self.program_counter = uint::<12>::new(0x200);
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.sound_this_frame = false;
+        self.stack.clear();
+        self.key_latch = None;
+    }
+
+    /// Reset as with `reset`, but also restore the originally loaded program and blank the screen,
+    /// returning it to 64x32 with only plane 1 selected.
+    pub fn hard_reset(&mut self) {
+        self.mmu.reload_program();
+        if self.hires {
+            self.set_hires(false);
+        }
+        if self.selected_planes != 0b01 {
+            self.set_planes(0b01);
+        }
+        self.window.blank_screen();
+        self.reset();
+    }
+
+    /// The HP48 flag registers. Resets leave them alone, as they are meant to outlive the program.
+    pub fn flags(&self) -> [u8; Cpu::FLAG_COUNT] {
+        self.flags
+    }
+
+    /// Overwrite the HP48 flag registers, e.g. with ones saved by an earlier run.
+    pub fn set_flags(&mut self, flags: [u8; Cpu::FLAG_COUNT]) {
+        self.flags = flags;
+    }
+
+    /// Whether the display is in SCHIP's 128x64 mode.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.window.set_hires(hires);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.selected_planes = planes;
+        self.window.set_planes(planes);
+    }
+
+    // The display's width and height in pixels
+    fn screen_size(&self) -> (u8, u8) {
+        if self.hires {
+            (
+                FrameBuffer::HIRES_WIDTH as u8,
+                FrameBuffer::HIRES_HEIGHT as u8,
+            )
+        } else {
+            (FrameBuffer::WIDTH as u8, FrameBuffer::HEIGHT as u8)
+        }
+    }
+
+    /// Replace the program with `program` and hard reset, as if it had been loaded from the start.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<LoadStatus, LoadError> {
+        let status = self.mmu.load_program_bytes(program)?;
+        self.hard_reset();
+        Ok(status)
+    }
+
+    /// The memory the CPU runs from, e.g. to patch it between instructions.
+    pub fn mmu_mut(&mut self) -> &mut dyn Mmu {
+        self.mmu.as_mut()
+    }
+
+    /// How many return addresses are on the stack.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The return addresses on the stack, oldest first.
+    pub fn stack_contents(&self) -> Vec<uint<12>> {
+        self.stack.iter().copied().collect()
+    }
+
+    /// The key being held during an FX0A wait. FX0A completes once this key is released.
+    pub fn key_latch(&self) -> Option<u8> {
+        self.key_latch
+    }
+
+    pub fn window(&self) -> &dyn Window {
+        self.window.as_ref()
+    }
+
+    pub fn window_mut(&mut self) -> &mut dyn Window {
+        self.window.as_mut()
+    }
+
+    pub fn audio_mut(&mut self) -> &mut dyn Audio {
+        self.audio.as_mut()
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> uint<12> {
+        self.program_counter
+    }
+
+    /// V0 to VF.
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// A copy of V0 to VF.
+    pub fn registers_array(&self) -> [u8; 16] {
+        let mut registers = [0; 16];
+        registers.copy_from_slice(&self.registers);
+        registers
+    }
+
+    /// Overwrite V0 to VF, e.g. to set up a test or from a debugger.
+    pub fn set_registers(&mut self, registers: &[u8; 16]) {
+        self.registers.copy_from_slice(registers);
+    }
+
+    pub fn index(&self) -> uint<12> {
+        self.index
+    }
+
+    /// A copy of the whole of memory.
+    pub fn memory(&self) -> Vec<u8> {
+        (0..Chip8Mmu::MEM_SIZE)
+            .map(|address| self.mmu.read_u8(uint::<12>::new(address as u16)))
+            .collect()
+    }
+
+    /// Capture the registers, timers, stack, flags, display mode and memory so the CPU can later
+    /// resume from here. The display's contents aren't included.
+    pub fn save_state(&self) -> MachineState {
+        MachineState {
+            registers: self.registers_array(),
+            index: self.index,
+            program_counter: self.program_counter,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack_contents(),
+            key_latch: self.key_latch,
+            flags: self.flags,
+            hires: self.hires,
+            selected_planes: self.selected_planes,
+            memory: self.memory(),
         }
     }
 
-    pub fn run_cycle(&mut self) {
+    /// Resume from a state made by `save_state`. Nothing is changed if the state is invalid.
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), StateError> {
+        if state.memory.len() != Chip8Mmu::MEM_SIZE {
+            return Err(StateError::Invalid("memory size"));
+        }
+        if state.stack.len() > Self::STACK_SIZE {
+            return Err(StateError::Invalid("stack depth"));
+        }
+        if state.selected_planes > 0b11 {
+            return Err(StateError::Invalid("selected planes"));
+        }
+        self.mmu
+            .load_at(uint::<12>::new(0), &state.memory)
+            .expect("memory is checked to fit");
+        self.set_registers(&state.registers);
+        self.index = state.index;
+        self.program_counter = state.program_counter;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.sound_this_frame = false;
+        self.stack = state.stack.iter().copied().collect();
+        self.key_latch = state.key_latch;
+        self.flags = state.flags;
+        if self.hires != state.hires {
+            self.set_hires(state.hires);
+        }
+        if self.selected_planes != state.selected_planes {
+            self.set_planes(state.selected_planes);
+        }
+        Ok(())
+    }
+
+    /// Fetch and execute a single instruction.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        if self.undo_limit > 0 {
+            self.pending_undo = Some(UndoEntry {
+                registers: self.registers.clone(),
+                index: self.index,
+                program_counter: self.program_counter,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+                stack: self.stack.clone(),
+                key_latch: self.key_latch,
+                flags: self.flags,
+                hires: self.hires,
+                selected_planes: self.selected_planes,
+                memory: Vec::new(),
+            });
+        }
+
         let opcode = self.mmu.read_u16(self.program_counter);
-        self.exec_opcode(opcode);
+        let result = self.exec_opcode(opcode);
+        // A failed step is not logged, as there is nothing to undo once it has been dealt with
+        if let (Some(entry), Ok(())) = (self.pending_undo.take(), &result) {
+            if self.undo_log.len() == self.undo_limit {
+                self.undo_log.pop_front();
+            }
+            self.undo_log.push_back(entry);
+        }
+        result
+    }
+
+    /// Move the program counter past the next instruction without running it, e.g. to carry on
+    /// after it failed.
+    pub fn skip_instruction(&mut self) {
+        self.program_counter = self
+            .program_counter
+            .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE));
+    }
+
+    /// Execute `opcode` as if it had been fetched from the current program counter. Only the fetch
+    /// is bypassed, so jumps, skips and calls are still relative to the program counter. Injected
+    /// opcodes are not steps, so they are not added to the undo log.
+    pub fn inject_opcode(&mut self, opcode: u16) -> Result<(), CpuError> {
+        self.exec_opcode(opcode)
+    }
+
+    /// Step until the program counter reaches `address`, or until `max_cycles` instructions have
+    /// run. At least one instruction is run, so this also works when already at `address`.
+    pub fn run_until(
+        &mut self,
+        address: uint<12>,
+        max_cycles: usize,
+    ) -> Result<RunResult, CpuError> {
+        for _ in 0..max_cycles {
+            self.step()?;
+            if self.program_counter == address {
+                return Ok(RunResult::ReachedAddress);
+            }
+        }
+        Ok(RunResult::CycleLimit)
+    }
+
+    /// Step until `stats().cycles` reaches `target_cycle`, for a host that drives the CPU from its
+    /// own clock. An instruction that costs several cycles, such as a draw, may overshoot the
+    /// target; the host can carry the difference into its next call. Does nothing if the target
+    /// has already been reached.
+    pub fn advance_to(&mut self, target_cycle: u64) -> Result<(), CpuError> {
+        while self.stats.cycles < target_cycle {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Keep enough history to undo up to `limit` steps. A limit of 0 (the default) disables the
+    /// undo log.
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.undo_limit = limit;
+        while self.undo_log.len() > limit {
+            self.undo_log.pop_front();
+        }
+    }
+
+    /// Revert the CPU state and memory changed by the most recent step. The display's contents are
+    /// not reverted, though its resolution and selected planes are. Return false if there was
+    /// nothing to undo.
+    pub fn undo_step(&mut self) -> bool {
+        let entry = match self.undo_log.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        for (address, data) in entry.memory.into_iter().rev() {
+            self.mmu.write_u8(address, data);
+        }
+        self.registers = entry.registers;
+        self.index = entry.index;
+        self.program_counter = entry.program_counter;
+        self.delay_timer = entry.delay_timer;
+        self.sound_timer = entry.sound_timer;
+        self.stack = entry.stack;
+        self.key_latch = entry.key_latch;
+        self.flags = entry.flags;
+        if self.hires != entry.hires {
+            self.set_hires(entry.hires);
+        }
+        if self.selected_planes != entry.selected_planes {
+            self.set_planes(entry.selected_planes);
+        }
+        true
+    }
+
+    /// Whether the program is spinning in a loop that only polls the delay timer, i.e.
+    ///
+    /// ```text
+    /// loop: LD VX, DT
+    ///       SE VX, 0
+    ///       JP loop
+    /// ```
+    ///
+    /// Nothing but VX and the program counter can change until the next 60hz tick, so it is safe
+    /// to skip running instructions until then.
+    pub fn is_idle(&self) -> bool {
+        if self.delay_timer == 0 {
+            return false;
+        }
+
+        let opcode_at = |offset: u16| {
+            Instruction::decode(
+                self.mmu
+                    .read_u16(self.program_counter.wrapping_add(uint::<12>::new(offset))),
+            )
+        };
+        match (opcode_at(0), opcode_at(2), opcode_at(4)) {
+            (
+                Instruction::LoadDelay(x),
+                Instruction::SkipEqByte(y, 0),
+                Instruction::Jump(target),
+            ) => x == y && target == self.program_counter,
+            _ => false,
+        }
+    }
+
+    /// The opcode at the program counter, i.e. the one `step` would run next.
+    pub fn peek_next_opcode(&self) -> u16 {
+        self.mmu.read_u16(self.program_counter)
+    }
+
+    /// The decoded instruction at the program counter.
+    pub fn peek_next_instruction(&self) -> Instruction {
+        Instruction::decode(self.peek_next_opcode())
+    }
+
+    /// Whether the program has stopped for good: the instruction at the program counter jumps to
+    /// itself, and CHIP-8 has no interrupts to break out of that.
+    pub fn is_halted(&self) -> bool {
+        Instruction::decode(self.mmu.read_u16(self.program_counter))
+            == Instruction::Jump(self.program_counter)
     }
 
+    /// Tick the timers and render. This is the only place the delay and sound timers count
+    /// down, by exactly one per call, so FX07 reads the same value between two ticks however many
+    /// instructions run. Call it 60 times per second of emulated time even when fewer than 60
+    /// instructions run per second, so that the timers keep real time at low frequencies.
     pub fn run_60hz_cycle(&mut self) {
-        if self.sound_timer > 0 {
+        // A beep set and cleared between two ticks still sounds for one frame
+        if self.sound_timer > 0 || self.sound_this_frame {
             self.audio.play();
-            self.sound_timer -= 1;
+            self.sound_timer = self.sound_timer.saturating_sub(1);
         } else {
             self.audio.pause();
         }
 
+        self.sound_this_frame = false;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -77,9 +805,37 @@ impl Cpu {
         self.window.render()
     }
 
-    fn exec_opcode(&mut self, opcode: u16) {
+    fn exec_opcode(&mut self, opcode: u16) -> Result<(), CpuError> {
+        if self.record_last_instruction {
+            LAST_INSTRUCTION.with(|last| {
+                last.set(Some(LastInstruction {
+                    program_counter: self.program_counter,
+                    opcode,
+                    index: self.index,
+                    registers: self.registers_array(),
+                }))
+            });
+        }
+        self.stats.instructions += 1;
+        self.stats.cycles += 1;
+
+        if let Some(callback) = &mut self.instruction_callback {
+            callback(self.program_counter, opcode);
+        }
+
+        // The hook is taken out while it runs so that it can borrow the CPU
+        if let Some(mut hook) = self.hooks.remove(&opcode) {
+            let program_counter = hook(self, opcode);
+            self.hooks.entry(opcode).or_insert(hook);
+            if let Some(program_counter) = program_counter {
+                self.program_counter = program_counter;
+                return Ok(());
+            }
+        }
+
         // Run the opcode, then update the program_counter
-        match Cpu::FUNC_MAP[(opcode >> 12) as usize](self, uint::<12>::new(opcode & 0xFFF)) {
+        let family = OpcodeFamily::from_opcode(opcode);
+        match Cpu::FUNC_MAP[family as usize](self, uint::<12>::new(opcode & 0xFFF))? {
             Some(program_counter) => self.program_counter = program_counter,
             None => {
                 self.program_counter = self
@@ -87,94 +843,154 @@ impl Cpu {
                     .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE))
             }
         }
+        Ok(())
     }
 
-    fn opcode_0(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_0(&mut self, data: uint<12>) -> OpcodeResult {
         match u16::from(data) {
             // Blank Screen
             0x0E0 => {
-                self.window.blank_screen();
-                None
+                if !self.ignore_clear {
+                    self.window.blank_screen();
+                }
+                Ok(None)
             }
             // Return from subroutine
-            0x0EE => Some(
-                self.stack
-                    .pop_back()
-                    .unwrap_or_else(|| panic!("Stack underflow!")),
-            ),
+            0x0EE => match self.stack.pop_back() {
+                Some(address) => Ok(Some(address)),
+                None => Err(CpuError::StackUnderflow {
+                    program_counter: self.program_counter,
+                }),
+            },
+            // Scroll the screen down N pixels (SCHIP)
+            0x0C0..=0x0CF if self.platform != Platform::Chip8 => {
+                let n = self.scroll_distance((u16::from(data) & 0xF) as u8);
+                self.window.scroll_down(n);
+                Ok(None)
+            }
+            // Leave or enter 128x64 high resolution mode, clearing the screen (SCHIP)
+            0x0FE | 0x0FF if self.platform != Platform::Chip8 => {
+                self.set_hires(u16::from(data) == 0x0FF);
+                Ok(None)
+            }
+            // Scroll the screen right 4 pixels (SCHIP)
+            0x0FB if self.platform != Platform::Chip8 => {
+                let n = self.scroll_distance(4);
+                self.window.scroll_right(n);
+                Ok(None)
+            }
+            // Scroll the screen left 4 pixels (SCHIP)
+            0x0FC if self.platform != Platform::Chip8 => {
+                let n = self.scroll_distance(4);
+                self.window.scroll_left(n);
+                Ok(None)
+            }
+            // Scroll the screen up N pixels (XO-CHIP)
+            0x0D0..=0x0DF if self.platform == Platform::XoChip => {
+                self.window.scroll_up((u16::from(data) & 0xF) as u8);
+                Ok(None)
+            }
+            // Zeroed memory rather than a real machine code routine. Skipped like any other
+            // unknown opcode if those are ignored, otherwise reported clearly
+            0x000 => match self.unknown_opcodes {
+                UnknownOpcodes::Ignore => self.unknown_opcode(0x0000),
+                UnknownOpcodes::Error => Err(CpuError::ExecutingEmptyMemory {
+                    program_counter: self.program_counter,
+                }),
+            },
             // Unhandled: Call machine code routine
-            _ => panic!("Unhandled machine code routine instruction"),
+            _ => self.unknown_opcode(u16::from(data)),
+        }
+    }
+
+    // SCHIP scrolls by high resolution pixels even in low resolution, so there it moves half as
+    // many of the doubled-up pixels. XO-CHIP scrolls by the pixels of the current resolution.
+    fn scroll_distance(&self, n: u8) -> u8 {
+        if self.platform == Platform::SuperChip && !self.hires {
+            n / 2
+        } else {
+            n
         }
     }
 
-    fn opcode_1(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_1(&mut self, data: uint<12>) -> OpcodeResult {
         // Jump to address
-        Some(data)
+        if self.halt_on_self_jump && data == self.program_counter {
+            return Err(CpuError::Halt {
+                program_counter: data,
+            });
+        }
+        Ok(Some(data))
     }
 
-    fn opcode_2(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_2(&mut self, data: uint<12>) -> OpcodeResult {
         // Call subroutine
+        if self.stack.len() == Self::STACK_SIZE {
+            return Err(CpuError::StackOverflow {
+                program_counter: self.program_counter,
+            });
+        }
         self.stack.push_back(
             self.program_counter
                 .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE)),
         );
-        Some(data)
+        Ok(Some(data))
     }
 
-    fn opcode_3(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_3(&mut self, data: uint<12>) -> OpcodeResult {
         // Skips the next instruction if VX equals NN.
         let (reg_index, value) = Self::split_xnn(data);
         if self.registers[reg_index as usize] == value {
-            Some(
+            Ok(Some(
                 self.program_counter
                     .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-            )
+            ))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn opcode_4(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_4(&mut self, data: uint<12>) -> OpcodeResult {
         // Skips the next instruction if VX doesn't equal NN.
         let (reg_index, value) = Self::split_xnn(data);
         if self.registers[reg_index as usize] != value {
-            Some(
+            Ok(Some(
                 self.program_counter
                     .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-            )
+            ))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn opcode_5(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_5(&mut self, data: uint<12>) -> OpcodeResult {
         // Skips the next instruction if VX equals VY
         let (x, y, _) = Self::split_xyn(data);
         if self.registers[x as usize] == self.registers[y as usize] {
-            Some(
+            Ok(Some(
                 self.program_counter
                     .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-            )
+            ))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn opcode_6(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_6(&mut self, data: uint<12>) -> OpcodeResult {
         // Sets VX to NN
         let (reg_index, value) = Self::split_xnn(data);
         self.registers[reg_index as usize] = value;
-        None
+        Ok(None)
     }
 
-    fn opcode_7(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_7(&mut self, data: uint<12>) -> OpcodeResult {
         // Adds NN to VX. (Carry flag is not changed)
         let (reg_index, value) = Self::split_xnn(data);
         self.registers[reg_index as usize] = self.registers[reg_index as usize].wrapping_add(value);
-        None
+        Ok(None)
     }
 
-    fn opcode_8(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_8(&mut self, data: uint<12>) -> OpcodeResult {
         let (x, y, opcode) = Self::split_xyn(data);
         let x = x as usize;
         let y = y as usize;
@@ -184,17 +1000,17 @@ impl Cpu {
             // Sets VX to VX or VY. (Bitwise OR operation)
             0x1 => {
                 self.registers[x] |= self.registers[y];
-                self.registers[Self::CARRY_REGISTER] = 0;
+                self.vf_reset_quirk();
             }
             // Sets VX to VX and VY. (Bitwise AND operation)
             0x2 => {
                 self.registers[x] &= self.registers[y];
-                self.registers[Self::CARRY_REGISTER] = 0;
+                self.vf_reset_quirk();
             }
             // Sets VX to VX xor VY. (Bitwise XOR operation)
             0x3 => {
                 self.registers[x] ^= self.registers[y];
-                self.registers[Self::CARRY_REGISTER] = 0;
+                self.vf_reset_quirk();
             }
             // Adds VY to VX. VF is set to 1 when there's a carry, and to 0 when there isn't.
             0x4 => {
@@ -202,7 +1018,8 @@ impl Cpu {
                 self.registers[x] = result;
                 self.registers[Self::CARRY_REGISTER] = overflow as u8;
             }
-            // VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there isn't.
+            // VY is subtracted from VX. VF is set to 0 when there's a borrow, and 1 when there
+            // isn't.
             0x5 => {
                 let (result, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
                 self.registers[x] = result;
@@ -210,8 +1027,12 @@ impl Cpu {
             }
             // Stores the least significant bit of VX in VF and then shifts VX to the right by 1.[b]
             0x6 => {
-                self.registers[Self::CARRY_REGISTER] = self.registers[x] & 0x1;
+                if self.quirks.shift_uses_vy {
+                    self.registers[x] = self.registers[y];
+                }
+                let flag = self.registers[x] & 0x1;
                 self.registers[x] >>= 1;
+                self.registers[Self::CARRY_REGISTER] = flag;
             }
             // Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there isn't.
             0x7 => {
@@ -221,65 +1042,96 @@ impl Cpu {
             }
             // Stores the most significant bit of VX in VF and then shifts VX to the left by 1.
             0xE => {
-                self.registers[Self::CARRY_REGISTER] = (self.registers[x] & 0x80) >> 7;
+                if self.quirks.shift_uses_vy {
+                    self.registers[x] = self.registers[y];
+                }
+                let flag = (self.registers[x] & 0x80) >> 7;
                 self.registers[x] <<= 1;
+                self.registers[Self::CARRY_REGISTER] = flag;
             }
             // Unhandled
-            _ => panic!("Unhandled register operation"),
+            _ => return self.unknown_opcode(0x8000 | u16::from(data)),
         }
-        None
+        Ok(None)
     }
 
-    fn opcode_9(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_9(&mut self, data: uint<12>) -> OpcodeResult {
         // Skips the next instruction if VX doesn't equal VY.
         let (x, y, _) = Self::split_xyn(data);
         if self.registers[x as usize] != self.registers[y as usize] {
-            Some(
+            Ok(Some(
                 self.program_counter
                     .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-            )
+            ))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    fn opcode_a(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_a(&mut self, data: uint<12>) -> OpcodeResult {
         // Sets I to the address NNN
         self.index = data;
-        None
+        Ok(None)
     }
 
-    fn opcode_b(&mut self, data: uint<12>) -> Option<uint<12>> {
-        // Jumps to the address NNN plus V0.
-        Some(uint::<12>::new(self.registers[0].into()).wrapping_add(data))
+    fn opcode_b(&mut self, data: uint<12>) -> OpcodeResult {
+        // Jumps to the address NNN plus V0, or with the quirk XNN plus VX.
+        let x = if self.quirks.jump_uses_vx {
+            Self::split_xnn(data).0 as usize
+        } else {
+            0
+        };
+        Ok(Some(
+            uint::<12>::new(self.registers[x].into()).wrapping_add(data),
+        ))
     }
 
-    fn opcode_c(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_c(&mut self, data: uint<12>) -> OpcodeResult {
         // Sets VX to the result of a bitwise and operation on a random number and NN.
         let (register_index, bitmask) = Self::split_xnn(data);
-        self.registers[register_index as usize] = fastrand::u8(..) & bitmask;
-        None
+        self.registers[register_index as usize] = self.rng.u8(..) & bitmask;
+        Ok(None)
     }
 
-    fn opcode_d(&mut self, data: uint<12>) -> Option<uint<12>> {
-        // Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N+1 pixels
+    fn opcode_d(&mut self, data: uint<12>) -> OpcodeResult {
+        // Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N
+        // pixels. On SCHIP and XO-CHIP, N = 0 draws a 16x16 sprite of 32 bytes instead, two per
+        // row. On XO-CHIP, each selected plane takes its own sprite from consecutive memory
         let (x, y, n) = Self::split_xyn(data);
 
-        let sprite = (0..n)
+        let (sprite_width, length) = match n {
+            0 if self.platform != Platform::Chip8 => (16, 32),
+            n => (8, n),
+        };
+        let length = length * self.selected_planes.count_ones() as u8;
+        let sprite = (0..length)
             .map(|i| {
-                self.mmu
-                    .read_u8(self.index.wrapping_add(uint::<12>::new(i.into())))
+                let past_end = usize::from(self.index) + i as usize >= Chip8Mmu::MEM_SIZE;
+                if past_end && self.sprite_reads == SpriteReads::Zero {
+                    0
+                } else {
+                    self.mmu
+                        .read_u8(self.index.wrapping_add(uint::<12>::new(i.into())))
+                }
             })
             .collect();
-        self.registers[Self::CARRY_REGISTER] = self.window.draw(
+        let (width, height) = self.screen_size();
+        let (x, y) = self.coordinate_mode.origin(
             self.registers[x as usize],
             self.registers[y as usize],
-            sprite,
-        ) as u8;
-        None
+            width,
+            height,
+        );
+        let collision = self.window.draw(x, y, sprite_width, sprite);
+        self.stats.cycles += self.draw_cycles as u64;
+        if collision {
+            self.stats.collisions += 1;
+        }
+        self.registers[Self::CARRY_REGISTER] = collision as u8;
+        Ok(None)
     }
 
-    fn opcode_e(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_e(&mut self, data: uint<12>) -> OpcodeResult {
         let (x, opcode) = Self::split_xnn(data);
 
         let is_key_pressed = self.window.is_key_pressed(self.registers[x as usize]);
@@ -288,31 +1140,31 @@ impl Cpu {
             // Skips the next instruction if the key stored in VX is pressed.
             0x9E => {
                 if is_key_pressed {
-                    Some(
+                    Ok(Some(
                         self.program_counter
                             .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-                    )
+                    ))
                 } else {
-                    None
+                    Ok(None)
                 }
             }
             // Skips the next instruction if the key stored in VX isn't pressed.
             0xA1 => {
                 if !is_key_pressed {
-                    Some(
+                    Ok(Some(
                         self.program_counter
                             .wrapping_add(uint::<12>::new(Self::OPCODE_SIZE * 2)),
-                    )
+                    ))
                 } else {
-                    None
+                    Ok(None)
                 }
             }
             // Unhandled
-            _ => panic!("Unhandled key check operation"),
+            _ => self.unknown_opcode(0xE000 | u16::from(data)),
         }
     }
 
-    fn opcode_f(&mut self, data: uint<12>) -> Option<uint<12>> {
+    fn opcode_f(&mut self, data: uint<12>) -> OpcodeResult {
         let (x, opcode) = Self::split_xnn(data);
         let x = x as usize;
 
@@ -323,21 +1175,24 @@ impl Cpu {
             0x0A => match self.window.get_pressed_key() {
                 Some(key) => {
                     self.key_latch = Some(key);
-                    return Some(self.program_counter);
+                    return Ok(Some(self.program_counter));
                 }
                 None => {
                     if let Some(latched_key) = self.key_latch {
                         self.registers[x] = latched_key;
                         self.key_latch = None // Reset the latch now that we are done
                     } else {
-                        return Some(self.program_counter);
+                        return Ok(Some(self.program_counter));
                     }
                 }
             },
             // Sets the delay timer to VX.
             0x15 => self.delay_timer = self.registers[x],
             // Sets the sound timer to VX.
-            0x18 => self.sound_timer = self.registers[x],
+            0x18 => {
+                self.sound_timer = self.registers[x];
+                self.sound_this_frame |= self.sound_timer > 0;
+            }
             // Adds VX to I. VF is not affected.
             0x1E => {
                 self.index = self
@@ -352,36 +1207,101 @@ impl Cpu {
             }
             // Stores the binary-coded decimal representation of VX
             0x33 => {
-                self.mmu.write_u8(self.index, self.registers[x] / 100);
-                self.mmu.write_u8(
+                self.write_memory(self.index, self.registers[x] / 100);
+                self.write_memory(
                     self.index.wrapping_add(uint::<12>::new(1)),
                     (self.registers[x] % 100) / 10,
                 );
-                self.mmu.write_u8(
+                self.write_memory(
                     self.index.wrapping_add(uint::<12>::new(2)),
                     self.registers[x] % 10,
                 );
             }
             // Stores V0 to VX (including VX) in memory starting at address I.
             0x55 => {
-                for i in 0..=x {
-                    self.mmu.write_u8(
+                let length = self.register_transfer_length(x)?;
+                for i in 0..length {
+                    self.write_memory(
                         self.index.wrapping_add(uint::<12>::new(i as u16)),
                         self.registers[i],
                     );
                 }
+                self.memory_increment_quirk(length);
             }
             // Fills V0 to VX (including VX) with values from memory starting at address I.
             0x65 => {
-                for i in 0..=x {
+                let length = self.register_transfer_length(x)?;
+                for i in 0..length {
                     self.registers[i] = self
                         .mmu
                         .read_u8(self.index.wrapping_add(uint::<12>::new(i as u16)));
                 }
+                self.memory_increment_quirk(length);
+            }
+            // Stores V0 to VX (including VX) in the HP48 flag registers, X <= 7 (SCHIP)
+            0x75 if self.platform != Platform::Chip8 && x < Cpu::FLAG_COUNT => {
+                self.flags[..=x].copy_from_slice(&self.registers[..=x])
             }
-            _ => panic!("Unhandled register operation"),
+            // Fills V0 to VX (including VX) from the HP48 flag registers, X <= 7 (SCHIP)
+            0x85 if self.platform != Platform::Chip8 && x < Cpu::FLAG_COUNT => {
+                self.registers[..=x].copy_from_slice(&self.flags[..=x])
+            }
+            // Selects the XO-CHIP bit planes to draw on, with N as a bitmask. Here N is in X's
+            // place
+            0x01 if self.platform == Platform::XoChip => self.set_planes(x as u8 & 0b11),
+            _ => return self.unknown_opcode(0xF000 | u16::from(data)),
+        }
+        Ok(None)
+    }
+
+    // Clear VF after a logic op, if the quirk is on. This comes after the op so that it wins
+    // when VX is VF.
+    fn vf_reset_quirk(&mut self) {
+        if self.quirks.vf_reset_on_logic {
+            self.registers[Self::CARRY_REGISTER] = 0;
+        }
+    }
+
+    // Move I past the registers FX55 or FX65 transferred, if the quirk is on.
+    fn memory_increment_quirk(&mut self, length: usize) {
+        if self.quirks.memory_increments_index {
+            self.index = self.index.wrapping_add(uint::<12>::new(length as u16));
+        }
+    }
+
+    fn unknown_opcode(&mut self, opcode: u16) -> OpcodeResult {
+        match self.unknown_opcodes {
+            UnknownOpcodes::Ignore => {
+                self.stats.unknown_opcodes += 1;
+                Ok(None)
+            }
+            UnknownOpcodes::Error => Err(CpuError::UnknownOpcode(opcode)),
+        }
+    }
+
+    /// How many of V0 to VX FX55 and FX65 should transfer, given the memory overflow policy.
+    fn register_transfer_length(&self, x: usize) -> Result<usize, CpuError> {
+        let length = x + 1;
+        let available = Chip8Mmu::MEM_SIZE - usize::from(self.index);
+        if length <= available {
+            return Ok(length);
+        }
+
+        match self.memory_overflow {
+            MemoryOverflow::Wrap => Ok(length),
+            MemoryOverflow::Clamp => Ok(available),
+            MemoryOverflow::Error => Err(CpuError::MemoryOverflow {
+                index: self.index,
+                length,
+            }),
         }
-        None
+    }
+
+    fn write_memory(&mut self, address: uint<12>, data: u8) {
+        if let Some(entry) = &mut self.pending_undo {
+            entry.memory.push((address, self.mmu.read_u8(address)));
+        }
+        self.mmu.write_u8(address, data);
     }
 
     fn split_xnn(data: uint<12>) -> (u8, u8) {
@@ -399,15 +1319,43 @@ impl Cpu {
     }
 }
 
+/// A compact view of the CPU state, e.g.
+///
+/// ```text
+/// PC=0x200 I=0x000 DT=00 ST=00 SP=0
+/// V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00
+/// V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00
+/// ```
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC={:#05X} I={:#05X} DT={:02X} ST={:02X} SP={}",
+            u16::from(self.program_counter),
+            u16::from(self.index),
+            self.delay_timer,
+            self.sound_timer,
+            self.stack.len()
+        )?;
+        for (i, register) in self.registers.iter().enumerate() {
+            let separator = if i % 8 == 0 { '\n' } else { ' ' };
+            write!(f, "{}V{:X}={:02X}", separator, i, register)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 #[allow(non_snake_case)]
 mod tests {
     use super::super::audio::MockAudio;
     use super::super::mmu::MockMmu;
-    use super::super::window::MockWindow;
+    use super::super::window::{HeadlessWindow, MockWindow, RecordingWindow, WindowCall};
     use super::*;
     use mockall::predicate::eq;
     use rstest::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     #[fixture]
     fn mmu() -> Box<MockMmu> {
@@ -426,55 +1374,1258 @@ mod tests {
 
     #[rstest]
     fn pc_has_default(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let cpu = Cpu::new(mmu, window, audio);
+        let cpu = Cpu::new(mmu, window, audio, Quirks::default());
         assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
     }
 
     #[rstest]
-    fn op_00E0_blanks_screen(
-        mut window: Box<MockWindow>,
+    fn reset_clears_state(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[3] = 0x12;
+        cpu.index = uint::<12>::new(0x300);
+        cpu.program_counter = uint::<12>::new(0x400);
+        cpu.delay_timer = 4;
+        cpu.sound_timer = 5;
+        cpu.stack.push_back(uint::<12>::new(0x202));
+        cpu.key_latch = Some(0x1);
+
+        cpu.reset();
+
+        assert_eq!(vec![0; 16], cpu.registers);
+        assert_eq!(uint::<12>::new(0), cpu.index);
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+        assert_eq!(0, cpu.delay_timer);
+        assert_eq!(0, cpu.sound_timer);
+        assert!(cpu.stack.is_empty());
+        assert_eq!(None, cpu.key_latch);
+    }
+
+    #[rstest]
+    fn randomize_startup_fills_registers(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_write_u8()
+            .times(Chip8Mmu::PROGRAM_START - Chip8Mmu::FONT_END)
+            .returning(|_, _| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_seed(42);
+
+        cpu.randomize_startup();
+
+        assert_ne!(vec![0; 16], cpu.registers);
+    }
+
+    #[rstest]
+    fn seed_makes_random_opcode_reproducible(
+        window: Box<MockWindow>,
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        window.expect_blank_screen().returning(|| ());
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        let mut values = Vec::new();
+        for _ in 0..2 {
+            cpu.set_seed(42);
+            cpu.exec_opcode(0xC0FF).unwrap();
+            cpu.exec_opcode(0xC1FF).unwrap();
+            values.push((cpu.registers[0], cpu.registers[1]));
+        }
 
-        cpu.exec_opcode(0x00E0);
+        assert_eq!(values[0], values[1]);
+    }
 
-        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    #[rstest]
+    fn op_CXNN_gives_known_stream_for_seed(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new_with_seed(mmu, window, audio, Quirks::default(), 42);
+        let mut values = Vec::new();
+        for _ in 0..4 {
+            cpu.exec_opcode(0xC0FF).unwrap();
+            values.push(cpu.registers[0]);
+        }
+        cpu.exec_opcode(0xC10F).unwrap();
+        values.push(cpu.registers[1]);
+
+        assert_eq!(vec![52, 210, 58, 113, 0], values);
+    }
+
+    #[test]
+    fn instances_have_independent_random_numbers() {
+        let new_cpu = |seed| Cpu::new_with_seed(mmu(), window(), audio(), Quirks::default(), seed);
+        let (mut first, mut second, mut alone) = (new_cpu(1), new_cpu(2), new_cpu(1));
+        let (mut first_values, mut second_values, mut alone_values) = (vec![], vec![], vec![]);
+
+        // Step the two instances in lockstep, then a third on its own
+        for _ in 0..8 {
+            first.exec_opcode(0xC0FF).unwrap();
+            first_values.push(first.registers[0]);
+            second.exec_opcode(0xC0FF).unwrap();
+            second_values.push(second.registers[0]);
+        }
+        for _ in 0..8 {
+            alone.exec_opcode(0xC0FF).unwrap();
+            alone_values.push(alone.registers[0]);
+        }
+
+        assert_ne!(first_values, second_values);
+        assert_eq!(first_values, alone_values);
+    }
+
+    #[rstest]
+    fn display_summarises_state(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x2A4);
+        cpu.index = uint::<12>::new(0x31);
+        cpu.registers[0xA] = 0x5C;
+        cpu.delay_timer = 0x10;
+        cpu.stack.push_back(uint::<12>::new(0x202));
+
+        let summary = cpu.to_string();
+
+        assert!(summary.contains("PC=0x2A4"));
+        assert!(summary.contains("I=0x031"));
+        assert!(summary.contains("DT=10 ST=00"));
+        assert!(summary.contains("SP=1"));
+        assert!(summary.contains("VA=5C"));
+        assert_eq!(3, summary.lines().count());
+    }
+
+    #[rstest]
+    fn hard_reset_restores_program_and_blanks_screen(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_reload_program().times(1).returning(|| ());
+        window.expect_blank_screen().times(1).returning(|| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x400);
+
+        cpu.hard_reset();
+
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn undo_step_restores_register_and_pc(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x6450u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_undo_limit(8);
+        cpu.registers[4] = 0x12;
+
+        cpu.step().unwrap();
+        assert_eq!(0x50, cpu.registers[4]); // Sanity check
+
+        assert!(cpu.undo_step());
+        assert_eq!(0x12, cpu.registers[4]);
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+        assert!(!cpu.undo_step());
+    }
+
+    #[rstest]
+    fn undo_step_restores_memory(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16().return_const(0xF055u16);
+        mmu.expect_read_u8()
+            .with(eq(uint::<12>::new(0x300)))
+            .return_const(0xAA);
+        mmu.expect_write_u8()
+            .with(eq(uint::<12>::new(0x300)), eq(0x10))
+            .times(1)
+            .returning(|_, _| ());
+        mmu.expect_write_u8()
+            .with(eq(uint::<12>::new(0x300)), eq(0xAA))
+            .times(1)
+            .returning(|_, _| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_undo_limit(8);
+        cpu.index = uint::<12>::new(0x300);
+        cpu.registers[0] = 0x10;
+
+        cpu.step().unwrap();
+
+        assert!(cpu.undo_step());
+    }
+
+    #[rstest]
+    fn undo_step_restores_flags_resolution_and_planes(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        for (address, opcode) in [(0x200, 0x00FFu16), (0x202, 0xF201), (0x204, 0xF175)] {
+            mmu.expect_read_u16()
+                .with(eq(uint::<12>::new(address)))
+                .return_const(opcode);
+        }
+        window
+            .expect_set_hires()
+            .with(eq(true))
+            .times(1)
+            .return_const(());
+        window
+            .expect_set_hires()
+            .with(eq(false))
+            .times(1)
+            .return_const(());
+        window
+            .expect_set_planes()
+            .with(eq(0b10))
+            .times(1)
+            .return_const(());
+        window
+            .expect_set_planes()
+            .with(eq(0b01))
+            .times(1)
+            .return_const(());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(Platform::XoChip);
+        cpu.set_undo_limit(8);
+        cpu.registers[0] = 0x12;
+        cpu.registers[1] = 0x34;
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!([0x12, 0x34, 0, 0, 0, 0, 0, 0], cpu.flags()); // Sanity check
+
+        assert!(cpu.undo_step());
+        assert_eq!([0; Cpu::FLAG_COUNT], cpu.flags());
+        assert_eq!(0b10, cpu.selected_planes);
+        assert!(cpu.undo_step());
+        assert_eq!(0b01, cpu.selected_planes);
+        assert!(cpu.is_hires());
+        assert!(cpu.undo_step());
+        assert!(!cpu.is_hires());
+    }
+
+    #[rstest]
+    fn undo_log_is_bounded(window: Box<MockWindow>, mut mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        mmu.expect_read_u16().return_const(0x7001u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_undo_limit(2);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        assert!(cpu.undo_step());
+        assert!(cpu.undo_step());
+        assert!(!cpu.undo_step());
+        assert_eq!(1, cpu.registers[0]);
+    }
+
+    #[rstest]
+    fn failed_step_is_not_logged(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x7001u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x202)))
+            .return_const(0xF165u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_undo_limit(8);
+        cpu.set_memory_overflow(MemoryOverflow::Error);
+        cpu.index = uint::<12>::new(0xFFF);
+
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+
+        // The undo goes back past the step that succeeded, not the one that failed
+        assert!(cpu.undo_step());
+        assert_eq!(0, cpu.registers[0]);
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+        assert!(!cpu.undo_step());
+    }
+
+    #[rstest]
+    fn inject_opcode_runs_without_fetch(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x300);
+        cpu.registers[2] = 0x7;
+
+        cpu.inject_opcode(0x3207).unwrap();
+
+        assert_eq!(uint::<12>::new(0x304), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn injected_opcode_is_not_undone(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x7001u16);
+        mmu.expect_write_u8()
+            .with(eq(uint::<12>::new(0x300)), eq(0x01))
+            .times(1)
+            .returning(|_, _| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_undo_limit(8);
+        cpu.index = uint::<12>::new(0x300);
+
+        cpu.step().unwrap();
+        cpu.inject_opcode(0xF055).unwrap();
+
+        // Only the step is undone, and the injected store is left in memory
+        assert!(cpu.undo_step());
+        assert_eq!(0, cpu.registers[0]);
+        assert!(!cpu.undo_step());
+    }
+
+    #[rstest]
+    fn opcode_hook_replaces_opcode(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_opcode_hook(
+            0x0FFF,
+            Box::new(|cpu, _| {
+                cpu.registers[0] += 1;
+                Some(cpu.program_counter.wrapping_add(uint::<12>::new(2)))
+            }),
+        );
+
+        cpu.exec_opcode(0x0FFF).unwrap();
+        cpu.exec_opcode(0x0FFF).unwrap();
+
+        assert_eq!(2, cpu.registers[0]);
+        assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn opcode_hook_can_fall_through(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_opcode_hook(
+            0x6005,
+            Box::new(|cpu, _| {
+                cpu.registers[1] = 0xAA;
+                None
+            }),
+        );
+
+        cpu.exec_opcode(0x6005).unwrap();
+
+        assert_eq!(0x05, cpu.registers[0]);
+        assert_eq!(0xAA, cpu.registers[1]);
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn instruction_callback_sees_each_instruction(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let callback_seen = Rc::clone(&seen);
+        cpu.set_instruction_callback(Box::new(move |program_counter, opcode| {
+            callback_seen
+                .borrow_mut()
+                .push((u16::from(program_counter), opcode))
+        }));
+
+        cpu.exec_opcode(0x6005).unwrap();
+        cpu.exec_opcode(0x1300).unwrap();
+        cpu.exec_opcode(0x7001).unwrap();
+        cpu.clear_instruction_callback();
+        cpu.exec_opcode(0x7001).unwrap();
+
+        assert_eq!(
+            vec![(0x200, 0x6005), (0x202, 0x1300), (0x300, 0x7001)],
+            *seen.borrow()
+        );
+    }
+
+    #[rstest]
+    fn advance_to_runs_until_target_cycle(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        // 0x200: ADD V0, 1
+        // 0x202: DRW V0, V0, 1
+        // 0x204: JP 0x200
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x7001u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x202)))
+            .return_const(0xD001u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x204)))
+            .return_const(0x1200u16);
+        mmu.expect_read_u8().return_const(0u8);
+        window.expect_draw().returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_draw_cycles(2);
+
+        // Each loop costs 1 + 3 + 1 cycles, so the second draw runs from cycle 6 to 9
+        cpu.advance_to(8).unwrap();
+        assert_eq!(5, cpu.stats().instructions);
+        assert_eq!(9, cpu.stats().cycles);
+
+        cpu.advance_to(9).unwrap();
+        assert_eq!(5, cpu.stats().instructions);
+
+        cpu.advance_to(11).unwrap();
+        assert_eq!(7, cpu.stats().instructions);
+        assert_eq!(11, cpu.stats().cycles);
+    }
+
+    #[rstest]
+    fn restores_saved_state(mut window: Box<MockWindow>, audio: Box<MockAudio>) {
+        window.expect_set_hires().return_const(());
+        window.expect_set_planes().return_const(());
+        let mut mmu = Box::new(Chip8Mmu::new());
+        // 200: HIGH; 202: PLANE 2; 204: LD V0, 5; 206: LD R, V0; 208: CALL 20C; 20A: JP 20A;
+        // 20C: LD I, 300
+        mmu.load_program_bytes(&[
+            0x00, 0xFF, 0xF2, 0x01, 0x60, 0x05, 0xF0, 0x75, 0x22, 0x0C, 0x12, 0x0A, 0xA3, 0x00,
+        ])
+        .unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(Platform::XoChip);
+        cpu.run_until(uint::<12>::new(0x20E), 10).unwrap();
+        let saved = MachineState::from_bytes(&cpu.save_state().to_bytes()).unwrap();
+
+        cpu.reset();
+        for opcode in [0x6107, 0x00FE, 0xF101, 0xF175] {
+            cpu.inject_opcode(opcode).unwrap();
+        }
+        cpu.load_state(&saved).unwrap();
+
+        assert_eq!(saved, cpu.save_state());
+        assert_eq!(0x05, cpu.registers[0]);
+        assert_eq!(0x00, cpu.registers[1]);
+        assert_eq!(uint::<12>::new(0x300), cpu.index);
+        assert_eq!(vec![uint::<12>::new(0x20A)], cpu.stack_contents());
+        assert_eq!([0x05, 0, 0, 0, 0, 0, 0, 0], cpu.flags());
+        assert!(cpu.is_hires());
+        assert_eq!(0b10, cpu.selected_planes);
+    }
+
+    #[cfg(feature = "serde")]
+    #[rstest]
+    fn restores_state_saved_as_json(window: Box<MockWindow>, audio: Box<MockAudio>) {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        // 200: LD V0, 5; 202: CALL 206; 204: JP 204; 206: ADD V0, 1; 208: RET
+        mmu.load_program_bytes(&[0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE])
+            .unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let saved = cpu.save_state();
+        let json = serde_json::to_string(&saved).unwrap();
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_ne!(saved, cpu.save_state());
+        cpu.load_state(&serde_json::from_str(&json).unwrap())
+            .unwrap();
+
+        assert_eq!(saved, cpu.save_state());
+        assert_eq!(uint::<12>::new(0x206), cpu.program_counter);
+        assert_eq!(vec![uint::<12>::new(0x204)], cpu.stack_contents());
+    }
+
+    #[rstest]
+    fn sets_and_reads_all_registers(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        let registers = [
+            0x10, 0x21, 0x32, 0x43, 0x54, 0x65, 0x76, 0x87, 0x98, 0xA9, 0xBA, 0xCB, 0xDC, 0xED,
+            0xFE, 0x0F,
+        ];
+
+        cpu.set_registers(&registers);
+
+        assert_eq!(registers, cpu.registers_array());
+        assert_eq!(&registers[..], cpu.registers());
+    }
+
+    #[rstest]
+    fn run_until_stops_at_address(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        // 0x200: ADD V0, 1
+        // 0x202: SE V0, 3
+        // 0x204: JP 0x200
+        // 0x206: JP 0x206
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x7001u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x202)))
+            .return_const(0x3003u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x204)))
+            .return_const(0x1200u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        let result = cpu.run_until(uint::<12>::new(0x206), 100).unwrap();
+
+        assert_eq!(RunResult::ReachedAddress, result);
+        assert_eq!(uint::<12>::new(0x206), cpu.program_counter);
+        assert_eq!(3, cpu.registers[0]);
+    }
+
+    #[rstest]
+    fn run_until_stops_at_cycle_limit(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16().return_const(0x7001u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        let result = cpu.run_until(uint::<12>::new(0x100), 5).unwrap();
+
+        assert_eq!(RunResult::CycleLimit, result);
+        assert_eq!(uint::<12>::new(0x20A), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn peeks_next_instruction_without_executing(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x200)))
+            .return_const(0x6002u16);
+        let cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        assert_eq!(0x6002, cpu.peek_next_opcode());
+        assert_eq!(Instruction::LoadByte(0, 0x02), cpu.peek_next_instruction());
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+        assert_eq!(0, cpu.registers[0]);
+    }
+
+    #[rstest]
+    fn is_halted_on_self_jump(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x300)))
+            .return_const(0x1300u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x302)))
+            .return_const(0x1300u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x300);
+
+        assert!(cpu.is_halted());
+
+        cpu.program_counter = uint::<12>::new(0x302);
+        assert!(!cpu.is_halted());
+    }
+
+    #[rstest]
+    fn sound_timer_only_ticks_at_60hz(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        mut audio: Box<MockAudio>,
+    ) {
+        // However many instructions run per frame (e.g. when unthrottled), a beep lasts the same
+        // number of 60hz ticks. The tone itself is fixed by the audio backend.
+        mmu.expect_read_u16().return_const(0x7001u16);
+        window.expect_render().returning(|| ());
+        audio.expect_play().times(2).returning(|| ());
+        audio.expect_pause().times(1).returning(|| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.sound_timer = 2;
+
+        for _ in 0..3 {
+            for _ in 0..1000 {
+                cpu.step().unwrap();
+            }
+            cpu.run_60hz_cycle();
+        }
+
+        assert_eq!(0, cpu.sound_timer);
+    }
+
+    #[rstest]
+    fn beep_cleared_before_tick_still_sounds(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        mut audio: Box<MockAudio>,
+    ) {
+        window.expect_render().returning(|| ());
+        let mut sequence = mockall::Sequence::new();
+        audio
+            .expect_play()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| ());
+        audio
+            .expect_pause()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        cpu.registers[1] = 1;
+        cpu.exec_opcode(0xF118).unwrap();
+        cpu.exec_opcode(0xF018).unwrap();
+        assert_eq!(0, cpu.sound_timer);
+        cpu.run_60hz_cycle();
+        cpu.run_60hz_cycle();
+    }
+
+    #[rstest]
+    fn delay_timer_reads_at_low_frequency(mut window: Box<MockWindow>, mut audio: Box<MockAudio>) {
+        // At 30hz two ticks pass between instructions, so each FX07 sees the timer two lower,
+        // and it never changes while instructions run
+        window.expect_render().returning(|| ());
+        audio.expect_pause().returning(|| ());
+        let mut cpu = Cpu::new(mmu(), window, audio, Quirks::default());
+        cpu.registers[0] = 9;
+        cpu.exec_opcode(0xF015).unwrap();
+
+        let mut reads = Vec::new();
+        for _ in 0..6 {
+            cpu.run_60hz_cycle();
+            cpu.run_60hz_cycle();
+            cpu.exec_opcode(0xF107).unwrap();
+            cpu.exec_opcode(0xF207).unwrap();
+            assert_eq!(cpu.registers[1], cpu.registers[2]);
+            reads.push(cpu.registers[1]);
+        }
+
+        assert_eq!(vec![7, 5, 3, 1, 0, 0], reads);
+    }
+
+    #[rstest]
+    fn is_idle_in_delay_timer_loop(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x300)))
+            .return_const(0xF307u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x302)))
+            .return_const(0x3300u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x304)))
+            .return_const(0x1300u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x300);
+        cpu.delay_timer = 10;
+
+        assert!(cpu.is_idle());
+
+        cpu.delay_timer = 0;
+        assert!(!cpu.is_idle());
+    }
+
+    #[rstest]
+    fn is_not_idle_when_loop_does_other_work(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x300)))
+            .return_const(0xF307u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x302)))
+            .return_const(0x7301u16);
+        mmu.expect_read_u16()
+            .with(eq(uint::<12>::new(0x304)))
+            .return_const(0x1300u16);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x300);
+        cpu.delay_timer = 10;
+
+        assert!(!cpu.is_idle());
+    }
+
+    #[rstest]
+    fn op_00E0_blanks_screen(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        window.expect_blank_screen().returning(|| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        cpu.exec_opcode(0x00E0).unwrap();
+
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn op_00E0_is_ignored_when_configured(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_ignore_clear(true);
+
+        cpu.exec_opcode(0x00E0).unwrap();
+
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[test]
+    fn dispatches_each_family_to_its_handler() {
+        type Check = fn(&Cpu) -> bool;
+        let dispatched: [(u16, Check); 16] = [
+            (0x00EE, |cpu| u16::from(cpu.program_counter) == 0x400),
+            (0x1ABC, |cpu| u16::from(cpu.program_counter) == 0xABC),
+            (0x2ABC, |cpu| cpu.stack.len() == 2),
+            (0x3000, |cpu| u16::from(cpu.program_counter) == 0x204),
+            (0x4001, |cpu| u16::from(cpu.program_counter) == 0x204),
+            (0x5020, |cpu| u16::from(cpu.program_counter) == 0x204),
+            (0x6A42, |cpu| cpu.registers[0xA] == 0x42),
+            (0x7A05, |cpu| cpu.registers[0xA] == 0x05),
+            (0x8A10, |cpu| cpu.registers[0xA] == 0x11),
+            (0x9010, |cpu| u16::from(cpu.program_counter) == 0x204),
+            (0xAABC, |cpu| u16::from(cpu.index) == 0xABC),
+            (0xB300, |cpu| u16::from(cpu.program_counter) == 0x300),
+            (0xC100, |cpu| cpu.registers[1] == 0),
+            (0xD011, |cpu| cpu.registers[0xF] == 1),
+            (0xE09E, |cpu| u16::from(cpu.program_counter) == 0x204),
+            (0xF115, |cpu| cpu.delay_timer == 0x11),
+        ];
+
+        for (nibble, (opcode, check)) in dispatched.iter().enumerate() {
+            assert_eq!(nibble, OpcodeFamily::from_opcode(*opcode) as usize);
+
+            let mut window = MockWindow::new();
+            window.expect_draw().returning(|_, _, _, _| true);
+            window.expect_is_key_pressed().returning(|_| true);
+            let mut mmu = MockMmu::new();
+            mmu.expect_read_u8().return_const(0);
+            let mut cpu = Cpu::new(
+                Box::new(mmu),
+                Box::new(window),
+                Box::new(MockAudio::new()),
+                Quirks::default(),
+            );
+            cpu.registers[1] = 0x11;
+            cpu.stack.push_back(uint::<12>::new(0x400));
+
+            cpu.exec_opcode(*opcode).unwrap();
+
+            assert!(
+                check(&cpu),
+                "{:04X} was not dispatched to its handler",
+                opcode
+            );
+        }
+    }
+
+    #[rstest]
+    fn op_00DN_scrolls_up_on_xochip(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        window
+            .expect_scroll_up()
+            .with(eq(3))
+            .times(1)
+            .returning(|_| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(Platform::XoChip);
+
+        cpu.exec_opcode(0x00D3).unwrap();
+
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    #[case::schip_hires(Platform::SuperChip, 0x00FF, 6, 4)]
+    #[case::schip_lores(Platform::SuperChip, 0x00FE, 3, 2)]
+    #[case::xochip_lores(Platform::XoChip, 0x00FE, 6, 4)]
+    fn schip_scroll_opcodes_scroll_window(
+        #[case] platform: Platform,
+        #[case] resolution: u16,
+        #[case] down: u8,
+        #[case] sideways: u8,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let window = RecordingWindow::new();
+        let calls = window.calls();
+        let mut cpu = Cpu::new(mmu, Box::new(window), audio, Quirks::default());
+        cpu.set_platform(platform);
+        cpu.exec_opcode(resolution).unwrap();
+        calls.borrow_mut().clear();
+
+        for opcode in [0x00C6, 0x00FB, 0x00FC] {
+            cpu.exec_opcode(opcode).unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                WindowCall::ScrollDown(down),
+                WindowCall::ScrollRight(sideways),
+                WindowCall::ScrollLeft(sideways)
+            ],
+            *calls.borrow()
+        );
+    }
+
+    #[rstest]
+    fn schip_scroll_opcodes_are_not_decoded_on_chip8(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Error);
+
+        for opcode in [0x00C5, 0x00FB, 0x00FC] {
+            assert!(matches!(
+                cpu.exec_opcode(opcode),
+                Err(CpuError::UnknownOpcode { .. })
+            ));
+        }
+    }
+
+    #[rstest]
+    fn op_00DN_is_not_decoded_on_chip8(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        assert_eq!(
+            Err(CpuError::UnknownOpcode(0x00D3)),
+            cpu.exec_opcode(0x00D3)
+        );
+    }
+
+    #[rstest]
+    #[case(Platform::SuperChip)]
+    #[case(Platform::XoChip)]
+    fn op_00FF_and_00FE_switch_resolution(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] platform: Platform,
+    ) {
+        let mut sequence = mockall::Sequence::new();
+        window
+            .expect_set_hires()
+            .with(eq(true))
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| ());
+        window
+            .expect_set_hires()
+            .with(eq(false))
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(platform);
+
+        cpu.exec_opcode(0x00FF).unwrap();
+        assert!(cpu.is_hires());
+        cpu.exec_opcode(0x00FE).unwrap();
+        assert!(!cpu.is_hires());
+    }
+
+    #[rstest]
+    fn op_00FF_is_not_decoded_on_chip8(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Error);
+
+        assert!(matches!(
+            cpu.exec_opcode(0x00FF),
+            Err(CpuError::UnknownOpcode { .. })
+        ));
+        assert!(!cpu.is_hires());
+    }
+
+    #[rstest]
+    #[case(Platform::SuperChip)]
+    #[case(Platform::XoChip)]
+    fn op_DXY0_draws_16x16_sprite(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] platform: Platform,
+    ) {
+        mmu.expect_read_u8()
+            .returning(|address| u16::from(address) as u8);
+        let sprite: Vec<u8> = (0x10..0x30).collect();
+        window
+            .expect_draw()
+            .with(eq(3), eq(4), eq(16), eq(sprite))
+            .times(1)
+            .returning(|_, _, _, _| true);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(platform);
+        cpu.index = uint::<12>::new(0x010);
+        cpu.registers[1] = 3;
+        cpu.registers[2] = 4;
+
+        cpu.exec_opcode(0xD120).unwrap();
+
+        assert_eq!(1, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_FN01_selects_planes_for_draws(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u8()
+            .returning(|address| u16::from(address) as u8);
+        window
+            .expect_set_planes()
+            .with(eq(0b11))
+            .times(1)
+            .return_const(());
+        window
+            .expect_draw()
+            .with(eq(0), eq(0), eq(8), eq(vec![0x10, 0x11, 0x12, 0x13]))
+            .times(1)
+            .returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(Platform::XoChip);
+        cpu.index = uint::<12>::new(0x010);
+
+        cpu.exec_opcode(0xF301).unwrap();
+        cpu.exec_opcode(0xD002).unwrap();
+    }
+
+    #[rstest]
+    #[case(Platform::Chip8)]
+    #[case(Platform::SuperChip)]
+    fn op_FN01_is_only_decoded_on_xochip(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] platform: Platform,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(platform);
+        cpu.set_unknown_opcodes(UnknownOpcodes::Error);
+
+        assert!(matches!(
+            cpu.exec_opcode(0xF201),
+            Err(CpuError::UnknownOpcode { .. })
+        ));
+    }
+
+    #[rstest]
+    fn op_FX75_and_FX85_save_and_restore_flags(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(Platform::SuperChip);
+        cpu.set_flags([9; 8]);
+        cpu.set_registers(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+
+        cpu.exec_opcode(0xF275).unwrap();
+        assert_eq!([1, 2, 3, 9, 9, 9, 9, 9], cpu.flags());
+
+        cpu.set_registers(&[0; 16]);
+        cpu.exec_opcode(0xF185).unwrap();
+        assert_eq!(&[1, 2, 0, 0][..], &cpu.registers()[..4]);
+    }
+
+    #[rstest]
+    #[case(Platform::Chip8, 0xF075)]
+    #[case(Platform::SuperChip, 0xF875)]
+    #[case(Platform::SuperChip, 0xF885)]
+    fn op_FX75_and_FX85_need_schip_and_x_below_8(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] platform: Platform,
+        #[case] opcode: u16,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_platform(platform);
+        cpu.set_unknown_opcodes(UnknownOpcodes::Error);
+
+        assert_eq!(
+            Err(CpuError::UnknownOpcode(opcode)),
+            cpu.exec_opcode(opcode)
+        );
+    }
+
+    #[rstest]
+    fn op_DXY0_draws_nothing_on_chip8(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        window
+            .expect_draw()
+            .with(eq(0), eq(0), eq(8), eq(vec![]))
+            .times(1)
+            .returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        cpu.exec_opcode(0xD000).unwrap();
+    }
+
+    #[rstest]
+    fn op_DXY0_collides_on_hires_screen(mut mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        mmu.expect_read_u8().returning(|_| 0xFF);
+        let mut cpu = Cpu::new(
+            mmu,
+            Box::new(HeadlessWindow::new()),
+            audio,
+            Quirks::default(),
+        );
+        cpu.set_platform(Platform::SuperChip);
+        cpu.exec_opcode(0x00FF).unwrap();
+        cpu.registers[0] = 100;
+        cpu.registers[1] = 40;
+
+        cpu.exec_opcode(0xD010).unwrap();
+        assert_eq!(256, lit_pixels(&cpu));
+        assert_eq!(0, cpu.registers[0xF]);
+
+        cpu.exec_opcode(0xD010).unwrap();
+        assert_eq!(0, lit_pixels(&cpu));
+        assert_eq!(1, cpu.registers[0xF]);
+    }
+
+    fn lit_pixels(cpu: &Cpu) -> usize {
+        let pixels = cpu.window().frame().pixels();
+        pixels.iter().filter(|pixel| **pixel).count()
+    }
+
+    #[rstest]
+    fn op_DXYN_wraps_origin_at_hires_width(mut mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        mmu.expect_read_u8().returning(|_| 0x80);
+        let mut cpu = Cpu::new(
+            mmu,
+            Box::new(HeadlessWindow::new()),
+            audio,
+            Quirks::default(),
+        );
+        cpu.set_platform(Platform::SuperChip);
+        cpu.set_coordinate_mode(CoordinateMode::Modulo);
+        cpu.exec_opcode(0x00FF).unwrap();
+        cpu.registers[0] = 130;
+        cpu.registers[1] = 40;
+
+        cpu.exec_opcode(0xD011).unwrap();
+
+        let frame = cpu.window().frame();
+        assert_eq!(FrameBuffer::HIRES_WIDTH, frame.width());
+        assert!(frame.pixels()[2 + 40 * FrameBuffer::HIRES_WIDTH]);
+    }
+
+    #[rstest]
+    fn hard_reset_returns_to_low_resolution(mut mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        mmu.expect_reload_program().returning(|| ());
+        let mut cpu = Cpu::new(
+            mmu,
+            Box::new(HeadlessWindow::new()),
+            audio,
+            Quirks::default(),
+        );
+        cpu.set_platform(Platform::SuperChip);
+        cpu.exec_opcode(0x00FF).unwrap();
+
+        cpu.hard_reset();
+
+        assert!(!cpu.is_hires());
+        assert_eq!(FrameBuffer::WIDTH, cpu.window().frame().width());
+    }
+
+    #[rstest]
+    fn op_00E0_returns_from_subroutine(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.stack.push_back(uint::<12>::new(0x400));
+
+        cpu.exec_opcode(0x00EE).unwrap();
+
+        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn op_1NNN_jumps_to_address(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        cpu.exec_opcode(0x1400).unwrap();
+
+        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn op_2NNN_calls_subroutine(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        cpu.exec_opcode(0x2400).unwrap();
+
+        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
+        assert_eq!(uint::<12>::new(0x202), cpu.stack.pop_back().unwrap());
     }
 
     #[rstest]
-    fn op_00E0_returns_from_subroutine(
+    fn op_00EE_with_empty_stack_is_an_error(
         window: Box<MockWindow>,
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
-        cpu.stack.push_back(uint::<12>::new(0x400));
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.program_counter = uint::<12>::new(0x2A4);
 
-        cpu.exec_opcode(0x00EE);
+        let result = cpu.exec_opcode(0x00EE);
 
-        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
+        assert_eq!(
+            Err(CpuError::StackUnderflow {
+                program_counter: uint::<12>::new(0x2A4)
+            }),
+            result
+        );
+        assert_eq!(uint::<12>::new(0x2A4), cpu.program_counter);
+        assert_eq!(0, cpu.stack_depth());
     }
 
     #[rstest]
-    fn op_1NNN_jumps_to_address(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+    fn call_fills_stack(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
-        cpu.exec_opcode(0x1400);
+        for depth in 0..Cpu::STACK_SIZE {
+            cpu.program_counter = uint::<12>::new(0x200 + depth as u16 * 2);
+            cpu.exec_opcode(0x2800).unwrap();
+        }
 
-        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
+        assert_eq!(Cpu::STACK_SIZE, cpu.stack_depth());
+        let contents = cpu.stack_contents();
+        assert_eq!(uint::<12>::new(0x202), contents[0]);
+        assert_eq!(uint::<12>::new(0x220), contents[Cpu::STACK_SIZE - 1]);
+        assert_eq!(uint::<12>::new(0x800), cpu.program_counter);
     }
 
     #[rstest]
-    fn op_2NNN_calls_subroutine(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+    fn call_with_full_stack_overflows(window: Box<MockWindow>, audio: Box<MockAudio>) {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        // 200: CALL 200, recursing forever
+        mmu.load_program_bytes(&[0x22, 0x00]).unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        for _ in 0..Cpu::STACK_SIZE {
+            cpu.step().unwrap();
+        }
+        let result = cpu.step();
 
-        cpu.exec_opcode(0x2400);
+        assert_eq!(
+            Err(CpuError::StackOverflow {
+                program_counter: uint::<12>::new(0x200)
+            }),
+            result
+        );
+        assert_eq!(Cpu::STACK_SIZE, cpu.stack_depth());
+    }
 
-        assert_eq!(uint::<12>::new(0x400), cpu.program_counter);
-        assert_eq!(uint::<12>::new(0x202), cpu.stack.pop_back().unwrap());
+    #[rstest]
+    fn self_jump_halts_when_enabled(window: Box<MockWindow>, audio: Box<MockAudio>) {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.load_program_bytes(&[0x60, 0x05, 0x12, 0x02]).unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+
+        cpu.set_halt_on_self_jump(true);
+
+        assert_eq!(
+            Err(CpuError::Halt {
+                program_counter: uint::<12>::new(0x202)
+            }),
+            cpu.step()
+        );
+        assert_eq!(0x05, cpu.registers[0]);
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn executing_zeroed_memory_fails(window: Box<MockWindow>, audio: Box<MockAudio>) {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.load_program_bytes(&[0x60, 0x05]).unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.step().unwrap();
+
+        assert_eq!(
+            Err(CpuError::ExecutingEmptyMemory {
+                program_counter: uint::<12>::new(0x202)
+            }),
+            cpu.step()
+        );
+    }
+
+    #[rstest]
+    fn executing_zeroed_memory_is_skipped_when_ignoring_unknown_opcodes(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Ignore);
+
+        cpu.exec_opcode(0x0000).unwrap();
+
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+        assert_eq!(1, cpu.stats().unknown_opcodes);
     }
 
     #[rstest]
@@ -483,10 +2634,10 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x10;
 
-        cpu.exec_opcode(0x3410);
+        cpu.exec_opcode(0x3410).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
@@ -497,10 +2648,10 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x11;
 
-        cpu.exec_opcode(0x3410);
+        cpu.exec_opcode(0x3410).unwrap();
 
         assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
     }
@@ -511,10 +2662,10 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x11;
 
-        cpu.exec_opcode(0x4410);
+        cpu.exec_opcode(0x4410).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
@@ -525,10 +2676,10 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x10;
 
-        cpu.exec_opcode(0x4410);
+        cpu.exec_opcode(0x4410).unwrap();
 
         assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
     }
@@ -539,11 +2690,11 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x10;
         cpu.registers[5] = 0x10;
 
-        cpu.exec_opcode(0x5450);
+        cpu.exec_opcode(0x5450).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
@@ -554,30 +2705,30 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x10;
         cpu.registers[5] = 0x11;
 
-        cpu.exec_opcode(0x5450);
+        cpu.exec_opcode(0x5450).unwrap();
 
         assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
     }
 
     #[rstest]
     fn op_6XNN_sets_register(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
-        cpu.exec_opcode(0x6450);
+        cpu.exec_opcode(0x6450).unwrap();
 
         assert_eq!(0x50, cpu.registers[4]);
     }
 
     #[rstest]
     fn op_7XNN_adds_to_register(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x02;
 
-        cpu.exec_opcode(0x74FF);
+        cpu.exec_opcode(0x74FF).unwrap();
 
         assert_eq!(0x01, cpu.registers[4]);
         assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -585,22 +2736,22 @@ mod tests {
 
     #[rstest]
     fn op_8XY0_sets_register(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x02;
 
-        cpu.exec_opcode(0x8140);
+        cpu.exec_opcode(0x8140).unwrap();
 
         assert_eq!(0x02, cpu.registers[1]);
     }
 
     #[rstest]
     fn op_8XY1_does_or(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0b1011;
         cpu.registers[4] = 0b1101;
         cpu.registers[Cpu::CARRY_REGISTER] = 1;
 
-        cpu.exec_opcode(0x8141);
+        cpu.exec_opcode(0x8141).unwrap();
 
         assert_eq!(0b1111, cpu.registers[1]);
         assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -608,12 +2759,12 @@ mod tests {
 
     #[rstest]
     fn op_8XY2_does_and(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0b1011;
         cpu.registers[4] = 0b1101;
         cpu.registers[Cpu::CARRY_REGISTER] = 1;
 
-        cpu.exec_opcode(0x8142);
+        cpu.exec_opcode(0x8142).unwrap();
 
         assert_eq!(0b1001, cpu.registers[1]);
         assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -621,12 +2772,12 @@ mod tests {
 
     #[rstest]
     fn op_8XY3_does_xor(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0b1011;
         cpu.registers[4] = 0b1101;
         cpu.registers[Cpu::CARRY_REGISTER] = 1;
 
-        cpu.exec_opcode(0x8143);
+        cpu.exec_opcode(0x8143).unwrap();
 
         assert_eq!(0b0110, cpu.registers[1]);
         assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -634,12 +2785,12 @@ mod tests {
 
     #[rstest]
     fn op_8XY4_does_add(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[Cpu::CARRY_REGISTER] = 0x01;
         cpu.registers[1] = 0x04;
         cpu.registers[4] = 0x03;
 
-        cpu.exec_opcode(0x8144);
+        cpu.exec_opcode(0x8144).unwrap();
 
         assert_eq!(0x07, cpu.registers[1]);
         assert_eq!(0x00, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -651,11 +2802,11 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0xFF;
         cpu.registers[4] = 0x03;
 
-        cpu.exec_opcode(0x8144);
+        cpu.exec_opcode(0x8144).unwrap();
 
         assert_eq!(0x02, cpu.registers[1]);
         assert_eq!(0x01, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -663,11 +2814,11 @@ mod tests {
 
     #[rstest]
     fn op_8XY5_does_sub(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0x05;
         cpu.registers[4] = 0x03;
 
-        cpu.exec_opcode(0x8145);
+        cpu.exec_opcode(0x8145).unwrap();
 
         assert_eq!(0x02, cpu.registers[1]);
         assert_eq!(0x01, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -679,12 +2830,12 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[Cpu::CARRY_REGISTER] = 0x01;
         cpu.registers[1] = 0x01;
         cpu.registers[4] = 0x02;
 
-        cpu.exec_opcode(0x8145);
+        cpu.exec_opcode(0x8145).unwrap();
 
         assert_eq!(0xFF, cpu.registers[1]);
         assert_eq!(0x00, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -692,22 +2843,68 @@ mod tests {
 
     #[rstest]
     fn op_8XY6_does_right_shift(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0b0101;
 
-        cpu.exec_opcode(0x8146);
+        cpu.exec_opcode(0x8146).unwrap();
 
         assert_eq!(0b0010, cpu.registers[1]);
         assert_eq!(0x01, cpu.registers[Cpu::CARRY_REGISTER]);
     }
 
+    #[rstest]
+    fn op_8XY6_follows_shift_quirk_changed_at_runtime(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[1] = 0b1000;
+        cpu.registers[4] = 0b0110;
+
+        cpu.exec_opcode(0x8146).unwrap();
+        assert_eq!(0b0100, cpu.registers[1]);
+
+        cpu.set_quirks(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        });
+        cpu.exec_opcode(0x8146).unwrap();
+        assert_eq!(0b0011, cpu.registers[1]);
+        assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
+    }
+
+    #[rstest]
+    fn op_8XYE_follows_shift_quirk_changed_at_runtime(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_quirks(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        });
+        cpu.registers[1] = 0b0001;
+        cpu.registers[4] = 0b1000_0011;
+
+        cpu.exec_opcode(0x814E).unwrap();
+        assert_eq!(0b0110, cpu.registers[1]);
+        assert_eq!(1, cpu.registers[Cpu::CARRY_REGISTER]);
+
+        cpu.set_quirks(Quirks::default());
+        cpu.exec_opcode(0x814E).unwrap();
+        assert_eq!(0b1100, cpu.registers[1]);
+        assert_eq!(0, cpu.registers[Cpu::CARRY_REGISTER]);
+    }
+
     #[rstest]
     fn op_8XY7_does_reverse_sub(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0x03;
         cpu.registers[4] = 0x05;
 
-        cpu.exec_opcode(0x8147);
+        cpu.exec_opcode(0x8147).unwrap();
 
         assert_eq!(0x02, cpu.registers[1]);
         assert_eq!(0x01, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -719,12 +2916,12 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[Cpu::CARRY_REGISTER] = 0x01;
         cpu.registers[1] = 0x02;
         cpu.registers[4] = 0x01;
 
-        cpu.exec_opcode(0x8147);
+        cpu.exec_opcode(0x8147).unwrap();
 
         assert_eq!(0xFF, cpu.registers[1]);
         assert_eq!(0x00, cpu.registers[Cpu::CARRY_REGISTER]);
@@ -732,49 +2929,315 @@ mod tests {
 
     #[rstest]
     fn op_8XYE_does_left_shift(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[1] = 0b1000_0010;
 
-        cpu.exec_opcode(0x814E);
+        cpu.exec_opcode(0x814E).unwrap();
 
         assert_eq!(0b0100, cpu.registers[1]);
         assert_eq!(0x01, cpu.registers[Cpu::CARRY_REGISTER]);
     }
 
+    #[rstest]
+    fn op_8FY4_leaves_carry_in_vf(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[0xF] = 0xFF;
+        cpu.registers[4] = 0x03;
+
+        cpu.exec_opcode(0x8F44).unwrap();
+
+        assert_eq!(0x01, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_8FY5_leaves_borrow_in_vf(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[0xF] = 0x01;
+        cpu.registers[4] = 0x02;
+
+        cpu.exec_opcode(0x8F45).unwrap();
+
+        assert_eq!(0x00, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_8FY6_leaves_shifted_bit_in_vf(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[0xF] = 0b0000_0010;
+
+        cpu.exec_opcode(0x8F46).unwrap();
+
+        assert_eq!(0x00, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_8FY7_leaves_borrow_in_vf(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[0xF] = 0x02;
+        cpu.registers[4] = 0x05;
+
+        cpu.exec_opcode(0x8F47).unwrap();
+
+        assert_eq!(0x01, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_8FYE_leaves_shifted_bit_in_vf(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.registers[0xF] = 0b1000_0000;
+
+        cpu.exec_opcode(0x8F4E).unwrap();
+
+        assert_eq!(0x01, cpu.registers[0xF]);
+    }
+
+    #[rstest]
+    fn op_8XY8_fails_by_default(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+
+        assert_eq!(
+            Err(CpuError::UnknownOpcode(0x8148)),
+            cpu.exec_opcode(0x8148)
+        );
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn skip_instruction_moves_past_failing_opcode(window: Box<MockWindow>, audio: Box<MockAudio>) {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.load_program_bytes(&[0x81, 0x48, 0x60, 0x05]).unwrap();
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        assert!(cpu.step().is_err());
+
+        cpu.skip_instruction();
+        cpu.step().unwrap();
+
+        assert_eq!(0x05, cpu.registers[0]);
+    }
+
+    #[rstest]
+    fn records_instruction_that_panicked(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_record_last_instruction(true);
+        cpu.exec_opcode(0x6105).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cpu.exec_opcode(0x8148).unwrap();
+        }));
+
+        assert!(result.is_err());
+        let last = last_instruction().unwrap();
+        assert_eq!(uint::<12>::new(0x202), last.program_counter);
+        assert_eq!(0x8148, last.opcode);
+        assert_eq!(
+            "PC=0x202 opcode=8148 I=0x000 V0-VF=00 05 00 00 00 00 00 00 00 00 00 00 00 00 00 00",
+            last.to_string()
+        );
+    }
+
+    // VF after each ALU op with V1=0xF0, V2=0x1F and VF=0x81 beforehand, with and without the VF
+    // reset quirk. X and Y of F alias VF.
+    #[rstest]
+    #[case(0x1, 0x1, 0x2, 0x00, 0x81)]
+    #[case(0x1, 0xF, 0x2, 0x00, 0x9F)]
+    #[case(0x1, 0x1, 0xF, 0x00, 0x81)]
+    #[case(0x1, 0xF, 0xF, 0x00, 0x81)]
+    #[case(0x2, 0x1, 0x2, 0x00, 0x81)]
+    #[case(0x2, 0xF, 0x2, 0x00, 0x01)]
+    #[case(0x2, 0x1, 0xF, 0x00, 0x81)]
+    #[case(0x2, 0xF, 0xF, 0x00, 0x81)]
+    #[case(0x3, 0x1, 0x2, 0x00, 0x81)]
+    #[case(0x3, 0xF, 0x2, 0x00, 0x9E)]
+    #[case(0x3, 0x1, 0xF, 0x00, 0x81)]
+    #[case(0x3, 0xF, 0xF, 0x00, 0x00)]
+    #[case(0x4, 0x1, 0x2, 0x01, 0x01)]
+    #[case(0x4, 0xF, 0x2, 0x00, 0x00)]
+    #[case(0x4, 0x1, 0xF, 0x01, 0x01)]
+    #[case(0x4, 0xF, 0xF, 0x01, 0x01)]
+    #[case(0x5, 0x1, 0x2, 0x01, 0x01)]
+    #[case(0x5, 0xF, 0x2, 0x01, 0x01)]
+    #[case(0x5, 0x2, 0xF, 0x00, 0x00)]
+    #[case(0x5, 0xF, 0xF, 0x01, 0x01)]
+    #[case(0x6, 0x1, 0x2, 0x00, 0x00)]
+    #[case(0x6, 0xF, 0x2, 0x01, 0x01)]
+    #[case(0x6, 0x1, 0xF, 0x00, 0x00)]
+    #[case(0x6, 0xF, 0xF, 0x01, 0x01)]
+    #[case(0x7, 0x1, 0x2, 0x00, 0x00)]
+    #[case(0x7, 0xF, 0x2, 0x00, 0x00)]
+    #[case(0x7, 0x1, 0xF, 0x00, 0x00)]
+    #[case(0x7, 0xF, 0xF, 0x01, 0x01)]
+    #[case(0xE, 0x1, 0x2, 0x01, 0x01)]
+    #[case(0xE, 0xF, 0x2, 0x01, 0x01)]
+    #[case(0xE, 0x2, 0xF, 0x00, 0x00)]
+    #[case(0xE, 0xF, 0xF, 0x01, 0x01)]
+    fn alu_ops_leave_authoritative_vf(
+        #[case] op: u16,
+        #[case] x: u16,
+        #[case] y: u16,
+        #[case] with_reset: u8,
+        #[case] without_reset: u8,
+    ) {
+        for (vf_reset, expected) in [(true, with_reset), (false, without_reset)] {
+            let quirks = Quirks {
+                vf_reset_on_logic: vf_reset,
+                ..Quirks::default()
+            };
+            let mut cpu = Cpu::new(mmu(), window(), audio(), quirks);
+            cpu.registers[0x1] = 0xF0;
+            cpu.registers[0x2] = 0x1F;
+            cpu.registers[0xF] = 0x81;
+
+            cpu.exec_opcode(0x8000 | x << 8 | y << 4 | op).unwrap();
+
+            assert_eq!(
+                expected, cpu.registers[0xF],
+                "8{:X}{:X}{:X} with vf_reset={}",
+                x, y, op, vf_reset
+            );
+        }
+    }
+
+    #[rstest]
+    fn op_8XY8_can_be_ignored(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Ignore);
+        cpu.registers[1] = 0x12;
+
+        cpu.exec_opcode(0x8148).unwrap();
+
+        assert_eq!(0x12, cpu.registers[1]);
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn stats_count_instructions_collisions_and_skipped_opcodes(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u8().returning(|_| 0x80);
+        window.expect_draw().returning(|_, _, _, _| true);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Ignore);
+
+        cpu.exec_opcode(0x6001).unwrap();
+        cpu.exec_opcode(0xD001).unwrap();
+        cpu.exec_opcode(0x8148).unwrap();
+
+        assert_eq!(
+            Stats {
+                instructions: 3,
+                cycles: 3,
+                collisions: 1,
+                unknown_opcodes: 1,
+            },
+            cpu.stats()
+        );
+    }
+
+    #[rstest]
+    fn draws_consume_extra_cycles(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_read_u8().returning(|_| 0x80);
+        window.expect_draw().returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_draw_cycles(3);
+
+        cpu.exec_opcode(0x6001).unwrap();
+        cpu.exec_opcode(0xD001).unwrap();
+        cpu.exec_opcode(0xD001).unwrap();
+
+        assert_eq!(3, cpu.stats().instructions);
+        assert_eq!(9, cpu.stats().cycles);
+    }
+
+    #[rstest]
+    fn op_8XY8_can_be_an_error(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_unknown_opcodes(UnknownOpcodes::Error);
+
+        let result = cpu.exec_opcode(0x8148);
+
+        assert_eq!(Err(CpuError::UnknownOpcode(0x8148)), result);
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
+    }
+
     #[rstest]
     fn op_9XY0_skips_instruction_if_ne(
         window: Box<MockWindow>,
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0x10;
         cpu.registers[5] = 0x11;
 
-        cpu.exec_opcode(0x9450);
+        cpu.exec_opcode(0x9450).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
 
     #[rstest]
     fn op_ANNN_sets_index(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
-        cpu.exec_opcode(0xA123);
+        cpu.exec_opcode(0xA123).unwrap();
 
         assert_eq!(uint::<12>::new(0x123), cpu.index);
     }
 
     #[rstest]
     fn op_BNNN_jumps(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[0] = 0x10;
 
-        cpu.exec_opcode(0xB113);
+        cpu.exec_opcode(0xB113).unwrap();
 
         assert_eq!(uint::<12>::new(0x123), cpu.program_counter);
     }
 
+    #[rstest]
+    fn op_BXNN_jumps_with_vx_quirk(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::schip());
+        cpu.registers[0] = 0x10;
+        cpu.registers[1] = 0x20;
+
+        cpu.exec_opcode(0xB113).unwrap();
+
+        assert_eq!(uint::<12>::new(0x133), cpu.program_counter);
+    }
+
     #[rstest]
     fn op_DXYN_draws_sprite(
         mut window: Box<MockWindow>,
@@ -784,15 +3247,15 @@ mod tests {
         mmu.expect_read_u8().returning(|x| u16::from(x) as u8);
         window
             .expect_draw()
-            .with(eq(7), eq(8), eq(vec![0x10]))
-            .returning(|_, _, _| true);
+            .with(eq(7), eq(8), eq(8), eq(vec![0x10]))
+            .returning(|_, _, _, _| true);
 
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[3] = 7;
         cpu.registers[2] = 8;
         cpu.index = uint::<12>::new(0x010);
 
-        cpu.exec_opcode(0xD321);
+        cpu.exec_opcode(0xD321).unwrap();
 
         assert_eq!(0x1, cpu.registers[0xF])
     }
@@ -808,17 +3271,124 @@ mod tests {
             .returning(|x| u16::from(x) as u8);
         window
             .expect_draw()
-            .with(eq(7), eq(8), eq(vec![0x10, 0x11]))
-            .returning(|_, _, _| false);
-        let mut cpu = Cpu::new(mmu, window, audio);
+            .with(eq(7), eq(8), eq(8), eq(vec![0x10, 0x11]))
+            .returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[3] = 7;
         cpu.registers[2] = 8;
         cpu.index = uint::<12>::new(0x010);
 
-        cpu.exec_opcode(0xD322);
+        cpu.exec_opcode(0xD322).unwrap();
         assert_eq!(0x0, cpu.registers[0xF])
     }
 
+    #[rstest]
+    fn records_window_calls(mut mmu: Box<MockMmu>, audio: Box<MockAudio>) {
+        mmu.expect_read_u8().returning(|x| u16::from(x) as u8);
+        let window = RecordingWindow::new();
+        let calls = window.calls();
+        let mut cpu = Cpu::new(mmu, Box::new(window), audio, Quirks::default());
+        cpu.set_platform(Platform::XoChip);
+        cpu.registers[1] = 3;
+        cpu.registers[2] = 4;
+        cpu.index = uint::<12>::new(0x010);
+
+        cpu.exec_opcode(0x00E0).unwrap();
+        cpu.exec_opcode(0xD122).unwrap();
+        cpu.exec_opcode(0x00D2).unwrap();
+
+        assert_eq!(
+            vec![
+                WindowCall::BlankScreen,
+                WindowCall::Draw {
+                    x: 3,
+                    y: 4,
+                    width: 8,
+                    sprite: vec![0x10, 0x11]
+                },
+                WindowCall::ScrollUp(2),
+            ],
+            *calls.borrow()
+        );
+    }
+
+    #[rstest]
+    #[case(SpriteReads::Wrap, vec![0xFE, 0xFF, 0x00, 0x01])]
+    #[case(SpriteReads::Zero, vec![0xFE, 0xFF, 0x00, 0x00])]
+    fn op_DXYN_reads_past_end_of_memory(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] sprite_reads: SpriteReads,
+        #[case] expected_sprite: Vec<u8>,
+    ) {
+        mmu.expect_read_u8().returning(|x| u16::from(x) as u8);
+        window
+            .expect_draw()
+            .with(eq(0), eq(0), eq(8), eq(expected_sprite))
+            .returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_sprite_reads(sprite_reads);
+        cpu.index = uint::<12>::new(0xFFE);
+
+        cpu.exec_opcode(0xD004).unwrap();
+    }
+
+    #[rstest]
+    #[case(CoordinateMode::Raw, 70, 40)]
+    #[case(CoordinateMode::Modulo, 6, 8)]
+    #[case(CoordinateMode::Clamp, 63, 31)]
+    fn op_DXYN_applies_coordinate_mode(
+        mut window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] coordinate_mode: CoordinateMode,
+        #[case] expected_x: u8,
+        #[case] expected_y: u8,
+    ) {
+        mmu.expect_read_u8().returning(|_| 0x80);
+        window
+            .expect_draw()
+            .with(eq(expected_x), eq(expected_y), eq(8), eq(vec![0x80]))
+            .returning(|_, _, _, _| false);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_coordinate_mode(coordinate_mode);
+        cpu.registers[3] = 70;
+        cpu.registers[2] = 40;
+
+        cpu.exec_opcode(0xD321).unwrap();
+    }
+
+    #[rstest]
+    #[case(CoordinateMode::Raw, 0)]
+    #[case(CoordinateMode::Modulo, 1)]
+    #[case(CoordinateMode::Clamp, 1)]
+    fn op_DXYN_at_max_registers_clips_tall_sprite(
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] coordinate_mode: CoordinateMode,
+        #[case] lit_pixels: usize,
+    ) {
+        mmu.expect_read_u8().returning(|_| 0xFF);
+        let mut cpu = Cpu::new(
+            mmu,
+            Box::new(HeadlessWindow::new()),
+            audio,
+            Quirks::default(),
+        );
+        cpu.set_coordinate_mode(coordinate_mode);
+        cpu.registers[3] = 255;
+        cpu.registers[2] = 255;
+
+        cpu.exec_opcode(0xD32F).unwrap();
+
+        // Either nothing is drawn or only the bottom-right corner pixel, with no wrapping
+        let pixels = cpu.window().frame().pixels();
+        assert_eq!(lit_pixels, pixels.iter().filter(|pixel| **pixel).count());
+        assert_eq!(lit_pixels == 1, *pixels.last().unwrap());
+        assert_eq!(0, cpu.registers[0xF]);
+    }
+
     #[rstest]
     fn op_EX9E_skips_if_key_pressed(
         mut window: Box<MockWindow>,
@@ -829,10 +3399,10 @@ mod tests {
             .expect_is_key_pressed()
             .with(eq(0xA))
             .returning(|_| true);
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0xA;
 
-        cpu.exec_opcode(0xE49E);
+        cpu.exec_opcode(0xE49E).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
@@ -847,20 +3417,20 @@ mod tests {
             .expect_is_key_pressed()
             .with(eq(0xA))
             .returning(|_| false);
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0xA;
 
-        cpu.exec_opcode(0xE4A1);
+        cpu.exec_opcode(0xE4A1).unwrap();
 
         assert_eq!(uint::<12>::new(0x204), cpu.program_counter);
     }
 
     #[rstest]
     fn op_FX07_sets_vx_to_delay(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.delay_timer = 0xA1;
 
-        cpu.exec_opcode(0xF407);
+        cpu.exec_opcode(0xF407).unwrap();
 
         assert_eq!(0xA1, cpu.registers[4]);
     }
@@ -876,12 +3446,12 @@ mod tests {
             .times(1)
             .returning(|| Some(0x8));
         window.expect_get_pressed_key().times(1).returning(|| None);
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
-        cpu.exec_opcode(0xF40A);
+        cpu.exec_opcode(0xF40A).unwrap();
         assert_eq!(0x0, cpu.registers[4]); // Sanity check
 
-        cpu.exec_opcode(0xF40A);
+        cpu.exec_opcode(0xF40A).unwrap();
         assert_eq!(0x08, cpu.registers[4]);
     }
 
@@ -896,14 +3466,14 @@ mod tests {
             .times(1)
             .returning(|| Some(0x8));
         window.expect_get_pressed_key().times(1).returning(|| None);
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
         // Key is held, wait for release
-        cpu.exec_opcode(0xF40A);
+        cpu.exec_opcode(0xF40A).unwrap();
         assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
 
         // Key is released, increment program counter
-        cpu.exec_opcode(0xF40A);
+        cpu.exec_opcode(0xF40A).unwrap();
         assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
     }
 
@@ -914,40 +3484,57 @@ mod tests {
         audio: Box<MockAudio>,
     ) {
         window.expect_get_pressed_key().returning(|| None);
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
 
-        cpu.exec_opcode(0xF40A);
+        cpu.exec_opcode(0xF40A).unwrap();
 
         assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
     }
 
+    #[rstest]
+    fn op_FX0A_latch_is_visible_and_cleared_by_reset(
+        mut window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        window.expect_get_pressed_key().returning(|| Some(0x8));
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        assert_eq!(None, cpu.key_latch());
+
+        cpu.exec_opcode(0xF40A).unwrap();
+        assert_eq!(Some(0x8), cpu.key_latch());
+
+        cpu.reset();
+        assert_eq!(None, cpu.key_latch());
+    }
+
     #[rstest]
     fn op_FX15_sets_delay(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0xA2;
 
-        cpu.exec_opcode(0xF415);
+        cpu.exec_opcode(0xF415).unwrap();
 
         assert_eq!(0xA2, cpu.delay_timer);
     }
 
     #[rstest]
     fn op_FX15_sets_sound(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0xA3;
 
-        cpu.exec_opcode(0xF418);
+        cpu.exec_opcode(0xF418).unwrap();
 
         assert_eq!(0xA3, cpu.sound_timer);
     }
 
     #[rstest]
     fn op_FX1E_increments_index(window: Box<MockWindow>, mmu: Box<MockMmu>, audio: Box<MockAudio>) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.index = uint::<12>::new(0xA00);
         cpu.registers[4] = 0xFF;
 
-        cpu.exec_opcode(0xF41E);
+        cpu.exec_opcode(0xF41E).unwrap();
 
         assert_eq!(uint::<12>::new(0xAFF), cpu.index);
     }
@@ -958,10 +3545,10 @@ mod tests {
         mmu: Box<MockMmu>,
         audio: Box<MockAudio>,
     ) {
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.registers[4] = 0xB;
 
-        cpu.exec_opcode(0xF429);
+        cpu.exec_opcode(0xF429).unwrap();
 
         assert_eq!(uint::<12>::new(55), cpu.index);
     }
@@ -978,11 +3565,11 @@ mod tests {
             .with(eq(uint::<12>::new(0x102)), eq(3))
             .returning(|_, _| ());
 
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.index = uint::<12>::new(0x100);
         cpu.registers[4] = 213;
 
-        cpu.exec_opcode(0xF433);
+        cpu.exec_opcode(0xF433).unwrap();
     }
 
     #[rstest]
@@ -998,12 +3585,74 @@ mod tests {
             .with(eq(uint::<12>::new(0x101)), eq(0x23))
             .returning(|_, _| ());
 
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.index = uint::<12>::new(0x100);
         cpu.registers[0] = 0x10;
         cpu.registers[1] = 0x23;
 
-        cpu.exec_opcode(0xF155);
+        cpu.exec_opcode(0xF155).unwrap();
+    }
+
+    #[rstest]
+    fn op_FX55_wraps_past_end_of_memory_by_default(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_write_u8()
+            .withf(|address, _| u16::from(*address) >= 0xFF1)
+            .times(15)
+            .returning(|_, _| ());
+        mmu.expect_write_u8()
+            .with(eq(uint::<12>::new(0x000)), eq(0x10))
+            .times(1)
+            .returning(|_, _| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.index = uint::<12>::new(0xFF1);
+        cpu.registers[0xF] = 0x10;
+
+        cpu.exec_opcode(0xFF55).unwrap();
+    }
+
+    #[rstest]
+    fn op_FX55_clamps_at_end_of_memory(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        mmu.expect_write_u8()
+            .withf(|address, _| u16::from(*address) >= 0xFF1)
+            .times(15)
+            .returning(|_, _| ());
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_memory_overflow(MemoryOverflow::Clamp);
+        cpu.index = uint::<12>::new(0xFF1);
+
+        cpu.exec_opcode(0xFF55).unwrap();
+
+        assert_eq!(uint::<12>::new(0x202), cpu.program_counter);
+    }
+
+    #[rstest]
+    fn op_FX65_errors_past_end_of_memory(
+        window: Box<MockWindow>,
+        mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+    ) {
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
+        cpu.set_memory_overflow(MemoryOverflow::Error);
+        cpu.index = uint::<12>::new(0xFF1);
+
+        let result = cpu.exec_opcode(0xFF65);
+
+        assert_eq!(
+            Err(CpuError::MemoryOverflow {
+                index: uint::<12>::new(0xFF1),
+                length: 16
+            }),
+            result
+        );
+        assert_eq!(uint::<12>::new(0x200), cpu.program_counter);
     }
 
     #[rstest]
@@ -1020,12 +3669,33 @@ mod tests {
             .with(eq(uint::<12>::new(0x101)))
             .return_const(8);
 
-        let mut cpu = Cpu::new(mmu, window, audio);
+        let mut cpu = Cpu::new(mmu, window, audio, Quirks::default());
         cpu.index = uint::<12>::new(0x100);
 
-        cpu.exec_opcode(0xF165);
+        cpu.exec_opcode(0xF165).unwrap();
 
         assert_eq!(7, cpu.registers[0]);
         assert_eq!(8, cpu.registers[1]);
     }
+
+    #[rstest]
+    #[case(Quirks::default(), 0x100)]
+    #[case(Quirks::chip8(), 0x102)]
+    fn op_FX55_and_FX65_follow_memory_increment_quirk(
+        window: Box<MockWindow>,
+        mut mmu: Box<MockMmu>,
+        audio: Box<MockAudio>,
+        #[case] quirks: Quirks,
+        #[case] index: u16,
+    ) {
+        mmu.expect_write_u8().returning(|_, _| ());
+        mmu.expect_read_u8().return_const(0);
+        let mut cpu = Cpu::new(mmu, window, audio, quirks);
+
+        for opcode in [0xF155, 0xF165] {
+            cpu.index = uint::<12>::new(0x100);
+            cpu.exec_opcode(opcode).unwrap();
+            assert_eq!(uint::<12>::new(index), cpu.index);
+        }
+    }
 }
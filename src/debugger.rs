@@ -0,0 +1,153 @@
+use crate::cpu::{Cpu, CpuError};
+use arbintrary::uint;
+use std::collections::BTreeSet;
+
+/// Wraps a CPU to run it a step at a time or up to breakpoints, and to inspect its state in
+/// between.
+pub struct Debugger {
+    cpu: Cpu,
+    breakpoints: BTreeSet<uint<12>>,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Debugger {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Stop before running the instruction at `address`.
+    pub fn add_breakpoint(&mut self, address: uint<12>) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Return false if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: uint<12>) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// The breakpoint addresses, lowest first.
+    pub fn breakpoints(&self) -> impl Iterator<Item = uint<12>> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Run a single instruction, ignoring breakpoints.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        self.cpu.step()
+    }
+
+    /// Run until the program counter reaches a breakpoint and return its address. At least one
+    /// instruction is run, so this carries on from a breakpoint it already stopped at. Also stops
+    /// if the program halts by jumping to itself, since no breakpoint can be reached after that.
+    pub fn continue_until_break(&mut self) -> Result<uint<12>, CpuError> {
+        loop {
+            self.cpu.step()?;
+            let program_counter = self.cpu.program_counter();
+            if self.breakpoints.contains(&program_counter) || self.cpu.is_halted() {
+                return Ok(program_counter);
+            }
+        }
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> uint<12> {
+        self.cpu.program_counter()
+    }
+
+    /// V0 to VF.
+    pub fn registers(&self) -> &[u8] {
+        self.cpu.registers()
+    }
+
+    pub fn index(&self) -> uint<12> {
+        self.cpu.index()
+    }
+
+    /// `length` bytes of memory starting at `address`, wrapping round at the end of memory.
+    pub fn memory(&self, address: uint<12>, length: usize) -> Vec<u8> {
+        let memory = self.cpu.memory();
+        (0..length)
+            .map(|offset| memory[(usize::from(u16::from(address)) + offset) % memory.len()])
+            .collect()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Stop debugging and hand back the CPU.
+    pub fn into_cpu(self) -> Cpu {
+        self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::mmu::{Chip8Mmu, Mmu};
+    use crate::quirks::Quirks;
+    use crate::window::HeadlessWindow;
+    use rstest::*;
+
+    // 0x200: ADD V0, 1
+    // 0x202: SE V0, 3
+    // 0x204: JP 0x200
+    // 0x206: JP 0x206
+    const COUNT_TO_THREE: [u8; 8] = [0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x12, 0x06];
+
+    #[fixture]
+    fn debugger() -> Debugger {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.load_program_bytes(&COUNT_TO_THREE).unwrap();
+        Debugger::new(Cpu::new(
+            mmu,
+            Box::new(HeadlessWindow::new()),
+            Box::new(NullAudio),
+            Quirks::default(),
+        ))
+    }
+
+    #[rstest]
+    fn continues_to_each_breakpoint_hit(mut debugger: Debugger) {
+        debugger.add_breakpoint(uint::<12>::new(0x202));
+
+        assert_eq!(
+            uint::<12>::new(0x202),
+            debugger.continue_until_break().unwrap()
+        );
+        assert_eq!(1, debugger.registers()[0]);
+        assert_eq!(
+            uint::<12>::new(0x202),
+            debugger.continue_until_break().unwrap()
+        );
+        assert_eq!(2, debugger.registers()[0]);
+
+        assert!(debugger.remove_breakpoint(uint::<12>::new(0x202)));
+        assert!(!debugger.remove_breakpoint(uint::<12>::new(0x202)));
+        assert_eq!(
+            uint::<12>::new(0x206),
+            debugger.continue_until_break().unwrap()
+        );
+        assert_eq!(3, debugger.registers()[0]);
+    }
+
+    #[rstest]
+    fn steps_and_inspects_memory(mut debugger: Debugger) {
+        debugger.add_breakpoint(uint::<12>::new(0x202));
+
+        debugger.step().unwrap();
+        debugger.step().unwrap();
+
+        assert_eq!(uint::<12>::new(0x204), debugger.program_counter());
+        assert_eq!(
+            vec![0x12, 0x00, 0x12, 0x06],
+            debugger.memory(uint::<12>::new(0x204), 4)
+        );
+    }
+}
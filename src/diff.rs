@@ -0,0 +1,174 @@
+use crate::config::Config;
+use crate::mmu::LoadError;
+use crate::script::{InputScript, ScriptedCpu};
+use arbintrary::uint;
+use std::fmt;
+
+/// The first point at which two runs of the same ROM stopped matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// How many instructions had run, including the one that diverged
+    pub cycle: usize,
+    /// Where the diverging instruction was, in the first run
+    pub program_counter: uint<12>,
+    pub opcode: u16,
+    /// What differs, first run's value first
+    pub difference: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "diverged at cycle {} (PC=0x{:03X} opcode={:04X}): {}",
+            self.cycle,
+            u16::from(self.program_counter),
+            self.opcode,
+            self.difference
+        )
+    }
+}
+
+/// Run `rom` headlessly under two configs in lockstep for up to `cycles` instructions, with the
+/// same seed and scripted input, and find the first instruction after which the registers, PC,
+/// index, memory or display differ.
+///
+/// Returns `None` if the runs still match after `cycles`, or if both stop with the same error.
+pub fn first_divergence(
+    rom: &[u8],
+    first: &Config,
+    second: &Config,
+    seed: u64,
+    input_script: &InputScript,
+    cycles: usize,
+) -> Result<Option<Divergence>, LoadError> {
+    let seeded = |config: &Config| Config {
+        seed: Some(seed),
+        ..config.clone()
+    };
+    let mut first = ScriptedCpu::new(rom, &seeded(first), input_script)?;
+    let mut second = ScriptedCpu::new(rom, &seeded(second), input_script)?;
+
+    for cycle in 1..=cycles {
+        let program_counter = first.cpu.program_counter();
+        let opcode = first.cpu.peek_next_opcode();
+        let divergence = |difference| {
+            Some(Divergence {
+                cycle,
+                program_counter,
+                opcode,
+                difference,
+            })
+        };
+
+        match (first.step(), second.step()) {
+            (Ok(()), Ok(())) => {}
+            (Err(a), Err(b)) if a == b => return Ok(None),
+            (a, b) => {
+                let outcome = |result: Result<(), _>| match result {
+                    Ok(()) => "ran".to_string(),
+                    Err(err) => format!("stopped: {}", err),
+                };
+                return Ok(divergence(format!("{} vs {}", outcome(a), outcome(b))));
+            }
+        }
+
+        if let Some(difference) = difference(&first, &second) {
+            return Ok(divergence(difference));
+        }
+    }
+    Ok(None)
+}
+
+fn difference(first: &ScriptedCpu, second: &ScriptedCpu) -> Option<String> {
+    let (a, b) = (&first.cpu, &second.cpu);
+    if a.program_counter() != b.program_counter() {
+        return Some(format!(
+            "PC is 0x{:03X} vs 0x{:03X}",
+            u16::from(a.program_counter()),
+            u16::from(b.program_counter())
+        ));
+    }
+    if a.index() != b.index() {
+        return Some(format!(
+            "I is 0x{:03X} vs 0x{:03X}",
+            u16::from(a.index()),
+            u16::from(b.index())
+        ));
+    }
+    let registers = a.registers().iter().zip(b.registers());
+    for (register, (x, y)) in registers.enumerate() {
+        if x != y {
+            return Some(format!("V{:X} is 0x{:02X} vs 0x{:02X}", register, x, y));
+        }
+    }
+    let memory = a.memory().into_iter().zip(b.memory());
+    for (address, (x, y)) in memory.enumerate() {
+        if x != y {
+            return Some(format!(
+                "memory at 0x{:03X} is 0x{:02X} vs 0x{:02X}",
+                address, x, y
+            ));
+        }
+    }
+    if a.window().frame() != b.window().frame() {
+        return Some("display differs".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::QuirkOverrides;
+
+    // VF holds 5 until the OR, which clears it only with the VF reset quirk
+    const OR_ROM: [u8; 8] = [
+        0x6F, 0x05, // 200: LD VF, 5
+        0x60, 0x01, // 202: LD V0, 1
+        0x80, 0x11, // 204: OR V0, V1
+        0x12, 0x06, // 206: JP 206
+    ];
+
+    #[test]
+    fn finds_first_divergence() {
+        let with_reset = Config::default();
+        let without_reset = Config {
+            quirk_overrides: QuirkOverrides {
+                vf_reset_on_logic: Some(false),
+                ..QuirkOverrides::default()
+            },
+            ..Config::default()
+        };
+
+        let divergence = first_divergence(
+            &OR_ROM,
+            &with_reset,
+            &without_reset,
+            0,
+            &InputScript::default(),
+            100,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(Divergence {
+                cycle: 3,
+                program_counter: uint::<12>::new(0x204),
+                opcode: 0x8011,
+                difference: "VF is 0x00 vs 0x05".to_string(),
+            }),
+            divergence
+        );
+    }
+
+    #[test]
+    fn matching_runs_do_not_diverge() {
+        let config = Config::default();
+
+        let divergence =
+            first_divergence(&OR_ROM, &config, &config, 0, &InputScript::default(), 100).unwrap();
+
+        assert_eq!(None, divergence);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::instruction::Instruction;
+use crate::mmu::Chip8Mmu;
+use arbintrary::uint;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Linearly decode bytes loaded at `base` into instructions and the address of each. Two-word
+/// instructions such as XO-CHIP's `F000 NNNN` are stepped over whole, so the words after them stay
+/// aligned. A dangling final byte is ignored. Each instruction displays as its mnemonic, or as
+/// `DW 0xNNNN` if the word isn't an instruction.
+pub fn disassemble(bytes: &[u8], base: uint<12>) -> Vec<(uint<12>, Instruction)> {
+    decode_all(bytes)
+        .into_iter()
+        .map(|(offset, _, instruction)| (address_at(base, offset), instruction))
+        .collect()
+}
+
+// Each instruction in `bytes` with its offset and length in bytes
+fn decode_all(bytes: &[u8]) -> Vec<(usize, usize, Instruction)> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while let Some((instruction, length)) = Instruction::decode_bytes(&bytes[offset..]) {
+        instructions.push((offset, length, instruction));
+        offset += length;
+    }
+    instructions
+}
+
+// The address of `offset` bytes past `base`, wrapping round at the end of memory
+fn address_at(base: uint<12>, offset: usize) -> uint<12> {
+    base.wrapping_add(uint::<12>::new((offset % 0x1000) as u16))
+}
+
+/// The instructions that refer to an address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Xrefs {
+    /// Addresses of jumps and calls to it (1NNN, 2NNN and BNNN)
+    pub code: Vec<u16>,
+    /// Addresses of ANNN instructions pointing I at it
+    pub data: Vec<u16>,
+}
+
+impl Xrefs {
+    /// Only ever pointed to by I and never jumped to, so most likely sprite or other data.
+    pub fn is_data(&self) -> bool {
+        self.code.is_empty() && !self.data.is_empty()
+    }
+}
+
+/// Find what refers to each address in a disassembly. BNNN is recorded against NNN, as its
+/// real target depends on V0.
+pub fn cross_references(instructions: &[(uint<12>, Instruction)]) -> BTreeMap<u16, Xrefs> {
+    let mut xrefs: BTreeMap<u16, Xrefs> = BTreeMap::new();
+    for (address, instruction) in instructions {
+        let from = u16::from(*address);
+        match instruction {
+            Instruction::Jump(target) | Instruction::Call(target) | Instruction::JumpV0(target) => {
+                xrefs.entry(u16::from(*target)).or_default().code.push(from)
+            }
+            Instruction::LoadIndex(target) => {
+                xrefs.entry(u16::from(*target)).or_default().data.push(from)
+            }
+            _ => (),
+        }
+    }
+    xrefs
+}
+
+/// A disassembly listing of `rom`, one instruction per line with its address, raw bytes and
+/// mnemonic. Addresses that are jumped to or called get a `; xref from` comment, and those
+/// only ever loaded into I are marked as data.
+pub fn listing(rom: &[u8]) -> String {
+    let base = uint::<12>::new(Chip8Mmu::PROGRAM_START as u16);
+    let xrefs = cross_references(&disassemble(rom, base));
+
+    let mut listing = String::new();
+    // Work from byte offsets, as addresses wrap round in ROMs too big to load
+    for (offset, length, instruction) in decode_all(rom) {
+        let address = address_at(base, offset);
+        let bytes: String = rom[offset..offset + length]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        let _ = write!(
+            listing,
+            "0x{:03X}  {:<8}  {}",
+            u16::from(address),
+            bytes,
+            instruction
+        );
+
+        if let Some(xrefs) = xrefs.get(&u16::from(address)) {
+            if xrefs.is_data() {
+                let _ = write!(listing, "  ; data, I from {}", addresses(&xrefs.data));
+            } else {
+                let _ = write!(listing, "  ; xref from {}", addresses(&xrefs.code));
+            }
+        }
+        listing.push('\n');
+    }
+    listing
+}
+
+fn addresses(addresses: &[u16]) -> String {
+    addresses
+        .iter()
+        .map(|address| format!("0x{:03X}", address))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_over_long_load() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x60, 0x01, 0x00, 0xE0, 0xFF];
+
+        let instructions = disassemble(&rom, uint::<12>::new(0x200));
+
+        assert_eq!(
+            vec![
+                (uint::<12>::new(0x200), Instruction::LoadLongI(0x1234)),
+                (uint::<12>::new(0x204), Instruction::LoadByte(0x0, 0x01)),
+                (uint::<12>::new(0x206), Instruction::Cls),
+            ],
+            instructions
+        );
+    }
+
+    // Loop calling a subroutine that draws the sprite at 0x20C
+    const ROM: [u8; 14] = [
+        0x22, 0x06, // 200: CALL 206
+        0x12, 0x00, // 202: JP 200
+        0x12, 0x00, // 204: JP 200
+        0xA2, 0x0C, // 206: LD I, 20C
+        0xD0, 0x01, // 208: DRW V0, V0, 1
+        0x00, 0xEE, // 20A: RET
+        0x80, 0x00, // 20C: sprite
+    ];
+
+    #[test]
+    fn finds_cross_references() {
+        let xrefs = cross_references(&disassemble(&ROM, uint::<12>::new(0x200)));
+
+        assert_eq!(vec![0x202, 0x204], xrefs[&0x200].code);
+        assert_eq!(vec![0x200], xrefs[&0x206].code);
+        assert!(!xrefs[&0x206].is_data());
+        assert_eq!(vec![0x206], xrefs[&0x20C].data);
+        assert!(xrefs[&0x20C].is_data());
+        assert!(!xrefs.contains_key(&0x208));
+    }
+
+    #[test]
+    fn listing_annotates_xrefs_and_data() {
+        let listing = listing(&ROM);
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(7, lines.len());
+        assert_eq!(
+            "0x200  2206      CALL 0x206  ; xref from 0x202, 0x204",
+            lines[0]
+        );
+        assert_eq!("0x202  1200      JP 0x200", lines[1]);
+        assert_eq!("0x206  A20C      LD I, 0x20C  ; xref from 0x200", lines[3]);
+        assert_eq!("0x208  D001      DRW V0, V0, 1", lines[4]);
+        assert_eq!("0x20C  8000      LD V0, V0  ; data, I from 0x206", lines[6]);
+    }
+
+    #[test]
+    fn listing_shows_both_words_of_long_load() {
+        assert_eq!(
+            "0x200  F0001234  LD I, 0x1234\n",
+            listing(&[0xF0, 0x00, 0x12, 0x34])
+        );
+    }
+
+    #[test]
+    fn listing_wraps_addresses_past_end_of_memory() {
+        let rom = vec![0x60; 0xE02];
+
+        let listing = listing(&rom);
+
+        assert_eq!(0x701, listing.lines().count());
+        assert_eq!(Some("0x000  6060      LD V0, 0x60"), listing.lines().last());
+    }
+
+    #[test]
+    fn disassembles_at_base_with_data_words() {
+        let instructions: Vec<(u16, String)> =
+            disassemble(&[0x64, 0x50, 0x81, 0x48, 0xFF], uint::<12>::new(0x300))
+                .into_iter()
+                .map(|(address, instruction)| (u16::from(address), instruction.to_string()))
+                .collect();
+
+        assert_eq!(
+            vec![
+                (0x300, "LD V4, 0x50".to_string()),
+                (0x302, "DW 0x8148".to_string()),
+            ],
+            instructions
+        );
+    }
+}
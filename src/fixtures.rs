@@ -0,0 +1,50 @@
+//! Tiny ROMs with well-understood behaviour, for tests that need a real program to run without
+//! shipping their own files. Each ends on a self-jump, so it can be run for any number of cycles.
+
+/// Set V0 to 0x42, then loop at 0x202.
+pub const SET_V0: &[u8] = include_bytes!("../resources/fixtures/set_v0.ch8");
+
+/// Clear the screen and draw a 4x5 "0" at the top left, then loop at 0x206.
+pub const DRAW_SPRITE: &[u8] = include_bytes!("../resources/fixtures/draw_sprite.ch8");
+
+/// corax89's opcode test ROM, which draws OK or an error code for each opcode it checks.
+pub const TEST_OPCODE: &[u8] = include_bytes!("../resources/test/test_opcode.ch8");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_rom_and_capture, Config};
+    use arbintrary::uint;
+
+    #[test]
+    fn set_v0_sets_v0() {
+        let state = run_rom_and_capture(SET_V0, 10, &Config::default()).unwrap();
+
+        assert_eq!(0x42, state.registers[0]);
+        assert_eq!(uint::<12>::new(0x202), state.program_counter);
+    }
+
+    #[test]
+    fn draw_sprite_draws_zero() {
+        let state = run_rom_and_capture(DRAW_SPRITE, 10, &Config::default()).unwrap();
+
+        assert_eq!(uint::<12>::new(0x206), state.program_counter);
+        let text = state.frame.text();
+        let corner: Vec<&str> = text.lines().take(6).map(|line| &line[..5]).collect();
+        assert_eq!(
+            vec!["####.", "#..#.", "#..#.", "#..#.", "####.", "....."],
+            corner
+        );
+        assert_eq!(
+            14,
+            state.frame.pixels().iter().filter(|pixel| **pixel).count()
+        );
+    }
+
+    #[test]
+    fn test_opcode_loads() {
+        let state = run_rom_and_capture(TEST_OPCODE, 1, &Config::default()).unwrap();
+
+        assert_eq!(TEST_OPCODE, &state.memory[0x200..][..TEST_OPCODE.len()]);
+    }
+}
@@ -0,0 +1,719 @@
+use fnv::FnvHasher;
+use std::hash::Hasher;
+
+/// A rectangle of the display that drawing is restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Clip {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Clip {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// The logical CHIP-8 display, independent of how it is presented.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    // XO-CHIP's plane 1, the only plane other variants have
+    pixels: Vec<bool>,
+    plane_2: Vec<bool>,
+    // Bit 0 for plane 1 and bit 1 for plane 2, set on the planes draws, clears and scrolls act on
+    planes: u8,
+    // Pixels a sprite has touched since they were last cleared, lit or not
+    drawn: Vec<bool>,
+    // Current resolution, 64x32 or 128x64 in SCHIP's high resolution mode
+    width: usize,
+    height: usize,
+    clip: Clip,
+    // Sprite pixels past an edge come back in at the opposite edge instead of being clipped
+    wrap: bool,
+    clipped_draws: u64,
+}
+
+impl FrameBuffer {
+    /// The standard resolution.
+    pub const WIDTH: usize = 64;
+    pub const HEIGHT: usize = 32;
+    /// SCHIP's high resolution mode.
+    pub const HIRES_WIDTH: usize = 128;
+    pub const HIRES_HEIGHT: usize = 64;
+
+    pub fn new() -> FrameBuffer {
+        FrameBuffer {
+            pixels: vec![false; Self::WIDTH * Self::HEIGHT],
+            plane_2: vec![false; Self::WIDTH * Self::HEIGHT],
+            planes: 0b01,
+            drawn: vec![false; Self::WIDTH * Self::HEIGHT],
+            width: Self::WIDTH,
+            height: Self::HEIGHT,
+            clip: Self::full_screen(Self::WIDTH, Self::HEIGHT),
+            wrap: false,
+            clipped_draws: 0,
+        }
+    }
+
+    fn full_screen(width: usize, height: usize) -> Clip {
+        Clip {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Pixels per row at the current resolution.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Rows at the current resolution.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == Self::HIRES_WIDTH
+    }
+
+    /// Switch between 64x32 and SCHIP's 128x64. Either way the display is cleared and any clip is
+    /// removed.
+    pub fn set_hires(&mut self, hires: bool) {
+        (self.width, self.height) = if hires {
+            (Self::HIRES_WIDTH, Self::HIRES_HEIGHT)
+        } else {
+            (Self::WIDTH, Self::HEIGHT)
+        };
+        self.pixels = vec![false; self.width * self.height];
+        self.plane_2 = vec![false; self.width * self.height];
+        self.drawn = vec![false; self.width * self.height];
+        self.clip = Self::full_screen(self.width, self.height);
+    }
+
+    /// Restrict drawing, clearing and scrolling to a rectangle. The rectangle is cropped to the
+    /// screen.
+    pub fn set_clip(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        self.clip = Clip {
+            x,
+            y,
+            width: width.min(self.width - x),
+            height: height.min(self.height - y),
+        };
+    }
+
+    /// Allow drawing to the whole screen again.
+    pub fn clear_clip(&mut self) {
+        self.clip = Self::full_screen(self.width, self.height);
+    }
+
+    /// Wrap sprite pixels past the right or bottom edge round to the left or top, instead of
+    /// clipping them. A sprite wide enough to wrap onto itself collides with its own pixels.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    /// Select the XO-CHIP bit planes that draws, clears and scrolls act on: bit 0 for plane 1 and
+    /// bit 1 for plane 2. Only plane 1 is selected to begin with.
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    // The buffers a clear or scroll moves: the selected planes, with the drawn mask following
+    // plane 1
+    fn selected_buffers(&mut self) -> Vec<&mut Vec<bool>> {
+        let mut buffers = Vec::with_capacity(3);
+        if self.planes & 0b01 != 0 {
+            buffers.push(&mut self.pixels);
+            buffers.push(&mut self.drawn);
+        }
+        if self.planes & 0b10 != 0 {
+            buffers.push(&mut self.plane_2);
+        }
+        buffers
+    }
+
+    /// Blank the selected planes inside the clip. A pixel left unlit on every plane counts as
+    /// cleared again.
+    pub fn clear(&mut self) {
+        for y in self.clip.y..self.clip.y + self.clip.height {
+            for x in self.clip.x..self.clip.x + self.clip.width {
+                let i = x + y * self.width;
+                if self.planes & 0b01 != 0 {
+                    self.pixels[i] = false;
+                }
+                if self.planes & 0b10 != 0 {
+                    self.plane_2[i] = false;
+                }
+                self.drawn[i] &= self.pixels[i] || self.plane_2[i];
+            }
+        }
+    }
+
+    /// XOR a sprite `width` pixels wide onto the selected planes. Each row takes `width` rounded
+    /// up to whole bytes. With both planes selected, the first half of `sprite` goes to plane 1
+    /// and the second half to plane 2. Return true if a collision has occurred on any plane.
+    pub fn draw(&mut self, x: u8, y: u8, width: u8, sprite: &[u8]) -> bool {
+        // Widen before adding offsets so that an origin near 255 can't overflow; anything past the
+        // edge is then clipped below
+        let (x, y) = (x as usize, y as usize);
+        let mut collision = false;
+        let mut off_screen = false;
+        let stride = (width as usize).div_ceil(8);
+        let planes: Vec<u8> = vec![0b01, 0b10]
+            .into_iter()
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+        let plane_length = sprite.len() / planes.len().max(1);
+        for (plane, sprite) in planes.iter().zip(sprite.chunks(plane_length.max(1))) {
+            let (hit, clipped) = self.draw_plane(*plane, x, y, width, stride, sprite);
+            collision |= hit;
+            off_screen |= clipped;
+        }
+        if off_screen {
+            self.clipped_draws += 1;
+        }
+        collision
+    }
+
+    // Draw onto one plane, returning whether it collided and whether pixels fell off the screen
+    fn draw_plane(
+        &mut self,
+        plane: u8,
+        x: usize,
+        y: usize,
+        width: u8,
+        stride: usize,
+        sprite: &[u8],
+    ) -> (bool, bool) {
+        let mut collision = false;
+        let mut off_screen = false;
+        for (y_offset, row) in sprite.chunks(stride).enumerate() {
+            for x_offset in 0..width as usize {
+                if (row[x_offset / 8] >> (7 - x_offset % 8)) & 0x1 == 0x0 {
+                    continue;
+                }
+
+                let (mut pixel_x, mut pixel_y) = (x + x_offset, y + y_offset);
+                if self.wrap {
+                    pixel_x %= self.width;
+                    pixel_y %= self.height;
+                }
+                off_screen |= pixel_x >= self.width || pixel_y >= self.height;
+                if !self.clip.contains(pixel_x, pixel_y) {
+                    continue;
+                }
+
+                let i = pixel_x + pixel_y * self.width;
+                let pixel = if plane == 0b01 {
+                    &mut self.pixels[i]
+                } else {
+                    &mut self.plane_2[i]
+                };
+                collision |= *pixel;
+                *pixel = !*pixel;
+                self.drawn[i] = true;
+            }
+        }
+        (collision, off_screen)
+    }
+
+    /// Read a `width` by `height` region back as sprite rows, each `width` rounded up to whole
+    /// bytes with the most significant bit leftmost. Pixels past the edge of the screen read as
+    /// unlit.
+    pub fn sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        let (x, y) = (x as usize, y as usize);
+        let stride = (width as usize).div_ceil(8);
+        let mut sprite = vec![0; stride * height as usize];
+        for y_offset in 0..height as usize {
+            for x_offset in 0..width as usize {
+                let (pixel_x, pixel_y) = (x + x_offset, y + y_offset);
+                if pixel_x < self.width
+                    && pixel_y < self.height
+                    && self.pixels[pixel_x + pixel_y * self.width]
+                {
+                    sprite[y_offset * stride + x_offset / 8] |= 0x80 >> (x_offset % 8);
+                }
+            }
+        }
+        sprite
+    }
+
+    /// Move every row inside the clip up by `n` pixels. Rows shifted in at the bottom are blank.
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n.min(self.height) as isize));
+    }
+
+    // Move the selected planes' pixels inside the clip by (dx, dy). Pixels moved in from outside
+    // the clip are blank, and pixels moved past its edge are lost.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (clip, width) = (self.clip, self.width);
+        for pixels in self.selected_buffers() {
+            let source = pixels.clone();
+            for y in clip.y..clip.y + clip.height {
+                for x in clip.x..clip.x + clip.width {
+                    let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                    pixels[x + y * width] = from_x >= 0
+                        && from_y >= 0
+                        && clip.contains(from_x as usize, from_y as usize)
+                        && source[from_x as usize + from_y as usize * width];
+                }
+            }
+        }
+    }
+
+    /// How many draws had lit pixels cut off at the edge of the screen. A ROM that does this a lot
+    /// was probably written for an interpreter that wraps sprites instead.
+    pub fn clipped_draws(&self) -> u64 {
+        self.clipped_draws
+    }
+
+    /// Move every row inside the clip down by `n` pixels. Rows shifted in at the top are blank.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n.min(self.height) as isize);
+    }
+
+    /// Move every column inside the clip left by `n` pixels. Columns shifted in at the right are
+    /// blank.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n.min(self.width) as isize), 0);
+    }
+
+    /// Move every column inside the clip right by `n` pixels. Columns shifted in at the left are
+    /// blank.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n.min(self.width) as isize, 0);
+    }
+
+    /// Replace every pixel, in row-major order.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn set_pixels(&mut self, pixels: &[bool]) {
+        assert_eq!(self.width * self.height, pixels.len());
+        self.pixels.copy_from_slice(pixels);
+    }
+
+    /// The display as text, one line per row, with `#` for pixels lit on any plane and `.` for
+    /// unlit ones.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            text.extend(
+                (0..self.width).map(|x| match self.plane_states(x + y * self.width) {
+                    0 => '.',
+                    _ => '#',
+                }),
+            );
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Pixels in row-major order, `true` where a pixel is lit. With XO-CHIP bit planes this is
+    /// plane 1.
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+
+    /// XO-CHIP's plane 2 in row-major order, `true` where a pixel is lit.
+    pub fn plane_2(&self) -> &[bool] {
+        &self.plane_2
+    }
+
+    /// The planes lit at pixel `i` in row-major order: bit 0 for plane 1 and bit 1 for plane 2,
+    /// as `Palette::color` takes them.
+    pub fn plane_states(&self, i: usize) -> u8 {
+        self.pixels[i] as u8 | (self.plane_2[i] as u8) << 1
+    }
+
+    /// Pixels in row-major order, `true` where a sprite has drawn since the pixel was last cleared,
+    /// even if it has been drawn off again. Lets a presenter show cleared and unlit pixels apart.
+    pub fn drawn(&self) -> &[bool] {
+        &self.drawn
+    }
+
+    /// A stable FNV-1a hash of the pixels on both planes, reproducible across platforms.
+    pub fn hash(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for i in 0..self.pixels.len() {
+            hasher.write_u8(self.plane_states(i));
+        }
+        hasher.finish()
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> FrameBuffer {
+        FrameBuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_lit(frame: &FrameBuffer, x: usize, y: usize) -> bool {
+        frame.pixels()[x + y * FrameBuffer::WIDTH]
+    }
+
+    #[test]
+    fn draws_sprite_rows() {
+        let mut frame = FrameBuffer::new();
+
+        let collision = frame.draw(2, 3, 8, &[0b1000_0001, 0b0100_0000]);
+
+        assert!(!collision);
+        assert!(is_lit(&frame, 2, 3));
+        assert!(is_lit(&frame, 9, 3));
+        assert!(is_lit(&frame, 3, 4));
+        assert_eq!(3, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn hires_doubles_resolution_and_clears() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0x80]);
+
+        frame.set_hires(true);
+        frame.draw(120, 60, 8, &[0xFF]);
+
+        assert_eq!((128, 64), (frame.width(), frame.height()));
+        assert_eq!(8, frame.pixels().iter().filter(|pixel| **pixel).count());
+        assert!(frame.pixels()[127 + 60 * 128]);
+        assert_eq!(0, frame.clipped_draws());
+
+        frame.set_hires(false);
+        assert_eq!(FrameBuffer::new().pixels(), frame.pixels());
+    }
+
+    #[test]
+    fn wraps_sprite_past_edges() {
+        let mut frame = FrameBuffer::new();
+        frame.set_wrap(true);
+
+        let collision = frame.draw(62, 31, 8, &[0b1110_0000, 0b1000_0000]);
+
+        assert!(!collision);
+        assert!(is_lit(&frame, 62, 31));
+        assert!(is_lit(&frame, 0, 31));
+        assert!(is_lit(&frame, 62, 0));
+        assert_eq!(0, frame.clipped_draws());
+    }
+
+    #[test]
+    fn wrapped_sprite_collides_with_itself() {
+        let mut frame = FrameBuffer::new();
+        frame.set_wrap(true);
+        // 72 pixels wide, so the last 8 columns wrap back onto the first 8
+        let mut sprite = vec![0x00; 9];
+        sprite[0] = 0b1000_0000;
+        sprite[8] = 0b1100_0000;
+
+        let collision = frame.draw(0, 0, 72, &sprite);
+
+        assert!(collision);
+        // Column 0 was drawn on then off again by the same draw
+        assert!(!is_lit(&frame, 0, 0));
+        assert!(is_lit(&frame, 1, 0));
+    }
+
+    #[test]
+    fn clipped_sprite_does_not_collide_with_itself() {
+        let mut frame = FrameBuffer::new();
+        let mut sprite = vec![0x00; 9];
+        sprite[0] = 0b1000_0000;
+        sprite[8] = 0b1100_0000;
+
+        assert!(!frame.draw(0, 0, 72, &sprite));
+        assert!(is_lit(&frame, 0, 0));
+    }
+
+    #[test]
+    fn reads_back_wide_sprite_past_edge() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(56, 0, 16, &[0xFF, 0x81]);
+
+        // Only the first 8 columns fit on screen, the rest read as unlit
+        assert_eq!(vec![0xFF, 0x00, 0x00, 0x00], frame.sprite(56, 0, 16, 2));
+        assert_eq!(vec![0xE0], frame.sprite(61, 0, 3, 1));
+    }
+
+    #[test]
+    fn draws_16_wide_sprite() {
+        let mut frame = FrameBuffer::new();
+
+        let collision = frame.draw(2, 3, 16, &[0x80, 0x01, 0x00, 0xFF]);
+
+        assert!(!collision);
+        assert!(is_lit(&frame, 2, 3));
+        assert!(is_lit(&frame, 17, 3));
+        assert!(!is_lit(&frame, 9, 3));
+        assert!(is_lit(&frame, 10, 4));
+        assert!(is_lit(&frame, 17, 4));
+        assert!(!is_lit(&frame, 9, 4));
+        assert_eq!(10, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn redraw_erases_and_collides() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(2, 3, 8, &[0xFF]);
+
+        let collision = frame.draw(2, 3, 8, &[0xFF]);
+
+        assert!(collision);
+        assert_eq!(FrameBuffer::new().pixels(), frame.pixels());
+    }
+
+    #[test]
+    fn tracks_drawn_pixels_until_cleared() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(2, 3, 8, &[0x80]);
+        frame.draw(2, 3, 8, &[0x80]);
+
+        assert!(!is_lit(&frame, 2, 3));
+        assert!(frame.drawn()[2 + 3 * FrameBuffer::WIDTH]);
+        assert_eq!(1, frame.drawn().iter().filter(|drawn| **drawn).count());
+
+        frame.clear();
+
+        assert_eq!(FrameBuffer::new(), frame);
+    }
+
+    #[test]
+    fn clips_at_screen_edge() {
+        let mut frame = FrameBuffer::new();
+
+        frame.draw(60, 31, 8, &[0xFF, 0xFF]);
+
+        assert_eq!(4, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn counts_draws_clipped_at_screen_edge() {
+        let mut frame = FrameBuffer::new();
+
+        frame.draw(0, 0, 8, &[0xFF]);
+        frame.draw(60, 0, 8, &[0xF0]);
+        frame.draw(60, 0, 8, &[0xFF]);
+        frame.draw(0, 31, 8, &[0xFF, 0x00]);
+        frame.draw(0, 31, 8, &[0xFF, 0x01]);
+
+        assert_eq!(2, frame.clipped_draws());
+    }
+
+    #[test]
+    fn draw_at_max_origin_clips_without_overflow() {
+        let mut frame = FrameBuffer::new();
+
+        let collision = frame.draw(255, 255, 8, &[0xFF; 15]);
+
+        assert!(!collision);
+        assert_eq!(FrameBuffer::new().pixels(), frame.pixels());
+        assert_eq!(1, frame.clipped_draws());
+    }
+
+    #[test]
+    fn clear_blanks_pixels() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xFF]);
+
+        frame.clear();
+
+        assert_eq!(FrameBuffer::new(), frame);
+    }
+
+    #[test]
+    fn clip_crops_straddling_sprite() {
+        let mut frame = FrameBuffer::new();
+        frame.set_clip(4, 2, 8, 2);
+
+        frame.draw(0, 1, 8, &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        // Only columns 4-7 of rows 2-3 fall inside the clip
+        assert_eq!(8, frame.pixels().iter().filter(|pixel| **pixel).count());
+        assert!(is_lit(&frame, 4, 2));
+        assert!(is_lit(&frame, 7, 3));
+        assert!(!is_lit(&frame, 3, 2));
+        assert!(!is_lit(&frame, 4, 1));
+        assert!(!is_lit(&frame, 4, 4));
+    }
+
+    #[test]
+    fn clip_limits_clear() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xFF]);
+        frame.set_clip(0, 0, 4, 1);
+
+        frame.clear();
+
+        assert!(!is_lit(&frame, 3, 0));
+        assert!(is_lit(&frame, 4, 0));
+    }
+
+    #[test]
+    fn clear_clip_restores_full_screen() {
+        let mut frame = FrameBuffer::new();
+        frame.set_clip(0, 0, 1, 1);
+
+        frame.clear_clip();
+        frame.draw(60, 31, 8, &[0xFF]);
+
+        assert_eq!(4, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn scroll_up_moves_rows_and_blanks_bottom() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 2, 8, &[0x80]);
+        frame.draw(8, 31, 8, &[0x80]);
+
+        frame.scroll_up(2);
+
+        assert!(is_lit(&frame, 0, 0));
+        assert!(is_lit(&frame, 8, 29));
+        assert!(!is_lit(&frame, 8, 31));
+        assert_eq!(2, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn scroll_up_past_height_blanks_screen() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xFF]);
+
+        frame.scroll_up(40);
+
+        assert_eq!(FrameBuffer::new(), frame);
+    }
+
+    #[test]
+    fn scroll_down_moves_rows_and_blanks_top() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0x80]);
+        frame.draw(8, 30, 8, &[0x80]);
+
+        frame.scroll_down(1);
+
+        assert!(is_lit(&frame, 0, 1));
+        assert!(is_lit(&frame, 8, 31));
+        assert!(!is_lit(&frame, 0, 0));
+        assert_eq!(2, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn scroll_left_and_right_blank_exposed_columns() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(2, 0, 8, &[0x80]);
+        frame.draw(62, 5, 8, &[0xC0]);
+
+        frame.scroll_left(4);
+
+        assert!(is_lit(&frame, 58, 5));
+        assert!(is_lit(&frame, 59, 5));
+        assert_eq!(2, frame.pixels().iter().filter(|pixel| **pixel).count());
+
+        frame.scroll_right(4);
+
+        assert!(is_lit(&frame, 62, 5));
+        assert!(!is_lit(&frame, 2, 0));
+        assert_eq!(2, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn clip_limits_sideways_scrolls() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xFF]);
+        frame.set_clip(2, 0, 4, 4);
+
+        frame.scroll_right(1);
+
+        assert!(!is_lit(&frame, 2, 0));
+        assert_eq!(7, frame.pixels().iter().filter(|pixel| **pixel).count());
+
+        frame.scroll_left(2);
+
+        assert!(is_lit(&frame, 2, 0) && is_lit(&frame, 3, 0));
+        assert!(!is_lit(&frame, 4, 0) && !is_lit(&frame, 5, 0));
+        assert!(is_lit(&frame, 6, 0) && is_lit(&frame, 7, 0));
+        assert_eq!(6, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+
+    #[test]
+    fn draws_each_selected_plane_from_its_own_sprite() {
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b11);
+
+        let collision = frame.draw(0, 0, 8, &[0xC0, 0x60]);
+
+        assert!(!collision);
+        assert_eq!(
+            vec![1, 3, 2, 0],
+            (0..4).map(|i| frame.plane_states(i)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn collision_on_either_plane_is_reported() {
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b10);
+        frame.draw(0, 0, 8, &[0x80]);
+        frame.set_planes(0b01);
+        assert!(!frame.draw(0, 0, 8, &[0x80]));
+
+        frame.set_planes(0b11);
+        assert!(frame.draw(0, 0, 8, &[0x00, 0x80]));
+        assert_eq!(1, frame.plane_states(0));
+    }
+
+    #[test]
+    fn clear_and_scroll_only_touch_selected_planes() {
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b11);
+        frame.draw(0, 0, 8, &[0x80, 0x80]);
+
+        frame.set_planes(0b10);
+        frame.scroll_down(1);
+        assert_eq!(1, frame.plane_states(0));
+        assert_eq!(2, frame.plane_states(FrameBuffer::WIDTH));
+
+        frame.clear();
+        assert_eq!(1, frame.plane_states(0));
+        assert!(frame.plane_2().iter().all(|pixel| !pixel));
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(0x28C3_1CF8_DF2E_C325, FrameBuffer::new().hash());
+    }
+
+    #[test]
+    fn hash_changes_with_pixels() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0x80]);
+
+        assert_ne!(FrameBuffer::new().hash(), frame.hash());
+    }
+
+    #[test]
+    fn clip_limits_scrolls() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xFF]);
+        frame.draw(0, 1, 8, &[0xFF]);
+        frame.draw(0, 3, 8, &[0x80]);
+        frame.set_clip(2, 0, 4, 4);
+
+        frame.scroll_up(1);
+
+        // Only columns 2 to 5 moved, and what moved past the clip's edge was lost
+        assert!(is_lit(&frame, 2, 0) && is_lit(&frame, 5, 0));
+        assert!(is_lit(&frame, 0, 1) && is_lit(&frame, 7, 1));
+        assert!(!is_lit(&frame, 2, 1) && !is_lit(&frame, 5, 1));
+        assert!(is_lit(&frame, 0, 3));
+        assert_eq!(13, frame.pixels().iter().filter(|pixel| **pixel).count());
+    }
+}
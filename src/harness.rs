@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::cpu::CpuError;
+use crate::framebuffer::FrameBuffer;
+use crate::mmu::LoadError;
+use crate::script::{InputScript, ScriptedCpu};
+use arbintrary::uint;
+use std::error::Error;
+use std::fmt;
+
+/// The state of the machine after `run_rom_and_capture`, for asserting against known-good values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedState {
+    /// V0 to VF
+    pub registers: Vec<u8>,
+    pub index: uint<12>,
+    pub program_counter: uint<12>,
+    /// All of memory, including any changes the ROM made to itself
+    pub memory: Vec<u8>,
+    pub frame: FrameBuffer,
+}
+
+/// Why `run_rom_and_capture` couldn't finish.
+#[derive(Debug)]
+pub enum CaptureError {
+    Load(LoadError),
+    Cpu(CpuError),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::Load(err) => err.fmt(f),
+            CaptureError::Cpu(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for CaptureError {}
+
+/// Run `rom` headlessly for `cycles` instructions, ticking the timers as often as `config`'s
+/// frequency would, and capture the final state.
+pub fn run_rom_and_capture(
+    rom: &[u8],
+    cycles: usize,
+    config: &Config,
+) -> Result<CapturedState, CaptureError> {
+    run_scripted(rom, cycles, config, &InputScript::default())
+}
+
+/// Reproduce a run exactly: `rom` is run headlessly with the random number generator seeded with
+/// `seed` (overriding `config`), the keypad driven by `input_script`, for `max_cycles`
+/// instructions. The same arguments always give the same state.
+pub fn run_deterministic(
+    rom: &[u8],
+    config: &Config,
+    seed: u64,
+    input_script: &InputScript,
+    max_cycles: usize,
+) -> Result<CapturedState, CaptureError> {
+    let config = Config {
+        seed: Some(seed),
+        ..config.clone()
+    };
+    run_scripted(rom, max_cycles, &config, input_script)
+}
+
+fn run_scripted(
+    rom: &[u8],
+    cycles: usize,
+    config: &Config,
+    input_script: &InputScript,
+) -> Result<CapturedState, CaptureError> {
+    let mut scripted = ScriptedCpu::new(rom, config, input_script).map_err(CaptureError::Load)?;
+    for _ in 0..cycles {
+        scripted.step().map_err(CaptureError::Cpu)?;
+    }
+
+    let cpu = &scripted.cpu;
+    Ok(CapturedState {
+        registers: cpu.registers().to_vec(),
+        index: cpu.index(),
+        program_counter: cpu.program_counter(),
+        memory: cpu.memory(),
+        frame: cpu.window().frame().clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_OPCODE_ROM: &[u8] = include_bytes!("../resources/test/test_opcode.ch8");
+
+    #[test]
+    fn test_opcode_rom_passes() {
+        let state = run_rom_and_capture(TEST_OPCODE_ROM, 2000, &Config::default()).unwrap();
+
+        // The ROM ends on a self-jump once every result is drawn
+        assert_eq!(uint::<12>::new(0x3DC), state.program_counter);
+        assert_eq!(uint::<12>::new(0x202), state.index);
+        assert_eq!(
+            vec![0x01, 0x03, 0x07, 0, 0, 0x2A, 0x89, 0xEC, 0x2C, 0x30, 0x34, 0x1A, 0, 0, 0, 0],
+            state.registers
+        );
+        assert_eq!(
+            TEST_OPCODE_ROM,
+            &state.memory[0x200..][..TEST_OPCODE_ROM.len()]
+        );
+        // Every opcode reports OK
+        assert_eq!(0x8F21_6719_12C1_2851, state.frame.hash());
+    }
+
+    // Read a random number, wait for a key, then read another random number
+    const RANDOM_KEY_ROM: [u8; 8] = [
+        0xC0, 0xFF, // 200: RND V0, FF
+        0xF1, 0x0A, // 202: LD V1, K
+        0xC2, 0xFF, // 204: RND V2, FF
+        0x12, 0x06, // 206: JP 206
+    ];
+
+    #[test]
+    fn deterministic_runs_match() {
+        let script = InputScript::parse("# press 5\n10 down 5\n20 up 5\n").unwrap();
+
+        let first = run_deterministic(&RANDOM_KEY_ROM, &Config::default(), 42, &script, 100);
+        let second = run_deterministic(&RANDOM_KEY_ROM, &Config::default(), 42, &script, 100);
+
+        let first = first.unwrap();
+        assert_eq!(first, second.unwrap());
+        assert_eq!(0x5, first.registers[1]);
+        assert_eq!(uint::<12>::new(0x206), first.program_counter);
+    }
+
+    #[test]
+    fn seed_changes_deterministic_run() {
+        let script = InputScript::parse("0 down 1\n1 up 1").unwrap();
+
+        let first = run_deterministic(&RANDOM_KEY_ROM, &Config::default(), 1, &script, 10);
+        let second = run_deterministic(&RANDOM_KEY_ROM, &Config::default(), 2, &script, 10);
+
+        assert_ne!(first.unwrap().registers, second.unwrap().registers);
+    }
+
+    #[test]
+    fn reports_load_errors() {
+        let result = run_rom_and_capture(&[], 10, &Config::default());
+
+        assert!(matches!(result, Err(CaptureError::Load(LoadError::Empty))));
+    }
+}
@@ -0,0 +1,441 @@
+use arbintrary::uint;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// The instruction family selected by the high nibble of an opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeFamily {
+    System = 0x0,
+    Jump = 0x1,
+    Call = 0x2,
+    SkipEqByte = 0x3,
+    SkipNeByte = 0x4,
+    SkipEqReg = 0x5,
+    LoadByte = 0x6,
+    AddByte = 0x7,
+    Arithmetic = 0x8,
+    SkipNeReg = 0x9,
+    LoadIndex = 0xA,
+    JumpOffset = 0xB,
+    Random = 0xC,
+    Draw = 0xD,
+    Key = 0xE,
+    Misc = 0xF,
+}
+
+impl OpcodeFamily {
+    const FAMILIES: [OpcodeFamily; 16] = [
+        OpcodeFamily::System,
+        OpcodeFamily::Jump,
+        OpcodeFamily::Call,
+        OpcodeFamily::SkipEqByte,
+        OpcodeFamily::SkipNeByte,
+        OpcodeFamily::SkipEqReg,
+        OpcodeFamily::LoadByte,
+        OpcodeFamily::AddByte,
+        OpcodeFamily::Arithmetic,
+        OpcodeFamily::SkipNeReg,
+        OpcodeFamily::LoadIndex,
+        OpcodeFamily::JumpOffset,
+        OpcodeFamily::Random,
+        OpcodeFamily::Draw,
+        OpcodeFamily::Key,
+        OpcodeFamily::Misc,
+    ];
+
+    /// The family of a full 16 bit opcode.
+    pub fn from_opcode(opcode: u16) -> OpcodeFamily {
+        Self::FAMILIES[(opcode >> 12) as usize]
+    }
+
+    /// A human readable name for the family.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpcodeFamily::System => "System",
+            OpcodeFamily::Jump => "Jump",
+            OpcodeFamily::Call => "Call subroutine",
+            OpcodeFamily::SkipEqByte => "Skip if equal to byte",
+            OpcodeFamily::SkipNeByte => "Skip if not equal to byte",
+            OpcodeFamily::SkipEqReg => "Skip if registers equal",
+            OpcodeFamily::LoadByte => "Load byte",
+            OpcodeFamily::AddByte => "Add byte",
+            OpcodeFamily::Arithmetic => "Register arithmetic",
+            OpcodeFamily::SkipNeReg => "Skip if registers not equal",
+            OpcodeFamily::LoadIndex => "Load index",
+            OpcodeFamily::JumpOffset => "Jump with offset",
+            OpcodeFamily::Random => "Random",
+            OpcodeFamily::Draw => "Draw sprite",
+            OpcodeFamily::Key => "Key check",
+            OpcodeFamily::Misc => "Timers, memory and misc",
+        }
+    }
+}
+
+/// Returned when converting a value greater than 0xF into an `OpcodeFamily`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOpcodeFamily(pub u16);
+
+impl fmt::Display for InvalidOpcodeFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#X} is not an opcode family nibble", self.0)
+    }
+}
+
+impl Error for InvalidOpcodeFamily {}
+
+impl TryFrom<u16> for OpcodeFamily {
+    type Error = InvalidOpcodeFamily;
+
+    /// Convert a high nibble (0x0-0xF) into its family.
+    fn try_from(nibble: u16) -> Result<Self, Self::Error> {
+        Self::FAMILIES
+            .get(nibble as usize)
+            .copied()
+            .ok_or(InvalidOpcodeFamily(nibble))
+    }
+}
+
+/// A single decoded CHIP-8 instruction. Register operands are stored as their index (0x0-0xF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN: Call machine code routine
+    Sys(uint<12>),
+    /// 00E0: Clear the screen
+    Cls,
+    /// 00EE: Return from subroutine
+    Ret,
+    /// 1NNN: Jump to address
+    Jump(uint<12>),
+    /// 2NNN: Call subroutine
+    Call(uint<12>),
+    /// 3XNN: Skip if VX == NN
+    SkipEqByte(u8, u8),
+    /// 4XNN: Skip if VX != NN
+    SkipNeByte(u8, u8),
+    /// 5XY0: Skip if VX == VY
+    SkipEqReg(u8, u8),
+    /// 6XNN: VX = NN
+    LoadByte(u8, u8),
+    /// 7XNN: VX += NN
+    AddByte(u8, u8),
+    /// 8XY0: VX = VY
+    LoadReg(u8, u8),
+    /// 8XY1: VX |= VY
+    Or(u8, u8),
+    /// 8XY2: VX &= VY
+    And(u8, u8),
+    /// 8XY3: VX ^= VY
+    Xor(u8, u8),
+    /// 8XY4: VX += VY with carry
+    AddReg(u8, u8),
+    /// 8XY5: VX -= VY with borrow
+    SubReg(u8, u8),
+    /// 8XY6: VX >>= 1
+    ShiftRight(u8, u8),
+    /// 8XY7: VX = VY - VX with borrow
+    SubNReg(u8, u8),
+    /// 8XYE: VX <<= 1
+    ShiftLeft(u8, u8),
+    /// 9XY0: Skip if VX != VY
+    SkipNeReg(u8, u8),
+    /// ANNN: I = NNN
+    LoadIndex(uint<12>),
+    /// BNNN: Jump to NNN + V0
+    JumpV0(uint<12>),
+    /// CXNN: VX = random & NN
+    Random(u8, u8),
+    /// DXYN: Draw an N byte sprite at (VX, VY)
+    Draw(u8, u8, u8),
+    /// EX9E: Skip if key VX is pressed
+    SkipKeyPressed(u8),
+    /// EXA1: Skip if key VX is not pressed
+    SkipKeyNotPressed(u8),
+    /// FX07: VX = delay timer
+    LoadDelay(u8),
+    /// FX0A: Wait for a key press and store it in VX
+    WaitKey(u8),
+    /// FX15: delay timer = VX
+    SetDelay(u8),
+    /// FX18: sound timer = VX
+    SetSound(u8),
+    /// FX1E: I += VX
+    AddIndex(u8),
+    /// FX29: I = font sprite for VX
+    LoadFont(u8),
+    /// FX33: Store the BCD of VX at I
+    StoreBcd(u8),
+    /// FX55: Store V0..=VX at I
+    StoreRegisters(u8),
+    /// FX65: Load V0..=VX from I
+    LoadRegisters(u8),
+    /// F000 NNNN: I = NNNN (XO-CHIP). The only instruction that is two words long.
+    LoadLongI(u16),
+    /// Any word that isn't a documented instruction
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = uint::<12>::new(opcode & 0x0FFF);
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                _ => Instruction::Sys(nnn),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipEqByte(x, nn),
+            0x4 => Instruction::SkipNeByte(x, nn),
+            0x5 if n == 0x0 => Instruction::SkipEqReg(x, y),
+            0x6 => Instruction::LoadByte(x, nn),
+            0x7 => Instruction::AddByte(x, nn),
+            0x8 => match n {
+                0x0 => Instruction::LoadReg(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddReg(x, y),
+                0x5 => Instruction::SubReg(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubNReg(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9 if n == 0x0 => Instruction::SkipNeReg(x, y),
+            0xA => Instruction::LoadIndex(nnn),
+            0xB => Instruction::JumpV0(nnn),
+            0xC => Instruction::Random(x, nn),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match nn {
+                0x9E => Instruction::SkipKeyPressed(x),
+                0xA1 => Instruction::SkipKeyNotPressed(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF => match nn {
+                0x07 => Instruction::LoadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddIndex(x),
+                0x29 => Instruction::LoadFont(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    /// Decode the instruction at the start of `bytes`, along with its length in bytes. Unlike
+    /// `decode` this understands two-word instructions. Return None if there isn't a whole word.
+    pub fn decode_bytes(bytes: &[u8]) -> Option<(Instruction, usize)> {
+        let opcode = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+        if opcode == 0xF000 {
+            if let Some(address) = bytes.get(2..4) {
+                return Some((
+                    Instruction::LoadLongI(u16::from_be_bytes([address[0], address[1]])),
+                    4,
+                ));
+            }
+        }
+        Some((Instruction::decode(opcode), 2))
+    }
+
+    /// The opcode pattern of this instruction, e.g. "8XY4". Useful as a key when grouping.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipEqByte(..) => "3XNN",
+            Instruction::SkipNeByte(..) => "4XNN",
+            Instruction::SkipEqReg(..) => "5XY0",
+            Instruction::LoadByte(..) => "6XNN",
+            Instruction::AddByte(..) => "7XNN",
+            Instruction::LoadReg(..) => "8XY0",
+            Instruction::Or(..) => "8XY1",
+            Instruction::And(..) => "8XY2",
+            Instruction::Xor(..) => "8XY3",
+            Instruction::AddReg(..) => "8XY4",
+            Instruction::SubReg(..) => "8XY5",
+            Instruction::ShiftRight(..) => "8XY6",
+            Instruction::SubNReg(..) => "8XY7",
+            Instruction::ShiftLeft(..) => "8XYE",
+            Instruction::SkipNeReg(..) => "9XY0",
+            Instruction::LoadIndex(_) => "ANNN",
+            Instruction::JumpV0(_) => "BNNN",
+            Instruction::Random(..) => "CXNN",
+            Instruction::Draw(..) => "DXYN",
+            Instruction::SkipKeyPressed(_) => "EX9E",
+            Instruction::SkipKeyNotPressed(_) => "EXA1",
+            Instruction::LoadDelay(_) => "FX07",
+            Instruction::WaitKey(_) => "FX0A",
+            Instruction::SetDelay(_) => "FX15",
+            Instruction::SetSound(_) => "FX18",
+            Instruction::AddIndex(_) => "FX1E",
+            Instruction::LoadFont(_) => "FX29",
+            Instruction::StoreBcd(_) => "FX33",
+            Instruction::StoreRegisters(_) => "FX55",
+            Instruction::LoadRegisters(_) => "FX65",
+            Instruction::LoadLongI(_) => "F000",
+            Instruction::Unknown(_) => "????",
+        }
+    }
+}
+
+/// The instruction in assembly form, with the usual CHIP-8 mnemonics, e.g. `LD V4, 0x50`. Words
+/// that aren't instructions are shown as data, `DW 0xNNNN`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(address) => write!(f, "SYS 0x{:03X}", u16::from(address)),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jump(address) => write!(f, "JP 0x{:03X}", u16::from(address)),
+            Instruction::Call(address) => write!(f, "CALL 0x{:03X}", u16::from(address)),
+            Instruction::SkipEqByte(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNeByte(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::LoadReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubReg(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubNReg(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex(address) => write!(f, "LD I, 0x{:03X}", u16::from(address)),
+            Instruction::JumpV0(address) => write!(f, "JP V0, 0x{:03X}", u16::from(address)),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKeyPressed(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipKeyNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LoadLongI(address) => write!(f, "LD I, 0x{:04X}", address),
+            Instruction::Unknown(word) => write!(f, "DW 0x{:04X}", word),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_each_nibble_to_its_family() {
+        let expected = [
+            (0x0, OpcodeFamily::System),
+            (0x1, OpcodeFamily::Jump),
+            (0x2, OpcodeFamily::Call),
+            (0x3, OpcodeFamily::SkipEqByte),
+            (0x4, OpcodeFamily::SkipNeByte),
+            (0x5, OpcodeFamily::SkipEqReg),
+            (0x6, OpcodeFamily::LoadByte),
+            (0x7, OpcodeFamily::AddByte),
+            (0x8, OpcodeFamily::Arithmetic),
+            (0x9, OpcodeFamily::SkipNeReg),
+            (0xA, OpcodeFamily::LoadIndex),
+            (0xB, OpcodeFamily::JumpOffset),
+            (0xC, OpcodeFamily::Random),
+            (0xD, OpcodeFamily::Draw),
+            (0xE, OpcodeFamily::Key),
+            (0xF, OpcodeFamily::Misc),
+        ];
+
+        for (nibble, family) in expected.iter() {
+            assert_eq!(Ok(*family), OpcodeFamily::try_from(*nibble));
+            assert_eq!(*nibble, *family as u16);
+            assert_eq!(*family, OpcodeFamily::from_opcode((*nibble << 12) | 0x123));
+        }
+    }
+
+    #[test]
+    fn rejects_values_above_a_nibble() {
+        assert_eq!(Err(InvalidOpcodeFamily(0x10)), OpcodeFamily::try_from(0x10));
+    }
+
+    #[test]
+    fn family_has_a_name() {
+        assert_eq!("Draw sprite", OpcodeFamily::from_opcode(0xD123).name());
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(
+            Instruction::Draw(0x3, 0x2, 0x1),
+            Instruction::decode(0xD321)
+        );
+        assert_eq!(
+            Instruction::LoadByte(0x4, 0x50),
+            Instruction::decode(0x6450)
+        );
+        assert_eq!(
+            Instruction::Jump(uint::<12>::new(0x24E)),
+            Instruction::decode(0x124E)
+        );
+    }
+
+    #[test]
+    fn decodes_system_instructions() {
+        assert_eq!(Instruction::Cls, Instruction::decode(0x00E0));
+        assert_eq!(Instruction::Ret, Instruction::decode(0x00EE));
+        assert_eq!(
+            Instruction::Sys(uint::<12>::new(0x123)),
+            Instruction::decode(0x0123)
+        );
+    }
+
+    #[test]
+    fn decodes_undocumented_words_as_unknown() {
+        assert_eq!(Instruction::Unknown(0x5121), Instruction::decode(0x5121));
+        assert_eq!(Instruction::Unknown(0x8148), Instruction::decode(0x8148));
+        assert_eq!(Instruction::Unknown(0xE1FF), Instruction::decode(0xE1FF));
+        assert_eq!(Instruction::Unknown(0xF1FF), Instruction::decode(0xF1FF));
+    }
+
+    #[test]
+    fn decodes_long_load_as_two_words() {
+        assert_eq!(
+            Some((Instruction::LoadLongI(0x1234), 4)),
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12, 0x34, 0x60, 0x01])
+        );
+        assert_eq!(
+            Some((Instruction::LoadByte(0x0, 0x01), 2)),
+            Instruction::decode_bytes(&[0x60, 0x01, 0xF0, 0x00])
+        );
+        // A long load cut short by the end of the ROM is just an unknown word
+        assert_eq!(
+            Some((Instruction::Unknown(0xF000), 2)),
+            Instruction::decode_bytes(&[0xF0, 0x00, 0x12])
+        );
+        assert_eq!(None, Instruction::decode_bytes(&[0xF0]));
+    }
+
+    #[test]
+    fn pattern_names_the_opcode() {
+        assert_eq!("8XY4", Instruction::decode(0x8144).pattern());
+        assert_eq!("FX65", Instruction::decode(0xF265).pattern());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::framebuffer::FrameBuffer;
+use crate::window::{Hotkey, Window};
+
+/// Called with the CHIP-8 key that changed state.
+pub type KeyCallback = Box<dyn FnMut(u8)>;
+
+/// Wraps a window to report keypad presses and releases as events instead of only by polling.
+///
+/// Keys are sampled each time the window renders, i.e. at 60hz, and a callback runs for every key
+/// whose state changed since the last sample. A key pressed and released between two samples is
+/// missed.
+pub struct KeyEventWindow {
+    window: Box<dyn Window>,
+    // Bit N is set while key N was held at the last sample
+    held: u16,
+    on_key_down: Option<KeyCallback>,
+    on_key_up: Option<KeyCallback>,
+}
+
+impl KeyEventWindow {
+    pub fn new(window: Box<dyn Window>) -> KeyEventWindow {
+        KeyEventWindow {
+            window,
+            held: 0,
+            on_key_down: None,
+            on_key_up: None,
+        }
+    }
+
+    /// Run `callback` whenever a key goes down. Replaces any callback already set.
+    pub fn set_on_key_down(&mut self, callback: KeyCallback) {
+        self.on_key_down = Some(callback);
+    }
+
+    /// Run `callback` whenever a key comes up. Replaces any callback already set.
+    pub fn set_on_key_up(&mut self, callback: KeyCallback) {
+        self.on_key_up = Some(callback);
+    }
+
+    fn poll_keys(&mut self) {
+        let held = (0..=0xF)
+            .filter(|key| self.window.is_key_pressed(*key))
+            .fold(0u16, |held, key| held | (1 << key));
+        let changed = held ^ self.held;
+        self.held = held;
+
+        for key in (0..=0xF).filter(|key| changed & (1 << key) != 0) {
+            let callback = if held & (1 << key) != 0 {
+                &mut self.on_key_down
+            } else {
+                &mut self.on_key_up
+            };
+            if let Some(callback) = callback {
+                callback(key);
+            }
+        }
+    }
+}
+
+impl Window for KeyEventWindow {
+    fn blank_screen(&mut self) {
+        self.window.blank_screen();
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        self.window.draw(x, y, width, sprite)
+    }
+
+    fn render(&mut self) {
+        self.window.render();
+        self.poll_keys();
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn present(&mut self) {
+        self.window.present();
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.window.is_key_pressed(key)
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        self.window.get_pressed_key()
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        self.window.pressed_hotkey()
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.window.frame_hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        self.window.frame()
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        self.window.screenshot()
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.window.export_sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        self.window.clipped_draws()
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.window.scroll_up(n);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.window.scroll_down(n);
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.window.scroll_left(n);
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.window.scroll_right(n);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8) {
+        self.window.set_clip(x, y, width, height);
+    }
+
+    fn clear_clip(&mut self) {
+        self.window.clear_clip();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.window.set_wrap(wrap);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.window.set_hires(hires);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.window.set_planes(planes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::HeadlessWindow;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn reports_presses_and_releases() {
+        let headless = HeadlessWindow::new();
+        let keys = headless.keys();
+        let mut window = KeyEventWindow::new(Box::new(headless));
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let down_events = Rc::clone(&events);
+        window.set_on_key_down(Box::new(move |key| {
+            down_events.borrow_mut().push((key, true))
+        }));
+        let up_events = Rc::clone(&events);
+        window.set_on_key_up(Box::new(move |key| {
+            up_events.borrow_mut().push((key, false))
+        }));
+
+        keys.store(1 << 0x5, Ordering::Relaxed);
+        window.render();
+        window.render();
+        keys.store(1 << 0xA, Ordering::Relaxed);
+        window.render();
+        keys.store(0, Ordering::Relaxed);
+        window.render();
+
+        assert_eq!(
+            vec![(0x5, true), (0x5, false), (0xA, true), (0xA, false)],
+            *events.borrow()
+        );
+    }
+}
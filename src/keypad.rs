@@ -0,0 +1,194 @@
+use clap::ValueEnum;
+use minifb::Key;
+
+/// Which keyboard keys stand in for the hex keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum KeypadLayout {
+    /// The 4x4 block 1234/QWER/ASDF/ZXCV, shaped like the COSMAC VIP keypad
+    #[default]
+    Classic,
+    /// The key with the same label: 0-9 on the number row and A-F on the letters
+    Hex,
+    /// The numeric keypad for 0-9, with / * - + Enter . for A-F
+    Numpad,
+}
+
+impl KeypadLayout {
+    /// The physical key for each CHIP-8 key, in order.
+    pub fn keys(self) -> [Key; 16] {
+        match self {
+            KeypadLayout::Classic => Keypad::CLASSIC,
+            KeypadLayout::Hex => Keypad::HEX,
+            KeypadLayout::Numpad => Keypad::NUMPAD,
+        }
+    }
+}
+
+/// Parse a key event, `down <key>` or `up <key>` where key is a hex digit, as sent for remote
+/// input and used in input scripts. Return the key and whether it is now held.
+pub(crate) fn parse_key_event(line: &str) -> Option<(u8, bool)> {
+    let mut words = line.split_whitespace();
+    let is_down = match words.next()? {
+        "down" => true,
+        "up" => false,
+        _ => return None,
+    };
+    let key = u8::from_str_radix(words.next()?, 16).ok()?;
+    if key > 0xF || words.next().is_some() {
+        return None;
+    }
+    Some((key, is_down))
+}
+
+/// Maps the 16 CHIP-8 keys to physical keyboard keys and back in constant time.
+#[derive(Debug, Clone)]
+pub struct Keypad {
+    physical_keys: [Key; 16],
+    chip8_keys: [Option<u8>; Key::Count as usize],
+}
+
+impl Keypad {
+    // The classic layout, mapping the 4x4 hex keypad onto 1234/QWER/ASDF/ZXCV
+    const CLASSIC: [Key; 16] = [
+        Key::X,    // 0
+        Key::Key1, // 1
+        Key::Key2, // 2
+        Key::Key3, // 3
+        Key::Q,    // 4
+        Key::W,    // 5
+        Key::E,    // 6
+        Key::A,    // 7
+        Key::S,    // 8
+        Key::D,    // 9
+        Key::Z,    // A
+        Key::C,    // B
+        Key::Key4, // C
+        Key::R,    // D
+        Key::F,    // E
+        Key::V,    // F
+    ];
+
+    const HEX: [Key; 16] = [
+        Key::Key0,
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+    ];
+
+    const NUMPAD: [Key; 16] = [
+        Key::NumPad0,
+        Key::NumPad1,
+        Key::NumPad2,
+        Key::NumPad3,
+        Key::NumPad4,
+        Key::NumPad5,
+        Key::NumPad6,
+        Key::NumPad7,
+        Key::NumPad8,
+        Key::NumPad9,
+        Key::NumPadSlash,    // A
+        Key::NumPadAsterisk, // B
+        Key::NumPadMinus,    // C
+        Key::NumPadPlus,     // D
+        Key::NumPadEnter,    // E
+        Key::NumPadDot,      // F
+    ];
+
+    /// Create a keypad where `physical_keys[n]` is the keyboard key for CHIP-8 key `n`.
+    pub fn new(physical_keys: [Key; 16]) -> Keypad {
+        let mut chip8_keys = [None; Key::Count as usize];
+        for (chip8_key, physical_key) in physical_keys.iter().enumerate() {
+            chip8_keys[*physical_key as usize] = Some(chip8_key as u8);
+        }
+        Keypad {
+            physical_keys,
+            chip8_keys,
+        }
+    }
+
+    pub fn chip8_to_physical(&self, key: u8) -> Key {
+        self.physical_keys[key as usize]
+    }
+
+    pub fn physical_to_chip8(&self, key: Key) -> Option<u8> {
+        self.chip8_keys.get(key as usize).copied().flatten()
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Keypad {
+        Keypad::new(Self::CLASSIC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[test]
+    fn maps_chip8_to_physical() {
+        let keypad = Keypad::default();
+        assert_eq!(Key::X, keypad.chip8_to_physical(0x0));
+        assert_eq!(Key::V, keypad.chip8_to_physical(0xF));
+    }
+
+    #[test]
+    fn maps_physical_to_chip8() {
+        let keypad = Keypad::default();
+        assert_eq!(Some(0x4), keypad.physical_to_chip8(Key::Q));
+        assert_eq!(None, keypad.physical_to_chip8(Key::P));
+    }
+
+    #[test]
+    fn parses_key_events() {
+        assert_eq!(Some((0xA, true)), parse_key_event("down a"));
+        assert_eq!(Some((0x3, false)), parse_key_event("up 3\r"));
+        assert_eq!(None, parse_key_event("down 10"));
+        assert_eq!(None, parse_key_event("press 1"));
+        assert_eq!(None, parse_key_event("down"));
+    }
+
+    #[rstest]
+    #[case(KeypadLayout::Classic)]
+    #[case(KeypadLayout::Hex)]
+    #[case(KeypadLayout::Numpad)]
+    fn round_trips_every_key(#[case] layout: KeypadLayout) {
+        let keypad = Keypad::new(layout.keys());
+        for key in 0..16 {
+            assert_eq!(
+                Some(key),
+                keypad.physical_to_chip8(keypad.chip8_to_physical(key))
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(KeypadLayout::Classic, 0x0, Key::X)]
+    #[case(KeypadLayout::Classic, 0xC, Key::Key4)]
+    #[case(KeypadLayout::Hex, 0x0, Key::Key0)]
+    #[case(KeypadLayout::Hex, 0x7, Key::Key7)]
+    #[case(KeypadLayout::Hex, 0xA, Key::A)]
+    #[case(KeypadLayout::Hex, 0xF, Key::F)]
+    #[case(KeypadLayout::Numpad, 0x5, Key::NumPad5)]
+    #[case(KeypadLayout::Numpad, 0xA, Key::NumPadSlash)]
+    #[case(KeypadLayout::Numpad, 0xF, Key::NumPadDot)]
+    fn layouts_map_keys(#[case] layout: KeypadLayout, #[case] chip8: u8, #[case] physical: Key) {
+        let keypad = Keypad::new(layout.keys());
+
+        assert_eq!(physical, keypad.chip8_to_physical(chip8));
+        assert_eq!(Some(chip8), keypad.physical_to_chip8(physical));
+    }
+}
@@ -1,31 +1,609 @@
 mod audio;
+mod capabilities;
+mod capture;
+mod config;
 mod cpu;
+mod debugger;
+mod diff;
+mod disasm;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+mod framebuffer;
+#[cfg(any(test, feature = "test-support"))]
+mod harness;
+mod instruction;
+mod key_events;
+mod keypad;
+mod machine;
+mod metrics;
 mod mmu;
+mod platform;
+mod quirks;
+#[cfg(unix)]
+mod remote;
+mod scan;
+mod script;
+mod state;
+mod theme;
 mod window;
 
-use mmu::Mmu;
+pub use audio::{Audio, AudioConfig, Chip8Audio, NullAudio, Waveform};
+pub use capabilities::{capabilities, Capabilities};
+pub use capture::{load_sprite, save_screenshot, save_sprite};
+pub use config::{cycles_per_frame, Config, FrequencySchedule};
+pub use cpu::{
+    last_instruction, CoordinateMode, Cpu, CpuError, InstructionCallback, LastInstruction,
+    MemoryOverflow, OnError, OpcodeHook, RunResult, SpriteReads, Stats, UnknownOpcodes,
+};
+pub use debugger::Debugger;
+pub use diff::{first_divergence, Divergence};
+pub use disasm::{cross_references, disassemble, listing, Xrefs};
+pub use framebuffer::FrameBuffer;
+#[cfg(any(test, feature = "test-support"))]
+pub use harness::{run_deterministic, run_rom_and_capture, CaptureError, CapturedState};
+pub use instruction::{Instruction, InvalidOpcodeFamily, OpcodeFamily};
+pub use key_events::{KeyCallback, KeyEventWindow};
+pub use keypad::{Keypad, KeypadLayout};
+pub use machine::Chip8;
+pub use metrics::Metrics;
+pub use mmu::{Chip8Mmu, LoadError, LoadStatus, Mmu};
+pub use platform::Platform;
+pub use quirks::{QuirkOverrides, Quirks};
+#[cfg(unix)]
+pub use remote::RemoteInputWindow;
+pub use script::InputScript;
+pub use state::{MachineState, StateError};
+pub use theme::{Palette, Theme};
+pub use window::{DisplayColors, HeadlessWindow, Hotkey, PixelAspect, Window, WindowScale};
+#[cfg(any(test, feature = "test-support"))]
+pub use window::{RecordingWindow, WindowCall};
+
+use clap::ValueEnum;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
 use tokio::time::{self, Duration, Instant};
 
-pub async fn run(frequency: u32, file_path: &str) {
+/// The version of this crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How many output pixels wide and high each logical pixel is in an F12 screenshot.
+const SCREENSHOT_SCALE: usize = 8;
+
+/// Load and run a ROM until the window is closed. A `file_path` of `-` reads the ROM from stdin.
+/// `on_load` is told how the ROM was loaded, before it starts running.
+pub async fn run(
+    config: Config,
+    file_path: &str,
+    on_load: impl FnOnce(LoadStatus),
+) -> Result<(), Box<dyn Error>> {
     let duration_60hz: Duration = Duration::from_secs_f64(1f64 / 60f64);
 
-    let mut mmu = Box::new(mmu::Chip8Mmu::new());
-    mmu.load_program(file_path).unwrap();
-    let window = Box::new(window::MiniFbWindow::new());
-    let audio = Box::new(audio::Chip8Audio::new().expect("Failed to initialize audio"));
+    let rom = read_rom(file_path)?;
+    let window =
+        match window::MiniFbWindow::new(config.display_colors(), config.pixel_aspect, config.scale)
+        {
+            Ok(mut window) => {
+                window.set_present_limit(config.max_presents_per_frame);
+                window.set_double_buffered(config.double_buffer);
+                window.set_keypad(Keypad::new(config.keypad_layout.keys()));
+                if let Some(dir) = &config.frames_dir {
+                    window.set_frame_recorder(capture::FrameRecorder::new(
+                        dir,
+                        config.display_colors(),
+                    )?);
+                }
+                Ok(Box::new(window) as Box<dyn Window>)
+            }
+            Err(error) => Err(error),
+        };
+    let window = or_headless(window, &config)?;
+    let window = with_remote_input(window, &config)?;
+    let mut audio = or_silent(audio::Chip8Audio::new(config.audio));
+    audio.set_volume(config.volume);
 
-    let mut cpu = cpu::Cpu::new(mmu, window, audio);
+    let mut chip8 = Chip8::new(config.clone(), window, audio);
+    on_load(chip8.load(&rom)?);
+    if let Some(path) = &config.flags_file {
+        chip8.cpu_mut().set_flags(read_flags(path)?);
+    }
+    if config.dump_state_on_panic {
+        chip8.cpu_mut().set_record_last_instruction(true);
+        install_state_dump();
+    }
+
+    let rom_name = match file_path {
+        "-" => "stdin".to_string(),
+        path => Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy().into_owned()),
+    };
+    let metrics = match config.metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(Metrics::default());
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            tokio::spawn(metrics::serve(listener, metrics.clone()));
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut rate_start = Instant::now();
+    let mut rate_instructions: u64 = 0;
+    let mut rate_frames: u64 = 0;
+    let mut smoothed_ips = metrics::Ema::new(config.rate_smoothing);
+    let mut smoothed_fps = metrics::Ema::new(config.rate_smoothing);
 
-    let mut last_60hz_tick = Instant::now();
-    let mut interval = time::interval(Duration::from_secs_f64(1f64 / (frequency as f64)));
+    let mut interval = time::interval(duration_60hz);
+    let mut frame: u64 = 0;
+    let mut clipped_draws: u64 = 0;
+    let mut halted_frames: u32 = 0;
+    let mut saved_flags = chip8.cpu().flags();
+    let mut muted = false;
     loop {
         let now = interval.tick().await;
+        let instructions = chip8.cpu().stats().instructions;
+        match chip8.run_frame() {
+            Ok(()) => {}
+            Err(error)
+                if config.on_error == OnError::Continue
+                    && !matches!(error, CpuError::Halt { .. }) =>
+            {
+                eprintln!("{}; skipping it", error);
+                chip8.cpu_mut().skip_instruction();
+            }
+            Err(error) => return halted_cleanly(error),
+        }
+        if let Some(path) = &config.flags_file {
+            let flags = chip8.cpu().flags();
+            if flags != saved_flags {
+                fs::write(path, flags)?;
+                saved_flags = flags;
+            }
+        }
+        if !chip8.cpu().window().is_open() {
+            return Ok(());
+        }
+
+        match chip8.cpu().window().pressed_hotkey() {
+            Some(Hotkey::SoftReset) => chip8.cpu_mut().reset(),
+            Some(Hotkey::HardReset) => chip8.reset(),
+            Some(Hotkey::Mute) => {
+                muted = !muted;
+                let volume = if muted { 0.0 } else { config.volume };
+                chip8.cpu_mut().audio_mut().set_volume(volume);
+            }
+            Some(Hotkey::Screenshot) => {
+                match capture::save_screenshot(
+                    Path::new("."),
+                    chip8.cpu().window().frame(),
+                    &config.display_colors(),
+                    SCREENSHOT_SCALE,
+                ) {
+                    Ok(path) => eprintln!("Saved screenshot to {}", path.display()),
+                    Err(err) => eprintln!("Failed to save screenshot: {}", err),
+                }
+            }
+            None => (),
+        }
+
+        if let Some(loop_delay) = config.loop_delay {
+            if !chip8.cpu().is_halted() {
+                halted_frames = 0;
+            } else if halted_frames as f64 / 60f64 >= loop_delay {
+                chip8.cpu_mut().reset();
+                halted_frames = 0;
+            } else {
+                halted_frames += 1;
+            }
+        }
+
+        let stats = chip8.cpu().stats();
+        if config.log_ipf {
+            eprintln!(
+                "frame {}: {} instructions",
+                frame,
+                stats.instructions - instructions
+            );
+        }
+        if config.log_clipped_draws {
+            let total = chip8.cpu().window().clipped_draws();
+            if total > clipped_draws {
+                eprintln!(
+                    "frame {}: {} draws clipped at the screen edge",
+                    frame,
+                    total - clipped_draws
+                );
+                clipped_draws = total;
+            }
+        }
+        frame += 1;
+
+        if let Some(metrics) = &metrics {
+            metrics.update(stats, frame);
+        }
+        let elapsed = (now - rate_start).as_secs_f64();
+        if elapsed >= 1.0 {
+            let ips =
+                smoothed_ips.update((stats.instructions - rate_instructions) as f64 / elapsed);
+            let fps = smoothed_fps.update((frame - rate_frames) as f64 / elapsed);
+            if let Some(metrics) = &metrics {
+                metrics.update_rates(ips, fps);
+            }
+            if config.title_stats {
+                let title = stats_title(&rom_name, ips, fps, chip8.cpu().program_counter());
+                chip8.cpu_mut().window_mut().set_title(&title);
+            }
+            rate_start = now;
+            rate_instructions = stats.instructions;
+            rate_frames = frame;
+        }
+    }
+}
+
+/// Run a ROM headlessly, as fast as possible, for `seconds` of wall-clock time and print how many
+/// instructions were executed. Frames are paced as `Chip8::run_frame` paces them, just without
+/// waiting between them, so the ROM behaves as it would at normal speed. `on_load` is told how the
+/// ROM was loaded, as with `run`.
+pub fn benchmark(
+    config: Config,
+    file_path: &str,
+    seconds: f64,
+    on_load: impl FnOnce(LoadStatus),
+) -> Result<(), Box<dyn Error>> {
+    let mut window = HeadlessWindow::new();
+    window.set_present_limit(config.max_presents_per_frame);
+    window.set_double_buffered(config.double_buffer);
+    let mut chip8 = Chip8::new(config, Box::new(window), Box::new(NullAudio));
+    on_load(chip8.load_file(file_path)?);
+
+    let duration = Duration::from_secs_f64(seconds);
+    let start = std::time::Instant::now();
+    while start.elapsed() < duration {
+        if let Err(error) = chip8.run_frame() {
+            halted_cleanly(error)?;
+            break;
+        }
+    }
+    let instructions = chip8.cpu().stats().instructions;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("{} instructions in {:.2}s", instructions, elapsed);
+    println!("{:.0} instructions/s", instructions as f64 / elapsed);
+    Ok(())
+}
+
+/// Print the last instruction the CPU started after the usual panic message.
+fn install_state_dump() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        match cpu::last_instruction() {
+            Some(last) => eprintln!("CPU state: {}", last),
+            None => eprintln!("CPU state: no instruction had run"),
+        }
+    }));
+}
+
+/// Ok if the CPU stopped because the ROM halted, otherwise the error.
+fn halted_cleanly(error: CpuError) -> Result<(), Box<dyn Error>> {
+    match error {
+        CpuError::Halt { .. } => {
+            eprintln!("{}", error);
+            Ok(())
+        }
+        error => Err(error.into()),
+    }
+}
+
+/// A window title showing the ROM and how fast it is running.
+fn stats_title(
+    rom_name: &str,
+    ips: f64,
+    fps: f64,
+    program_counter: arbintrary::uint<12>,
+) -> String {
+    format!(
+        "Chip8 - {} - {:.0} IPS - {:.1} FPS - PC=0x{:03X}",
+        rom_name,
+        ips,
+        fps,
+        u16::from(program_counter)
+    )
+}
+
+/// The flag registers saved in `path`, or all zero if there is no such file yet. A short file
+/// fills the first registers only.
+fn read_flags(path: &Path) -> io::Result<[u8; cpu::Cpu::FLAG_COUNT]> {
+    let mut flags = [0; cpu::Cpu::FLAG_COUNT];
+    match fs::read(path) {
+        Ok(saved) => {
+            let length = saved.len().min(flags.len());
+            flags[..length].copy_from_slice(&saved[..length]);
+            Ok(flags)
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(flags),
+        Err(error) => Err(error),
+    }
+}
+
+fn read_rom(file_path: &str) -> io::Result<Vec<u8>> {
+    if file_path == "-" {
+        let mut rom = Vec::new();
+        io::stdin().read_to_end(&mut rom)?;
+        Ok(rom)
+    } else {
+        fs::read(file_path)
+    }
+}
+
+fn apply_pokes(mmu: &mut dyn Mmu, config: &Config) {
+    for (address, byte) in &config.pokes {
+        mmu.write_u8(*address, *byte);
+    }
+}
+
+/// The platform given in the config, otherwise the one the ROM's opcodes point to, if any.
+fn platform_for(config: &Config, rom: &[u8]) -> Option<Platform> {
+    if config.platform.is_some() {
+        return config.platform;
+    }
+    let platform = scan::detect_platform(rom)?;
+    let name = platform.to_possible_value().unwrap();
+    eprintln!(
+        "Detected {} opcodes, pass --platform to override",
+        name.get_name()
+    );
+    Some(platform)
+}
+
+/// The config's quirks, otherwise those of `platform`, or the defaults with no platform, with the
+/// config's overrides applied on top.
+fn quirks_for(config: &Config, platform: Option<Platform>) -> Quirks {
+    let quirks = config
+        .quirks
+        .or_else(|| platform.map(Quirks::for_platform))
+        .unwrap_or_default();
+    config.quirk_overrides.apply(quirks)
+}
 
-        if (now - last_60hz_tick) >= duration_60hz {
-            last_60hz_tick += duration_60hz;
-            cpu.run_60hz_cycle();
+/// The window if it opened, otherwise a headless one if the config allows falling back.
+fn or_headless(
+    window: Result<Box<dyn Window>, minifb::Error>,
+    config: &Config,
+) -> Result<Box<dyn Window>, Box<dyn Error>> {
+    match window {
+        Ok(window) => Ok(window),
+        Err(error) if config.window_fallback => {
+            eprintln!("Unable to open a window ({}), running without one", error);
+            let mut window = HeadlessWindow::new();
+            window.set_present_limit(config.max_presents_per_frame);
+            window.set_double_buffered(config.double_buffer);
+            Ok(Box::new(window))
         }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Fall back to running without sound if audio couldn't be opened.
+fn or_silent(audio: Result<audio::Chip8Audio, Box<dyn Error>>) -> Box<dyn Audio> {
+    match audio {
+        Ok(audio) => Box::new(audio),
+        Err(error) => {
+            eprintln!("Unable to open audio ({}), running without sound", error);
+            Box::new(NullAudio)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn with_remote_input(
+    window: Box<dyn Window>,
+    config: &Config,
+) -> Result<Box<dyn Window>, Box<dyn Error>> {
+    match &config.remote_input {
+        Some(path) => Ok(Box::new(RemoteInputWindow::new(window, path)?)),
+        None => Ok(window),
+    }
+}
+
+#[cfg(not(unix))]
+fn with_remote_input(
+    window: Box<dyn Window>,
+    config: &Config,
+) -> Result<Box<dyn Window>, Box<dyn Error>> {
+    match &config.remote_input {
+        Some(_) => Err("--remote-input needs Unix domain sockets".into()),
+        None => Ok(window),
+    }
+}
+
+fn configure_cpu(cpu: &mut cpu::Cpu, config: &Config, rom: &[u8]) {
+    let platform = platform_for(config, rom);
+    cpu.set_platform(platform.unwrap_or_default());
+    cpu.set_memory_overflow(config.memory_overflow);
+    cpu.set_ignore_clear(config.ignore_clear);
+    // Looping replays a halted program, so it has to keep running through the self-jump
+    cpu.set_halt_on_self_jump(config.halt_on_infinite_loop && config.loop_delay.is_none());
+    cpu.set_unknown_opcodes(config.unknown_opcodes);
+    let quirks = quirks_for(config, platform);
+    cpu.set_quirks(quirks);
+    // Sprites are clipped or wrapped by the window, so it takes the quirk from here and only here
+    cpu.window_mut().set_wrap(!quirks.display_clipping);
+    cpu.set_coordinate_mode(config.coordinate_mode);
+    cpu.set_sprite_reads(config.sprite_reads);
+    cpu.set_draw_cycles(config.draw_cycles);
+    if let Some(seed) = config.seed {
+        cpu.set_seed(seed);
+    }
+    if config.randomize_startup {
+        cpu.randomize_startup();
+    }
+}
+
+/// Run a ROM headlessly under `config` and `other` in lockstep for up to `cycles` instructions and
+/// print the first point at which their states differ. Both runs use `config`'s seed, or 0.
+pub fn compare(
+    config: &Config,
+    other: &Config,
+    file_path: &str,
+    input_script: &InputScript,
+    cycles: usize,
+) -> Result<(), Box<dyn Error>> {
+    let rom = read_rom(file_path)?;
+    let seed = config.seed.unwrap_or(0);
+    match diff::first_divergence(&rom, config, other, seed, input_script, cycles)? {
+        Some(divergence) => println!("{}", divergence),
+        None => println!("no divergence in {} cycles", cycles),
+    }
+    Ok(())
+}
+
+/// Print a disassembly listing of the ROM at `file_path`, or from stdin if it is `-`, without
+/// executing anything.
+pub fn print_listing(file_path: &str) -> Result<(), Box<dyn Error>> {
+    print!("{}", listing(&read_rom(file_path)?));
+    Ok(())
+}
+
+/// Print how many of each instruction the straight-line path through the ROM at `file_path`, or
+/// from stdin if it is `-`, contains, without executing anything.
+pub fn print_instruction_counts(file_path: &str) -> Result<(), Box<dyn Error>> {
+    let rom = read_rom(file_path)?;
+    let counts = scan::count_instructions(&rom);
+
+    println!(
+        "{} instructions on the straight-line path from 0x{:03X}",
+        counts.values().sum::<usize>(),
+        mmu::Chip8Mmu::PROGRAM_START
+    );
+    for (pattern, count) in counts {
+        println!("{}  {}", pattern, count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arbintrary::uint;
+
+    #[test]
+    fn reads_saved_flags_or_zeroes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flags");
+        assert_eq!([0; 8], read_flags(&path).unwrap());
+
+        fs::write(&path, [1, 2, 3]).unwrap();
+        assert_eq!([1, 2, 3, 0, 0, 0, 0, 0], read_flags(&path).unwrap());
+    }
+
+    #[test]
+    fn explicit_platform_overrides_detection() {
+        let rom = [0x00, 0xFF];
+        let mut config = Config::default();
+        assert_eq!(Some(Platform::SuperChip), platform_for(&config, &rom));
+
+        config.platform = Some(Platform::Chip8);
+        assert_eq!(Some(Platform::Chip8), platform_for(&config, &rom));
+    }
+
+    #[test]
+    fn detected_platform_picks_its_quirks() {
+        // F000 NNNN only exists on XO-CHIP
+        let rom = [0xF0, 0x00, 0x03, 0x00, 0x12, 0x04];
+        let mut config = Config::default();
+        let mut chip8 = Chip8::headless(config.clone());
+        chip8.load(&rom).unwrap();
+        assert_eq!(Quirks::xochip(), chip8.cpu().quirks());
+
+        config.quirk_overrides.vf_reset_on_logic = Some(true);
+        assert!(quirks_for(&config, Some(Platform::XoChip)).vf_reset_on_logic);
+        config.quirks = Some(Quirks::schip());
+        assert_eq!(
+            Quirks {
+                vf_reset_on_logic: true,
+                ..Quirks::schip()
+            },
+            quirks_for(&config, Some(Platform::XoChip))
+        );
+        assert_eq!(Quirks::default(), quirks_for(&Config::default(), None));
+    }
+
+    #[test]
+    fn display_clipping_quirk_sets_window_wrap() {
+        // Draw the font glyph for 0 at (60, 30), across the bottom right corner
+        let rom = [0x63, 0x3C, 0x62, 0x1E, 0xD3, 0x25, 0x12, 0x06];
+        let lit = |config: Config| {
+            let mut chip8 = Chip8::headless(config);
+            chip8.load(&rom).unwrap();
+            chip8.run_frame().unwrap();
+            let pixels = chip8.framebuffer().pixels();
+            pixels.iter().filter(|pixel| **pixel).count()
+        };
+
+        assert_eq!(6, lit(Config::default()));
+        let mut config = Config::default();
+        config.quirk_overrides.display_clipping = Some(false);
+        assert_eq!(14, lit(config));
+    }
+
+    #[test]
+    fn looping_keeps_running_through_a_halt() {
+        let mut config = Config {
+            halt_on_infinite_loop: true,
+            ..Config::default()
+        };
+        let mut chip8 = Chip8::headless(config.clone());
+        chip8.load(&[0x12, 0x00]).unwrap();
+        assert!(matches!(chip8.run_frame(), Err(CpuError::Halt { .. })));
+
+        config.loop_delay = Some(0.0);
+        let mut chip8 = Chip8::headless(config);
+        chip8.load(&[0x12, 0x00]).unwrap();
+        assert_eq!(Ok(()), chip8.run_frame());
+        assert!(chip8.cpu().is_halted());
+    }
+
+    #[test]
+    fn falls_back_to_headless_window() {
+        let error = || Err(minifb::Error::WindowCreate("no display".into()));
+        let mut config = Config::default();
+
+        let mut window = or_headless(error(), &config).unwrap();
+        assert!(window.is_open());
+        window.draw(0, 0, 8, vec![0x80]);
+        assert!(window.frame().pixels()[0]);
+
+        config.window_fallback = false;
+        assert!(or_headless(error(), &config).is_err());
+    }
+
+    #[test]
+    fn pokes_patch_loaded_rom() {
+        let config = Config {
+            pokes: vec![
+                (uint::<12>::new(0x200), 0x12),
+                (uint::<12>::new(0x300), 0xAB),
+            ],
+            ..Config::default()
+        };
+
+        let mut chip8 = Chip8::headless(config);
+
+        chip8.load(&[0x60, 0x01, 0x70, 0x02]).unwrap();
+
+        let memory = chip8.cpu().memory();
+        assert_eq!([0x12, 0x01], memory[0x200..0x202]);
+        assert_eq!(0xAB, memory[0x300]);
+    }
 
-        cpu.run_cycle()
+    #[test]
+    fn stats_title_shows_rom_rates_and_pc() {
+        assert_eq!(
+            "Chip8 - pong.ch8 - 700 IPS - 59.9 FPS - PC=0x2A4",
+            stats_title("pong.ch8", 700.4, 59.94, uint::<12>::new(0x2A4))
+        );
     }
 }
@@ -0,0 +1,472 @@
+use crate::audio::{Audio, NullAudio};
+use crate::config::{cycles_per_frame, Config};
+use crate::cpu::{Cpu, CpuError};
+use crate::framebuffer::FrameBuffer;
+use crate::mmu::{Chip8Mmu, LoadError, LoadStatus};
+use crate::window::{DisplayColors, HeadlessWindow, Hotkey, Window};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// A complete interpreter: a CPU with its memory, display and audio.
+///
+/// This is the simplest way to embed the interpreter. Call `run_frame` 60 times a second and show
+/// `framebuffer`, or `pixels` in the config's colours, after each call. To keep time yourself,
+/// e.g. to step through a ROM alongside its state, call `step` for each instruction and
+/// `tick_timers` 60 times per emulated second instead. For finer control, `cpu_mut` gives access
+/// to the CPU itself.
+pub struct Chip8 {
+    cpu: Cpu,
+    config: Config,
+    // Bit N is set while key N is held through `key_down`
+    keys: Rc<Cell<u16>>,
+    pacer: FramePacer,
+    colors: DisplayColors,
+    // The display `pixels` was last painted from
+    rendered: FrameBuffer,
+    pixels: Vec<u32>,
+}
+
+impl Chip8 {
+    /// Create an interpreter with no program loaded. Keys held on `window` and through `key_down`
+    /// both count as pressed.
+    pub fn new(config: Config, window: Box<dyn Window>, audio: Box<dyn Audio>) -> Chip8 {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.set_pad_byte(config.pad_byte);
+        let keys = Rc::new(Cell::new(0));
+        let window = Box::new(HeldKeysWindow {
+            window,
+            keys: Rc::clone(&keys),
+        });
+
+        let colors = config.display_colors();
+        let rendered = FrameBuffer::new();
+        Chip8 {
+            cpu: Cpu::new(mmu, window, audio, config.quirks.unwrap_or_default()),
+            config,
+            keys,
+            pacer: FramePacer::default(),
+            pixels: colors.paint(&rendered, 1),
+            colors,
+            rendered,
+        }
+    }
+
+    /// Create an interpreter with no window or audio device, for applications that show `pixels`
+    /// themselves.
+    pub fn headless(config: Config) -> Chip8 {
+        Chip8::new(config, Box::new(HeadlessWindow::new()), Box::new(NullAudio))
+    }
+
+    /// Load `rom` and start it from the beginning, with a blank screen. The config's pokes are
+    /// applied on top, and the platform is detected from `rom` unless the config sets one.
+    pub fn load(&mut self, rom: &[u8]) -> Result<LoadStatus, LoadError> {
+        let status = self.cpu.load_program(rom)?;
+        crate::apply_pokes(self.cpu.mmu_mut(), &self.config);
+        crate::configure_cpu(&mut self.cpu, &self.config, rom);
+        self.pacer = FramePacer::default();
+        self.render();
+        Ok(status)
+    }
+
+    /// Load the ROM at `file_path`, or from stdin if it is `-`.
+    pub fn load_file(&mut self, file_path: &str) -> Result<LoadStatus, LoadError> {
+        self.load(&crate::read_rom(file_path)?)
+    }
+
+    /// Run one 60hz frame: as many instructions as the frequency allows, then tick the timers and
+    /// render. With `idle_throttle`, the frame ends early once the ROM is only waiting on the
+    /// delay timer.
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        let result = self.run_frame_instructions();
+        if result.is_ok() {
+            self.cpu.run_60hz_cycle();
+        }
+        self.render();
+        result
+    }
+
+    // Run the instructions `run_frame` has cycles for, stopping at the first to fail
+    fn run_frame_instructions(&mut self) -> Result<(), CpuError> {
+        let frequency = self
+            .config
+            .frequency_schedule
+            .frequency_at(self.config.frequency, self.cpu.stats().cycles);
+        self.pacer.start_frame(frequency);
+        while self.pacer.has_cycle() {
+            if self.config.idle_throttle && self.cpu.is_idle() {
+                // Nothing can happen until the delay timer ticks
+                self.pacer.end_frame_early();
+                break;
+            }
+            let cycles = self.cpu.stats().cycles;
+            self.cpu.step()?;
+            self.pacer.spend(self.cpu.stats().cycles - cycles);
+        }
+        Ok(())
+    }
+
+    /// Execute exactly one opcode, whatever the frequency. The timers are left alone.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        let result = self.cpu.step();
+        self.render();
+        result
+    }
+
+    /// Count the delay and sound timers down by one tick, as happens at 60hz, and render.
+    pub fn tick_timers(&mut self) {
+        self.cpu.run_60hz_cycle();
+        self.render();
+    }
+
+    /// Hold down CHIP-8 key `key`, 0x0 to 0xF, until `key_up`.
+    pub fn key_down(&mut self, key: u8) {
+        self.keys.set(self.keys.get() | (1 << key));
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.keys.set(self.keys.get() & !(1 << key));
+    }
+
+    /// Restart the loaded program from the beginning, restoring its memory, with the config's
+    /// pokes applied again, and blanking the screen.
+    pub fn reset(&mut self) {
+        self.cpu.hard_reset();
+        crate::apply_pokes(self.cpu.mmu_mut(), &self.config);
+        self.pacer = FramePacer::default();
+        self.render();
+    }
+
+    pub fn framebuffer(&self) -> &FrameBuffer {
+        self.cpu.window().frame()
+    }
+
+    /// The display as 0RGB colours, `FrameBuffer::width` pixels per row: 64, or 128 in SCHIP's
+    /// high resolution mode. The colours are the config's, as the window would show them. Changes
+    /// made through `cpu_mut` show up after the next `run_frame`, `step` or `tick_timers`.
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    // Repaint `pixels` if the display has changed since it was last painted
+    fn render(&mut self) {
+        let frame = self.cpu.window().frame();
+        if *frame != self.rendered {
+            self.pixels = self.colors.paint(frame, 1);
+            self.rendered.clone_from(frame);
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+/// Spreads instructions at a frequency over 60hz frames, keeping the timers at 60hz whatever the
+/// frequency. Below 60hz some frames run no instructions at all.
+#[derive(Default)]
+pub(crate) struct FramePacer {
+    // Cycles owed to the current frame. Carries the fraction when the frequency isn't a multiple
+    // of 60, and goes negative when an instruction overran the last frame
+    budget: f64,
+}
+
+impl FramePacer {
+    /// Add a frame's worth of cycles at `frequency` hz to the budget.
+    pub fn start_frame(&mut self, frequency: u32) {
+        self.budget += cycles_per_frame(frequency, 60);
+    }
+
+    /// Whether the frame has a whole cycle left to start an instruction with.
+    pub fn has_cycle(&self) -> bool {
+        self.budget >= 1.0
+    }
+
+    /// Take the `cycles` an instruction consumed out of the frame.
+    pub fn spend(&mut self, cycles: u64) {
+        self.budget -= cycles as f64;
+    }
+
+    /// Give up the frame's remaining whole cycles, keeping the fraction for the next frame.
+    pub fn end_frame_early(&mut self) {
+        self.budget = self.budget.fract();
+    }
+}
+
+/// Adds the keys held through `Chip8::key_down` to those held on a window.
+struct HeldKeysWindow {
+    window: Box<dyn Window>,
+    keys: Rc<Cell<u16>>,
+}
+
+impl HeldKeysWindow {
+    fn is_held(&self, key: u8) -> bool {
+        key <= 0xF && self.keys.get() & (1 << key) != 0
+    }
+}
+
+impl Window for HeldKeysWindow {
+    fn blank_screen(&mut self) {
+        self.window.blank_screen();
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        self.window.draw(x, y, width, sprite)
+    }
+
+    fn render(&mut self) {
+        self.window.render();
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn present(&mut self) {
+        self.window.present();
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.is_held(key) || self.window.is_key_pressed(key)
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        let held = (0..=0xF).find(|key| self.is_held(*key));
+        match (held, self.window.get_pressed_key()) {
+            (Some(held), Some(key)) => Some(held.min(key)),
+            (held, key) => held.or(key),
+        }
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        self.window.pressed_hotkey()
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.window.frame_hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        self.window.frame()
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        self.window.screenshot()
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.window.export_sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        self.window.clipped_draws()
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.window.scroll_up(n);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.window.scroll_down(n);
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.window.scroll_left(n);
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.window.scroll_right(n);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8) {
+        self.window.set_clip(x, y, width, height);
+    }
+
+    fn clear_clip(&mut self) {
+        self.window.clear_clip();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.window.set_wrap(wrap);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.window.set_hires(hires);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.window.set_planes(planes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Theme;
+    use arbintrary::uint;
+    use rstest::*;
+
+    // Set V1 while key 5 is held, checking once every 5 instructions
+    const KEY_ROM: [u8; 10] = [
+        0x62, 0x05, // 200: LD V2, 5
+        0x61, 0x00, // 202: LD V1, 0
+        0xE2, 0xA1, // 204: SKNP V2
+        0x61, 0x01, // 206: LD V1, 1
+        0x12, 0x00, // 208: JP 200
+    ];
+
+    fn chip8(frequency: u32) -> Chip8 {
+        let config = Config {
+            frequency,
+            ..Config::default()
+        };
+        Chip8::headless(config)
+    }
+
+    #[rstest]
+    #[case(600, 1, 10)]
+    #[case(90, 1, 1)]
+    #[case(90, 2, 3)]
+    #[case(30, 1, 0)]
+    #[case(30, 4, 2)]
+    #[case(20, 7, 2)]
+    fn runs_frequency_over_60_instructions_per_frame(
+        #[case] frequency: u32,
+        #[case] frames: usize,
+        #[case] instructions: u64,
+    ) {
+        let mut chip8 = chip8(frequency);
+        chip8.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+        }
+
+        assert_eq!(instructions, chip8.cpu().stats().instructions);
+    }
+
+    #[test]
+    fn ticks_timers_every_frame_below_60hz() {
+        let mut chip8 = chip8(30);
+        // LD V0, 10; LD DT, V0; then spin
+        chip8.load(&[0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+
+        for _ in 0..10 {
+            chip8.run_frame().unwrap();
+        }
+
+        // Set to 10 in the fourth frame and ticked at the end of it and the six after
+        assert_eq!(5, chip8.cpu().stats().instructions);
+        assert_eq!(3, chip8.cpu().save_state().delay_timer);
+    }
+
+    #[test]
+    fn held_keys_reach_the_rom() {
+        let mut chip8 = chip8(300);
+        chip8.load(&KEY_ROM).unwrap();
+
+        chip8.key_down(0x5);
+        chip8.run_frame().unwrap();
+        assert_eq!(1, chip8.cpu().registers()[1]);
+
+        chip8.key_up(0x5);
+        chip8.reset();
+        chip8.run_frame().unwrap();
+        assert_eq!(0, chip8.cpu().registers()[1]);
+    }
+
+    #[test]
+    fn reset_restarts_program_with_blank_screen() {
+        let mut chip8 = chip8(500);
+        // Draw the font glyph for 0 at (0, 0), then spin
+        chip8.load(&[0xD0, 0x05, 0x12, 0x02]).unwrap();
+
+        chip8.run_frame().unwrap();
+        assert_eq!(14, lit(chip8.framebuffer()));
+
+        chip8.reset();
+        assert_eq!(0, lit(chip8.framebuffer()));
+        assert_eq!(0x200, u16::from(chip8.cpu().program_counter()));
+    }
+
+    #[test]
+    fn reset_keeps_pokes() {
+        let config = Config {
+            pokes: vec![(uint::<12>::new(0x300), 0xAB)],
+            ..Config::default()
+        };
+        let mut chip8 = Chip8::headless(config);
+        // LD V0, 1; LD I, 0x300; LD [I], V0, overwriting the poked byte
+        chip8.load(&[0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55]).unwrap();
+        for _ in 0..3 {
+            chip8.cpu_mut().step().unwrap();
+        }
+        assert_eq!(0x01, chip8.cpu().memory()[0x300]);
+
+        chip8.reset();
+
+        assert_eq!(0xAB, chip8.cpu().memory()[0x300]);
+    }
+
+    #[test]
+    fn step_runs_one_opcode() {
+        let mut chip8 = Chip8::headless(Config::default());
+        chip8.load(&[0x60, 0x07, 0x61, 0x09]).unwrap();
+
+        chip8.step().unwrap();
+
+        assert_eq!([0x07, 0x00], chip8.cpu().registers()[..2]);
+        assert_eq!(0x202, u16::from(chip8.cpu().program_counter()));
+    }
+
+    #[test]
+    fn timers_only_tick_when_asked() {
+        let mut chip8 = Chip8::headless(Config::default());
+        // LD V0, 3; LD DT, V0
+        chip8.load(&[0x60, 0x03, 0xF0, 0x15]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(3, chip8.cpu().save_state().delay_timer);
+
+        chip8.tick_timers();
+
+        assert_eq!(2, chip8.cpu().save_state().delay_timer);
+    }
+
+    #[test]
+    fn pixels_use_theme_colours() {
+        let config = Config {
+            theme: Theme::Lcd,
+            ..Config::default()
+        };
+        let (foreground, background) = Theme::Lcd.colors();
+        let mut chip8 = Chip8::headless(config);
+        // Draw the top row of the font glyph for 0 at (0, 0), then clear the screen
+        chip8.load(&[0xD0, 0x01, 0x00, 0xE0]).unwrap();
+        assert_eq!([background; 4], chip8.pixels()[..4]);
+
+        chip8.step().unwrap();
+        let pixels = chip8.pixels();
+        assert_eq!(FrameBuffer::WIDTH * FrameBuffer::HEIGHT, pixels.len());
+        assert_eq!([foreground; 4], pixels[..4]);
+        assert_eq!(background, pixels[4]);
+
+        chip8.step().unwrap();
+        assert_eq!([background; 4], chip8.pixels()[..4]);
+    }
+
+    fn lit(frame: &FrameBuffer) -> usize {
+        frame.pixels().iter().filter(|pixel| **pixel).count()
+    }
+}
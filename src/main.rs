@@ -6,16 +6,431 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The ch8 binary file to load
+    /// The ch8 binary file to load, or - to read it from stdin
     file: String,
 
     /// Sets the CPU frequency in hz
     #[arg(short, long, default_value_t = 500)]
     freq: u32,
+
+    /// Switch to HZ once CYCLE cycles have run, e.g. 5000=1000 to speed up a demo. Can be
+    /// repeated
+    #[arg(long, value_name = "CYCLE=HZ", value_parser = parse_frequency_change)]
+    frequency_at: Vec<(u64, u32)>,
+
+    /// Log how many instructions ran in each 60hz frame
+    #[arg(long)]
+    log_ipf: bool,
+
+    /// Log draws that had pixels cut off at the screen edge, which usually means the ROM expects
+    /// sprites to wrap
+    #[arg(long)]
+    log_clipped_draws: bool,
+
+    /// Show the ROM name, IPS, FPS and program counter in the window title, updated each second
+    #[arg(long)]
+    title_stats: bool,
+
+    /// Smooth the reported IPS and FPS, by 1 (raw) down to just above 0 (smoothest)
+    #[arg(long, value_name = "ALPHA", default_value_t = 0.3, value_parser = parse_alpha)]
+    rate_smoothing: f64,
+
+    /// Byte used to pad a ROM with an odd length, e.g. 0x00
+    #[arg(long, default_value = "0x00", value_parser = parse_u8)]
+    pad_byte: u8,
+
+    /// Write a byte into memory after loading the ROM, e.g. 0x200=0x12. Can be repeated
+    #[arg(long, value_name = "ADDR=BYTE", value_parser = parse_poke)]
+    poke: Vec<(arbintrary::uint<12>, u8)>,
+
+    /// Sleep instead of spinning while the ROM busy-waits on the delay timer
+    #[arg(long)]
+    idle_throttle: bool,
+
+    /// Display colour preset
+    #[arg(long, value_enum, default_value_t = chip8::Theme::Amber)]
+    theme: chip8::Theme,
+
+    /// Colour for lit pixels as hex RRGGBB, instead of the theme's or --palette's plane 1 colour
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    fg: Option<u32>,
+
+    /// Colour for unlit pixels as hex RRGGBB, instead of the theme's or --palette's first colour
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    bg: Option<u32>,
+
+    /// Darken alternate rows of output for a CRT look, by 0 (off) to 1 (black)
+    #[arg(long, value_name = "INTENSITY", num_args = 0..=1, default_missing_value = "0.5")]
+    scanlines: Option<f32>,
+
+    /// Fill the screen with this colour, as hex RRGGBB, when it is cleared. Pixels drawn and then
+    /// erased still use the theme's background
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    clear_color: Option<u32>,
+
+    /// XO-CHIP colours as hex RRGGBB for no plane, plane 1, plane 2 and both planes lit
+    #[arg(long, value_name = "C0,C1,C2,C3")]
+    palette: Option<chip8::Palette>,
+
+    /// Stretch each presented pixel to this width:height, e.g. 2:1, like some original displays
+    #[arg(long, value_name = "W:H", default_value = "1:1")]
+    pixel_aspect: chip8::PixelAspect,
+
+    /// Screen pixels per CHIP-8 pixel: 1, 2, 4, 8 or 16. --pixel-aspect stretches pixels on top of
+    /// this
+    #[arg(long, default_value = "8")]
+    scale: chip8::WindowScale,
+
+    /// Which keyboard keys stand in for the hex keypad
+    #[arg(long, value_enum, default_value_t = chip8::KeypadLayout::Classic)]
+    keypad_layout: chip8::KeypadLayout,
+
+    /// CHIP-8 variant to emulate. Detected from the ROM's opcodes if not given
+    #[arg(long, value_enum)]
+    platform: Option<chip8::Platform>,
+
+    /// Seed the random number generator so runs are reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Fill registers and memory below the program with random bytes instead of zeros
+    #[arg(long)]
+    randomize_startup: bool,
+
+    /// What FX55 and FX65 do when I + X runs past the end of memory
+    #[arg(long, value_enum, default_value_t = chip8::MemoryOverflow::Wrap)]
+    memory_overflow: chip8::MemoryOverflow,
+
+    /// Exit with an error if no window can be opened, instead of running without one
+    #[arg(long)]
+    no_window_fallback: bool,
+
+    /// Use this variant's quirks instead of those of the ROM's platform; the quirk flags below
+    /// override it
+    #[arg(long, value_enum, value_name = "PLATFORM")]
+    quirks: Option<chip8::Platform>,
+
+    /// Leave VF alone after 8XY1, 8XY2 and 8XY3 instead of clearing it as the COSMAC VIP does
+    #[arg(long)]
+    no_vf_reset: bool,
+
+    /// Make 8XY6 and 8XYE shift VY into VX, as the COSMAC VIP does, instead of shifting VX
+    #[arg(long)]
+    shift_uses_vy: bool,
+
+    /// Leave I past the last register FX55 and FX65 transfer, as the COSMAC VIP does
+    #[arg(long)]
+    memory_increments_index: bool,
+
+    /// Treat BNNN as SCHIP's BXNN, jumping to XNN plus VX instead of NNN plus V0
+    #[arg(long)]
+    jump_uses_vx: bool,
+
+    /// How DXYN treats sprite origins that are off the screen
+    #[arg(long, value_enum, default_value_t = chip8::CoordinateMode::Raw)]
+    coordinates: chip8::CoordinateMode,
+
+    /// Wrap sprites that cross the edge of the screen round to the opposite edge instead of
+    /// clipping them, as XO-CHIP does
+    #[arg(long)]
+    wrap_sprites: bool,
+
+    /// What DXYN reads for sprite rows past the end of memory
+    #[arg(long, value_enum, default_value_t = chip8::SpriteReads::Wrap)]
+    sprite_reads: chip8::SpriteReads,
+
+    /// Extra cycles each DXYN consumes out of --freq, to mimic draws on slow hardware
+    #[arg(long, value_name = "CYCLES", default_value_t = 0)]
+    draw_cycles: u32,
+
+    /// What to do with undefined opcodes
+    #[arg(long, value_enum, default_value_t = chip8::UnknownOpcodes::Error)]
+    unknown_opcodes: chip8::UnknownOpcodes,
+
+    /// Whether to stop, or log the error and skip the instruction, when the ROM does something
+    /// the CPU can't, such as an unknown opcode or returning with an empty stack
+    #[arg(long, value_enum, default_value_t = chip8::OnError::Halt)]
+    on_error: chip8::OnError,
+
+    /// Debugging aid (non-standard): make 00E0 a no-op so draws accumulate on screen
+    #[arg(long)]
+    ignore_clear: bool,
+
+    /// Exit when the ROM jumps to itself with 1NNN, the usual way a ROM ends, instead of spinning
+    #[arg(long)]
+    halt_on_infinite_loop: bool,
+
+    /// If the interpreter panics, print the instruction it was running and the registers
+    #[arg(long)]
+    dump_state_on_panic: bool,
+
+    /// Draw into a back buffer and only show it once per 60hz frame, avoiding mid-frame tearing
+    #[arg(long)]
+    double_buffer: bool,
+
+    /// Only show the first N draws, clears or scrolls of each 60hz frame, coalescing the rest into
+    /// the next frame. The CPU is not slowed down.
+    #[arg(long, value_name = "N")]
+    max_presents_per_frame: Option<u32>,
+
+    /// Also accept keypad input as "down <key>" / "up <key>" lines on this Unix domain socket
+    #[arg(long, value_name = "PATH")]
+    remote_input: Option<std::path::PathBuf>,
+
+    /// Soft reset and replay the ROM once it halts on a self-jump
+    #[arg(long = "loop", conflicts_with = "halt_on_infinite_loop")]
+    loop_rom: bool,
+
+    /// Seconds to keep showing a halted ROM's final screen before --loop restarts it
+    #[arg(long, default_value_t = 3.0, value_name = "SECONDS")]
+    loop_delay: f64,
+
+    /// Write every rendered frame to this directory as frame_00001.png, frame_00002.png, ...
+    #[arg(long, value_name = "DIR")]
+    frames_dir: Option<std::path::PathBuf>,
+
+    /// Keep the SCHIP flag registers (FX75/FX85) in this file, so high scores survive restarts
+    #[arg(long, value_name = "PATH")]
+    flags_file: Option<std::path::PathBuf>,
+
+    /// Serve Prometheus-style metrics over HTTP on this address, e.g. 127.0.0.1:9100
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Pitch of the beep in hz
+    #[arg(long, default_value_t = 587.33, value_name = "HZ")]
+    beep_hz: f32,
+
+    /// Shape of the beep's wave
+    #[arg(long, value_enum, default_value_t = chip8::Waveform::Square)]
+    waveform: chip8::Waveform,
+
+    /// Loudness of the beep from 0.0, silent, to 1.0. Press F2 to mute and unmute
+    #[arg(long, default_value_t = 1.0, value_name = "VOLUME")]
+    volume: f32,
+
+    /// Run headlessly as fast as possible for this many seconds and report the throughput
+    #[arg(long, value_name = "SECONDS")]
+    benchmark: Option<f64>,
+
+    /// Count the instructions on the ROM's straight-line path without running it, then exit
+    #[arg(long)]
+    count_instructions_only: bool,
+
+    /// Print a disassembly of the ROM without running it, then exit
+    #[arg(long)]
+    disassemble: bool,
+
+    /// Run the ROM headlessly twice in lockstep, once with these options and once with the
+    /// options in FILE (e.g. `--no-vf-reset`), print the first cycle at which they differ, then
+    /// exit
+    #[arg(long, value_name = "FILE")]
+    compare_with: Option<std::path::PathBuf>,
+
+    /// How many cycles --compare-with runs for
+    #[arg(long, value_name = "CYCLES", default_value_t = 10_000)]
+    compare_cycles: usize,
+
+    /// Key presses to replay in both --compare-with runs, one `<cycle> down|up <key>` per line
+    #[arg(long, value_name = "FILE")]
+    compare_input: Option<std::path::PathBuf>,
+}
+
+/// Parse a byte given in decimal or as 0x prefixed hex.
+fn parse_u8(value: &str) -> Result<u8, String> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|err| format!("invalid byte {:?}: {}", value, err))
+}
+
+fn parse_alpha(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        Ok(_) => Err(format!("{} is not in (0, 1]", value)),
+        Err(err) => Err(format!("invalid number {:?}: {}", value, err)),
+    }
+}
+
+/// Parse a colour given as 6 hex digits, optionally prefixed with #.
+fn parse_color(value: &str) -> Result<u32, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 {
+        return Err(format!("expected RRGGBB, got {:?}", value));
+    }
+    u32::from_str_radix(hex, 16).map_err(|err| format!("invalid colour {:?}: {}", value, err))
+}
+
+/// Parse a `cycle=frequency` pair.
+fn parse_frequency_change(value: &str) -> Result<(u64, u32), String> {
+    let (cycle, frequency) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected CYCLE=HZ, got {:?}", value))?;
+    let cycle = cycle
+        .parse()
+        .map_err(|err| format!("invalid cycle {:?}: {}", cycle, err))?;
+    match frequency.parse() {
+        Ok(0) => Err("frequency must be at least 1".to_string()),
+        Ok(frequency) => Ok((cycle, frequency)),
+        Err(err) => Err(format!("invalid frequency {:?}: {}", frequency, err)),
+    }
+}
+
+/// Parse an `address=byte` pair, each in decimal or as 0x prefixed hex.
+fn parse_poke(value: &str) -> Result<(arbintrary::uint<12>, u8), String> {
+    let (address, byte) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected ADDR=BYTE, got {:?}", value))?;
+    let address = match address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => address.parse(),
+    }
+    .map_err(|err| format!("invalid address {:?}: {}", address, err))?;
+    if address > 0xFFF {
+        return Err(format!("address 0x{:X} is past the end of memory", address));
+    }
+    Ok((arbintrary::uint::<12>::new(address), parse_u8(byte)?))
+}
+
+fn config(args: &Args) -> chip8::Config {
+    chip8::Config {
+        frequency: args.freq,
+        frequency_schedule: chip8::FrequencySchedule::new(args.frequency_at.clone()),
+        log_ipf: args.log_ipf,
+        pad_byte: args.pad_byte,
+        pokes: args.poke.clone(),
+        idle_throttle: args.idle_throttle,
+        theme: args.theme,
+        foreground: args.fg,
+        background: args.bg,
+        scanlines: args.scanlines.unwrap_or(0.0),
+        clear_color: args.clear_color,
+        palette: args.palette,
+        pixel_aspect: args.pixel_aspect,
+        scale: args.scale,
+        keypad_layout: args.keypad_layout,
+        platform: args.platform,
+        seed: args.seed,
+        randomize_startup: args.randomize_startup,
+        log_clipped_draws: args.log_clipped_draws,
+        title_stats: args.title_stats,
+        rate_smoothing: args.rate_smoothing,
+        memory_overflow: args.memory_overflow,
+        window_fallback: !args.no_window_fallback,
+        quirks: args.quirks.map(chip8::Quirks::for_platform),
+        quirk_overrides: quirk_overrides(args),
+        coordinate_mode: args.coordinates,
+        sprite_reads: args.sprite_reads,
+        draw_cycles: args.draw_cycles,
+        unknown_opcodes: args.unknown_opcodes,
+        on_error: args.on_error,
+        ignore_clear: args.ignore_clear,
+        halt_on_infinite_loop: args.halt_on_infinite_loop,
+        dump_state_on_panic: args.dump_state_on_panic,
+        double_buffer: args.double_buffer,
+        max_presents_per_frame: args.max_presents_per_frame,
+        remote_input: args.remote_input.clone(),
+        frames_dir: args.frames_dir.clone(),
+        flags_file: args.flags_file.clone(),
+        metrics_addr: args.metrics_addr,
+        audio: chip8::AudioConfig {
+            frequency: args.beep_hz,
+            waveform: args.waveform,
+        },
+        volume: args.volume,
+        loop_delay: if args.loop_rom {
+            Some(args.loop_delay)
+        } else {
+            None
+        },
+    }
+}
+
+/// The individual quirk flags, to apply on top of the `--quirks` preset or the platform's quirks.
+fn quirk_overrides(args: &Args) -> chip8::QuirkOverrides {
+    chip8::QuirkOverrides {
+        shift_uses_vy: args.shift_uses_vy.then_some(true),
+        memory_increments_index: args.memory_increments_index.then_some(true),
+        jump_uses_vx: args.jump_uses_vx.then_some(true),
+        display_clipping: args.wrap_sprites.then_some(false),
+        vf_reset_on_logic: args.no_vf_reset.then_some(false),
+    }
+}
+
+/// Warn if the ROM had to be padded to a whole number of instructions, as it may be truncated.
+fn report_load(status: chip8::LoadStatus) {
+    if let chip8::LoadStatus::Padded { length, pad_byte } = status {
+        eprintln!(
+            "Warning: program length {} is odd, padding with {:#04X}",
+            length, pad_byte
+        );
+    }
+}
+
+/// Parse the options in `path` as if they followed the ROM on the command line and compare a run
+/// with them against a run with `args`.
+fn compare(args: &Args, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let options = std::fs::read_to_string(path)?;
+    let other = Args::try_parse_from(
+        vec!["chip8", args.file.as_str()]
+            .into_iter()
+            .chain(options.split_whitespace()),
+    )?;
+    let input_script = match &args.compare_input {
+        Some(path) => chip8::InputScript::parse(&std::fs::read_to_string(path)?)?,
+        None => chip8::InputScript::default(),
+    };
+    chip8::compare(
+        &config(args),
+        &config(&other),
+        &args.file,
+        &input_script,
+        args.compare_cycles,
+    )
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let args = Args::parse();
-    chip8::run(args.freq, &args.file).await;
+
+    if args.disassemble {
+        if let Err(err) = chip8::print_listing(&args.file) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.count_instructions_only {
+        if let Err(err) = chip8::print_instruction_counts(&args.file) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.compare_with {
+        if let Err(err) = compare(&args, path) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = config(&args);
+    let result = match args.benchmark {
+        Some(seconds) => chip8::benchmark(config, &args.file, seconds, report_load),
+        None => chip8::run(config, &args.file, report_load).await,
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,202 @@
+use crate::cpu::Stats;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// An exponential moving average, for rates that jitter from one sample to the next. Each sample
+/// moves the average `alpha` of the way towards it, so an alpha of 1 does no smoothing.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Ema {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(alpha: f64) -> Ema {
+        Ema { alpha, value: None }
+    }
+
+    /// Add a sample and return the new average. The first sample is taken as is.
+    pub fn update(&mut self, sample: f64) -> f64 {
+        let value = match self.value {
+            Some(value) => value + self.alpha * (sample - value),
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+}
+
+/// Counters for a running interpreter, shared with the metrics endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    instructions: AtomicU64,
+    frames: AtomicU64,
+    collisions: AtomicU64,
+    unknown_opcodes: AtomicU64,
+    // Rates are stored as f64 bits so they can be updated without a lock
+    ips: AtomicU64,
+    fps: AtomicU64,
+}
+
+impl Metrics {
+    /// Publish the CPU's latest totals and the number of frames rendered so far.
+    pub fn update(&self, stats: Stats, frames: u64) {
+        self.instructions
+            .store(stats.instructions, Ordering::Relaxed);
+        self.frames.store(frames, Ordering::Relaxed);
+        self.collisions.store(stats.collisions, Ordering::Relaxed);
+        self.unknown_opcodes
+            .store(stats.unknown_opcodes, Ordering::Relaxed);
+    }
+
+    /// Publish the instructions and frames per second measured over the last interval.
+    pub fn update_rates(&self, ips: f64, fps: f64) {
+        self.ips.store(ips.to_bits(), Ordering::Relaxed);
+        self.fps.store(fps.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "chip8_instructions_total",
+                "Instructions executed",
+                &self.instructions,
+            ),
+            ("chip8_frames_total", "Frames rendered", &self.frames),
+            (
+                "chip8_collisions_total",
+                "Draws that turned off a lit pixel",
+                &self.collisions,
+            ),
+            (
+                "chip8_unknown_opcodes_total",
+                "Undefined opcodes skipped",
+                &self.unknown_opcodes,
+            ),
+        ];
+        let gauges = [
+            ("chip8_ips", "Instructions per second", &self.ips),
+            ("chip8_fps", "Frames per second", &self.fps),
+        ];
+
+        let mut text = String::new();
+        for (name, help, value) in counters {
+            let value = value.load(Ordering::Relaxed);
+            let _ = write!(
+                text,
+                "# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n",
+                name, help, value
+            );
+        }
+        for (name, help, value) in gauges {
+            let value = f64::from_bits(value.load(Ordering::Relaxed));
+            let _ = write!(
+                text,
+                "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2:.1}\n",
+                name, help, value
+            );
+        }
+        text
+    }
+}
+
+/// Answer every HTTP request on `listener` with the current metrics, whatever the path.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) -> io::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        // The request itself doesn't matter, but read it so the client sees a clean close
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await;
+
+        let body = metrics.render();
+        let response = format!(
+            concat!(
+                "HTTP/1.1 200 OK\r\n",
+                "Content-Type: text/plain; version=0.0.4\r\n",
+                "Content-Length: {}\r\n",
+                "Connection: close\r\n",
+                "\r\n",
+                "{}",
+            ),
+            body.len(),
+            body
+        );
+        // A client hanging up early is its own problem, keep serving the others
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn ema_smooths_samples() {
+        let mut ema = Ema::new(0.5);
+
+        let smoothed: Vec<f64> = [500.0, 600.0, 400.0, 400.0]
+            .iter()
+            .map(|sample| ema.update(*sample))
+            .collect();
+
+        assert_eq!(vec![500.0, 550.0, 475.0, 437.5], smoothed);
+    }
+
+    #[test]
+    fn ema_alpha_of_one_passes_samples_through() {
+        let mut ema = Ema::new(1.0);
+        ema.update(500.0);
+        assert_eq!(600.0, ema.update(600.0));
+    }
+
+    #[test]
+    fn renders_counters_and_rates() {
+        let metrics = Metrics::default();
+        metrics.update(
+            Stats {
+                instructions: 700,
+                cycles: 700,
+                collisions: 3,
+                unknown_opcodes: 1,
+            },
+            60,
+        );
+        metrics.update_rates(700.0, 59.9);
+
+        let text = metrics.render();
+
+        assert!(text
+            .contains("# TYPE chip8_instructions_total counter\nchip8_instructions_total 700\n"));
+        assert!(text.contains("\nchip8_frames_total 60\n"));
+        assert!(text.contains("\nchip8_collisions_total 3\n"));
+        assert!(text.contains("\nchip8_unknown_opcodes_total 1\n"));
+        assert!(text.contains("# TYPE chip8_ips gauge\nchip8_ips 700.0\n"));
+        assert!(text.contains("\nchip8_fps 59.9\n"));
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        metrics.update(Stats::default(), 5);
+        tokio::spawn(serve(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nchip8_frames_total 5\n"));
+    }
+}
@@ -2,8 +2,58 @@ use arbintrary::uint;
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
+use std::fmt;
+use std::fs;
+use std::io;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The ROM couldn't be read
+    Io(io::Error),
+    /// The ROM contains no data
+    Empty,
+    /// The ROM doesn't fit in the memory available for programs
+    TooLarge { size: u64, max: u64 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "Failed to read program: {}", err),
+            LoadError::Empty => write!(f, "Program is empty"),
+            LoadError::TooLarge { size, max } => write!(
+                f,
+                "Memory overflow, program too large. {:?} > {:?}",
+                size, max
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// How a program was fitted into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStatus {
+    /// The program was loaded as it is
+    Loaded,
+    /// The program was an odd `length` of bytes, so `pad_byte` was added to complete its last
+    /// instruction
+    Padded { length: usize, pad_byte: u8 },
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
 
 #[cfg_attr(test, automock)]
 pub trait Mmu {
@@ -13,20 +63,38 @@ pub trait Mmu {
     fn write_u8(&mut self, address: uint<12>, data: u8);
     fn write_u16(&mut self, address: uint<12>, data: u16);
 
-    fn load_program(&mut self, file_path: &str) -> Result<(), Box<dyn Error>>;
+    fn load_program(&mut self, file_path: &str) -> Result<LoadStatus, LoadError>;
+
+    /// Copy `bytes` into memory starting at `address`. Fail without writing anything if they
+    /// would run past the end of memory.
+    fn load_at(&mut self, address: uint<12>, bytes: &[u8]) -> Result<(), LoadError>;
+
+    /// Load a program that is already in memory, e.g. one read from stdin.
+    fn load_program_bytes(&mut self, program: &[u8]) -> Result<LoadStatus, LoadError>;
+
+    /// Restore the program region to the program as it was originally loaded.
+    fn reload_program(&mut self);
+
+    /// Zero everything from the start of the program to the end of memory.
+    fn clear_program_area(&mut self);
 }
 
 pub struct Chip8Mmu {
     memory: Vec<u8>,
+    pad_byte: u8,
+    // Pristine copy of the loaded program, used to undo self-modification
+    program: Vec<u8>,
 }
 
 impl Chip8Mmu {
     // Address of the first instruction
-    const PROGRAM_START: usize = 0x200;
+    pub const PROGRAM_START: usize = 0x200;
     // Total number of bytes available
-    const MEM_SIZE: usize = 4096;
+    pub const MEM_SIZE: usize = 4096;
     // Number of bytes in each font sprite
     pub const FONT_SPRITE_HEIGHT: u8 = 5;
+    /// First address after the font sprites
+    pub const FONT_END: usize = Self::FONT_SET.len();
     // Collection fo characters at a known location
     const FONT_SET: [u8; 80] = [
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -55,7 +123,22 @@ impl Chip8Mmu {
             memory[i] = *font_data;
         }
 
-        Chip8Mmu { memory }
+        Chip8Mmu {
+            memory,
+            pad_byte: 0x00,
+            program: Vec::new(),
+        }
+    }
+
+    /// Set the byte used to pad odd length programs out to a whole instruction.
+    pub fn set_pad_byte(&mut self, pad_byte: u8) {
+        self.pad_byte = pad_byte;
+    }
+}
+
+impl Default for Chip8Mmu {
+    fn default() -> Chip8Mmu {
+        Chip8Mmu::new()
     }
 }
 
@@ -78,29 +161,71 @@ impl Mmu for Chip8Mmu {
         self.memory[usize::from(address + uint::<12>::new(1))] = data as u8;
     }
 
-    fn load_program(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
-        let file = File::open(&file_path)?;
+    fn load_program(&mut self, file_path: &str) -> Result<LoadStatus, LoadError> {
+        self.load_program_bytes(&fs::read(file_path)?)
+    }
 
-        if file.metadata()?.len() > (Self::MEM_SIZE - Self::PROGRAM_START) as u64 {
-            return Err(format!(
-                "Memory overflow, program too large. {:?} > {:?}",
-                file.metadata()?.len(),
-                Self::MEM_SIZE - Self::PROGRAM_START
-            )
-            .into());
+    fn load_at(&mut self, address: uint<12>, bytes: &[u8]) -> Result<(), LoadError> {
+        let start = usize::from(address);
+        if bytes.len() > Self::MEM_SIZE - start {
+            return Err(LoadError::TooLarge {
+                size: bytes.len() as u64,
+                max: (Self::MEM_SIZE - start) as u64,
+            });
         }
+        self.memory[start..][..bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
 
-        for (i, data) in file.bytes().enumerate() {
-            self.memory[Self::PROGRAM_START + i] = data?;
+    fn load_program_bytes(&mut self, program: &[u8]) -> Result<LoadStatus, LoadError> {
+        let size = program.len() as u64;
+        if size == 0 {
+            return Err(LoadError::Empty);
+        }
+        if size > (Self::MEM_SIZE - Self::PROGRAM_START) as u64 {
+            return Err(LoadError::TooLarge {
+                size,
+                max: (Self::MEM_SIZE - Self::PROGRAM_START) as u64,
+            });
         }
 
-        Ok(())
+        let mut program = program.to_vec();
+
+        // Instructions are two bytes, so pad out a dangling final byte
+        let status = if program.len().is_multiple_of(2) {
+            LoadStatus::Loaded
+        } else {
+            let status = LoadStatus::Padded {
+                length: program.len(),
+                pad_byte: self.pad_byte,
+            };
+            program.push(self.pad_byte);
+            status
+        };
+
+        self.program = program;
+        self.reload_program();
+        Ok(status)
+    }
+
+    fn reload_program(&mut self) {
+        // Clear first so nothing is left past the end of a shorter program
+        self.clear_program_area();
+        let program = std::mem::take(&mut self.program);
+        self.load_at(uint::<12>::new(Self::PROGRAM_START as u16), &program)
+            .expect("program size is checked when it is loaded");
+        self.program = program;
+    }
+
+    fn clear_program_area(&mut self) {
+        self.memory[Self::PROGRAM_START..].fill(0);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
     use std::path::PathBuf;
 
     #[test]
@@ -161,4 +286,130 @@ mod tests {
 
         assert_eq!(vec![0x12, 0x4E], mmu.memory[0x200..0x202]); // Verify the first two bytes
     }
+
+    #[test]
+    fn pads_odd_length_program() {
+        let mut mmu = Chip8Mmu::new();
+        mmu.set_pad_byte(0xAB);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0x60, 0x01, 0x70]).unwrap();
+
+        let status = mmu.load_program(file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(
+            LoadStatus::Padded {
+                length: 3,
+                pad_byte: 0xAB
+            },
+            status
+        );
+        assert_eq!(vec![0x60, 0x01, 0x70, 0xAB], mmu.memory[0x200..0x204]);
+    }
+
+    #[test]
+    fn does_not_pad_even_length_program() {
+        let mut mmu = Chip8Mmu::new();
+        mmu.set_pad_byte(0xAB);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0x60, 0x01]).unwrap();
+
+        let status = mmu.load_program(file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(LoadStatus::Loaded, status);
+        assert_eq!(vec![0x60, 0x01, 0x00], mmu.memory[0x200..0x203]);
+    }
+
+    #[test]
+    fn reload_restores_modified_program() {
+        let mut mmu = Chip8Mmu::new();
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/test_opcode.ch8");
+        mmu.load_program(path.to_str().unwrap()).unwrap();
+        mmu.write_u16(uint::<12>::new(0x200), 0xFFFF);
+
+        mmu.reload_program();
+
+        assert_eq!(vec![0x12, 0x4E], mmu.memory[0x200..0x202]);
+    }
+
+    #[test]
+    fn loading_shorter_program_zeroes_old_tail() {
+        let mut mmu = Chip8Mmu::new();
+        mmu.load_program_bytes(&[0xAA; 8]).unwrap();
+
+        mmu.load_program_bytes(&[0x60, 0x01]).unwrap();
+
+        assert_eq!(vec![0x60, 0x01, 0, 0, 0, 0, 0, 0], mmu.memory[0x200..0x208]);
+    }
+
+    #[test]
+    fn reload_zeroes_memory_past_program() {
+        let mut mmu = Chip8Mmu::new();
+        mmu.load_program_bytes(&[0x60, 0x01]).unwrap();
+        mmu.write_u8(uint::<12>::new(0xFFF), 0xAA);
+
+        mmu.reload_program();
+
+        assert_eq!(0, mmu.memory[0xFFF]);
+        assert_eq!(0x20, mmu.memory[5]);
+    }
+
+    #[test]
+    fn rejects_empty_program() {
+        let mut mmu = Chip8Mmu::new();
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        let result = mmu.load_program(file.path().to_str().unwrap());
+
+        assert!(matches!(result, Err(LoadError::Empty)));
+    }
+
+    #[test]
+    fn loads_bytes_at_address() {
+        let mut mmu = Chip8Mmu::new();
+
+        mmu.load_at(uint::<12>::new(0x800), &[0xDE, 0xAD, 0xBE])
+            .unwrap();
+
+        assert_eq!(0xDEAD, mmu.read_u16(uint::<12>::new(0x800)));
+        assert_eq!(0xBE, mmu.read_u8(uint::<12>::new(0x802)));
+        assert_eq!(0x00, mmu.read_u8(uint::<12>::new(0x803)));
+    }
+
+    #[test]
+    fn load_at_rejects_bytes_past_end_of_memory() {
+        let mut mmu = Chip8Mmu::new();
+
+        assert!(matches!(
+            mmu.load_at(uint::<12>::new(0xFFE), &[0x01, 0x02, 0x03]),
+            Err(LoadError::TooLarge { size: 3, max: 2 })
+        ));
+        assert_eq!(0x00, mmu.read_u8(uint::<12>::new(0xFFE)));
+        mmu.load_at(uint::<12>::new(0xFFE), &[0x01, 0x02]).unwrap();
+        assert_eq!(0x0102, mmu.read_u16(uint::<12>::new(0xFFE)));
+    }
+
+    #[test]
+    fn loads_program_bytes() {
+        let mut mmu = Chip8Mmu::new();
+
+        mmu.load_program_bytes(&[0x60, 0x01, 0x70]).unwrap();
+
+        assert_eq!(vec![0x60, 0x01, 0x70, 0x00], mmu.memory[0x200..0x204]);
+    }
+
+    #[test]
+    fn rejects_oversized_program_bytes() {
+        let mut mmu = Chip8Mmu::new();
+
+        let result = mmu.load_program_bytes(&[0; 0xE01]);
+
+        assert!(matches!(
+            result,
+            Err(LoadError::TooLarge {
+                size: 0xE01,
+                max: 0xE00
+            })
+        ));
+    }
 }
@@ -0,0 +1,13 @@
+use clap::ValueEnum;
+
+/// The CHIP-8 variant being emulated, which decides how ambiguous or extended opcodes behave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Platform {
+    /// The original COSMAC VIP interpreter
+    #[default]
+    Chip8,
+    #[value(name = "schip")]
+    SuperChip,
+    #[value(name = "xochip")]
+    XoChip,
+}
@@ -0,0 +1,134 @@
+use crate::platform::Platform;
+
+/// Opcode behaviours that differ between CHIP-8 variants.
+///
+/// The default is this interpreter's historical mix rather than any one variant; the constructors
+/// give each variant's own set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX, as on the COSMAC VIP, rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    /// FX55 and FX65 leave I pointing past the last register transferred
+    pub memory_increments_index: bool,
+    /// BNNN jumps to XNN plus VX, as SCHIP's BXNN, rather than NNN plus V0
+    pub jump_uses_vx: bool,
+    /// Sprites crossing the edge of the screen are clipped rather than wrapped round. This is the
+    /// only setting for it: `--wrap-sprites` turns it off on top of the preset, and
+    /// `CoordinateMode` only moves the sprite's origin
+    pub display_clipping: bool,
+    /// 8XY1, 8XY2 and 8XY3 clear VF
+    pub vf_reset_on_logic: bool,
+}
+
+impl Quirks {
+    /// The COSMAC VIP interpreter.
+    pub fn chip8() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_index: true,
+            jump_uses_vx: false,
+            display_clipping: true,
+            vf_reset_on_logic: true,
+        }
+    }
+
+    /// SCHIP 1.1 on the HP48.
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_index: false,
+            jump_uses_vx: true,
+            display_clipping: true,
+            vf_reset_on_logic: false,
+        }
+    }
+
+    /// XO-CHIP, as in Octo.
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increments_index: true,
+            jump_uses_vx: false,
+            display_clipping: false,
+            vf_reset_on_logic: false,
+        }
+    }
+
+    /// The quirks of `platform`.
+    pub fn for_platform(platform: Platform) -> Quirks {
+        match platform {
+            Platform::Chip8 => Quirks::chip8(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+}
+
+/// Individual quirks to force on or off, whichever set they are applied to. `None` leaves a quirk
+/// as the set has it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuirkOverrides {
+    pub shift_uses_vy: Option<bool>,
+    pub memory_increments_index: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub display_clipping: Option<bool>,
+    pub vf_reset_on_logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    /// `quirks` with the overridden ones replaced.
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            shift_uses_vy: self.shift_uses_vy.unwrap_or(quirks.shift_uses_vy),
+            memory_increments_index: self
+                .memory_increments_index
+                .unwrap_or(quirks.memory_increments_index),
+            jump_uses_vx: self.jump_uses_vx.unwrap_or(quirks.jump_uses_vx),
+            display_clipping: self.display_clipping.unwrap_or(quirks.display_clipping),
+            vf_reset_on_logic: self.vf_reset_on_logic.unwrap_or(quirks.vf_reset_on_logic),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increments_index: false,
+            jump_uses_vx: false,
+            display_clipping: true,
+            vf_reset_on_logic: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_follow_platform() {
+        assert_eq!(Quirks::chip8(), Quirks::for_platform(Platform::Chip8));
+        assert_eq!(Quirks::schip(), Quirks::for_platform(Platform::SuperChip));
+        assert_eq!(Quirks::xochip(), Quirks::for_platform(Platform::XoChip));
+        assert!(!Quirks::xochip().display_clipping);
+    }
+
+    #[test]
+    fn overrides_replace_only_the_quirks_they_set() {
+        let overrides = QuirkOverrides {
+            vf_reset_on_logic: Some(true),
+            display_clipping: Some(true),
+            ..QuirkOverrides::default()
+        };
+
+        assert_eq!(
+            Quirks {
+                vf_reset_on_logic: true,
+                display_clipping: true,
+                ..Quirks::xochip()
+            },
+            overrides.apply(Quirks::xochip())
+        );
+    }
+}
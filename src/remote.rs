@@ -0,0 +1,185 @@
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::parse_key_event;
+use crate::window::{Hotkey, Window};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Wraps a window so the keypad can also be driven by another process over a Unix domain socket.
+///
+/// Each connection sends one message per line, `down <key>` or `up <key>`, with the key as a hex
+/// digit. A key counts as pressed if it is held on either the window or the socket.
+pub struct RemoteInputWindow {
+    window: Box<dyn Window>,
+    // Bit N is set while key N is held remotely
+    keys: Arc<AtomicU16>,
+}
+
+impl RemoteInputWindow {
+    /// Listen on `path`, replacing a stale socket left there by an earlier run.
+    pub fn new(window: Box<dyn Window>, path: &Path) -> io::Result<RemoteInputWindow> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        let keys = Arc::new(AtomicU16::new(0));
+
+        let thread_keys = Arc::clone(&keys);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let keys = Arc::clone(&thread_keys);
+                thread::spawn(move || {
+                    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                        match parse_key_event(&line) {
+                            Some((key, true)) => keys.fetch_or(1 << key, Ordering::Relaxed),
+                            Some((key, false)) => keys.fetch_and(!(1 << key), Ordering::Relaxed),
+                            None => {
+                                eprintln!("Ignoring remote input {:?}", line);
+                                continue;
+                            }
+                        };
+                    }
+                });
+            }
+        });
+
+        Ok(RemoteInputWindow { window, keys })
+    }
+
+    fn is_remote_key_pressed(&self, key: u8) -> bool {
+        key <= 0xF && self.keys.load(Ordering::Relaxed) & (1 << key) != 0
+    }
+}
+
+impl Window for RemoteInputWindow {
+    fn blank_screen(&mut self) {
+        self.window.blank_screen();
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        self.window.draw(x, y, width, sprite)
+    }
+
+    fn render(&mut self) {
+        self.window.render();
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn present(&mut self) {
+        self.window.present();
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.is_remote_key_pressed(key) || self.window.is_key_pressed(key)
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        let remote_key = (0..=0xF).find(|key| self.is_remote_key_pressed(*key));
+        match (remote_key, self.window.get_pressed_key()) {
+            (Some(remote_key), Some(key)) => Some(remote_key.min(key)),
+            (remote_key, key) => remote_key.or(key),
+        }
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        self.window.pressed_hotkey()
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.window.frame_hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        self.window.frame()
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        self.window.screenshot()
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.window.export_sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        self.window.clipped_draws()
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.window.scroll_up(n);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.window.scroll_down(n);
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.window.scroll_left(n);
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.window.scroll_right(n);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8) {
+        self.window.set_clip(x, y, width, height);
+    }
+
+    fn clear_clip(&mut self) {
+        self.window.clear_clip();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.window.set_wrap(wrap);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.window.set_hires(hires);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.window.set_planes(planes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::HeadlessWindow;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn socket_messages_press_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.sock");
+        let window = RemoteInputWindow::new(Box::new(HeadlessWindow::new()), &path).unwrap();
+        let mut stream = UnixStream::connect(&path).unwrap();
+
+        stream.write_all(b"down 5\ndown c\nup c\n").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while window.keys.load(Ordering::Relaxed) != 1 << 5 {
+            assert!(Instant::now() < deadline, "key state never updated");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(window.is_key_pressed(0x5));
+        assert!(!window.is_key_pressed(0xC));
+        assert_eq!(Some(0x5), window.get_pressed_key());
+    }
+}
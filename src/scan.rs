@@ -0,0 +1,129 @@
+use super::instruction::Instruction;
+use crate::mmu::Chip8Mmu;
+use crate::platform::Platform;
+use std::collections::BTreeMap;
+
+/// Decode (without executing) the straight-line path through a ROM starting at its first
+/// instruction and count each instruction by pattern.
+///
+/// Forward jumps are followed. The scan stops after the first backward jump (which includes the
+/// classic `1NNN` self-jump halt), call, return, computed jump, or undecodable word, or when it
+/// runs off the end of the ROM.
+pub fn count_instructions(rom: &[u8]) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    let mut offset = 0;
+
+    // A forward jump can land past the end of the ROM, which ends the scan like running off it
+    while let Some((instruction, length)) = rom.get(offset..).and_then(Instruction::decode_bytes) {
+        *counts.entry(instruction.pattern()).or_insert(0) += 1;
+
+        match instruction {
+            Instruction::Jump(target) => {
+                let target = usize::from(target);
+                if target <= Chip8Mmu::PROGRAM_START + offset {
+                    break;
+                }
+                offset = target - Chip8Mmu::PROGRAM_START;
+            }
+            Instruction::Sys(_)
+            | Instruction::Ret
+            | Instruction::Call(_)
+            | Instruction::JumpV0(_)
+            | Instruction::Unknown(_) => break,
+            _ => offset += length,
+        }
+    }
+
+    counts
+}
+
+/// Guess which variant a ROM was written for from opcodes only that variant has. Every aligned
+/// word is checked, so data that happens to look like one of these opcodes can mislead it.
+///
+/// SCHIP: 00FE, 00FF, DXY0, FX30, FX75. XO-CHIP: F000, 5XY2, FN01. XO-CHIP wins if both appear,
+/// as it is a superset. Return None for a ROM with neither.
+pub fn detect_platform(rom: &[u8]) -> Option<Platform> {
+    let mut detected = None;
+    for word in rom.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([word[0], word[1]]);
+        match opcode {
+            0xF000 => return Some(Platform::XoChip),
+            _ if opcode & 0xF00F == 0x5002 || opcode & 0xF0FF == 0xF001 => {
+                return Some(Platform::XoChip)
+            }
+            0x00FE | 0x00FF => detected = Some(Platform::SuperChip),
+            _ if opcode & 0xF00F == 0xD000 || matches!(opcode & 0xF0FF, 0xF030 | 0xF075) => {
+                detected = Some(Platform::SuperChip)
+            }
+            _ => (),
+        }
+    }
+    detected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_on_self_jump() {
+        let rom = [0x60, 0x01, 0x60, 0x02, 0x12, 0x04, 0x00, 0xE0];
+
+        let counts = count_instructions(&rom);
+
+        assert_eq!(Some(&2), counts.get("6XNN"));
+        assert_eq!(Some(&1), counts.get("1NNN"));
+        assert_eq!(None, counts.get("00E0"));
+    }
+
+    #[test]
+    fn follows_forward_jumps() {
+        let rom = [0x12, 0x04, 0x00, 0xE0, 0xA2, 0x00, 0x00, 0xEE];
+
+        let counts = count_instructions(&rom);
+
+        assert_eq!(Some(&1), counts.get("ANNN"));
+        assert_eq!(Some(&1), counts.get("00EE"));
+        assert_eq!(None, counts.get("00E0"));
+    }
+
+    #[test]
+    fn stops_on_jump_past_end_of_rom() {
+        let rom = [0x13, 0x00];
+
+        let counts = count_instructions(&rom);
+
+        assert_eq!(Some(&1), counts.get("1NNN"));
+        assert_eq!(1, counts.len());
+    }
+
+    #[test]
+    fn stops_at_end_of_rom() {
+        let rom = [0x60, 0x01, 0x70, 0x01, 0x80];
+
+        let counts = count_instructions(&rom);
+
+        assert_eq!(2, counts.values().sum::<usize>());
+    }
+
+    #[test]
+    fn detects_platform_from_opcodes() {
+        assert_eq!(None, detect_platform(&[0x60, 0x01, 0xD0, 0x15, 0x12, 0x04]));
+        assert_eq!(None, detect_platform(&[0x01, 0xFF]));
+        assert_eq!(
+            Some(Platform::SuperChip),
+            detect_platform(&[0x00, 0xFF, 0x60, 0x01])
+        );
+        assert_eq!(Some(Platform::SuperChip), detect_platform(&[0xD0, 0x10]));
+        assert_eq!(Some(Platform::SuperChip), detect_platform(&[0xF3, 0x75]));
+        assert_eq!(
+            Some(Platform::XoChip),
+            detect_platform(&[0xF0, 0x00, 0x12, 0x34])
+        );
+        assert_eq!(Some(Platform::XoChip), detect_platform(&[0x51, 0x22]));
+        assert_eq!(
+            Some(Platform::XoChip),
+            detect_platform(&[0x00, 0xFE, 0xF2, 0x01])
+        );
+    }
+}
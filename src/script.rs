@@ -0,0 +1,150 @@
+use crate::audio::NullAudio;
+use crate::config::Config;
+use crate::cpu::{Cpu, CpuError};
+use crate::keypad::parse_key_event;
+use crate::machine::FramePacer;
+use crate::mmu::{Chip8Mmu, LoadError, Mmu};
+use crate::window::HeadlessWindow;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// Key presses and releases to replay, each after a given number of instructions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    // (cycle, key, is_down), in cycle order
+    events: Vec<(usize, u8, bool)>,
+}
+
+impl InputScript {
+    /// Parse one event per line, `<cycle> down <key>` or `<cycle> up <key>` with the key as a hex
+    /// digit. The event happens once `cycle` instructions have run. Blank lines and lines starting
+    /// with `#` are ignored.
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {}: expected <cycle> down|up <key>", number + 1);
+            let (cycle, event) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let cycle = cycle.parse().map_err(|_| invalid())?;
+            let (key, is_down) = parse_key_event(event).ok_or_else(invalid)?;
+            events.push((cycle, key, is_down));
+        }
+        // Stable, so events on the same cycle keep their order
+        events.sort_by_key(|(cycle, _, _)| *cycle);
+        Ok(InputScript { events })
+    }
+}
+
+/// A headless CPU stepped one instruction at a time, with the keypad driven by an input script
+/// and the timers ticked as often as the config's frequency would.
+pub(crate) struct ScriptedCpu {
+    pub cpu: Cpu,
+    keys: Arc<AtomicU16>,
+    events: Vec<(usize, u8, bool)>,
+    next_event: usize,
+    frequency: u32,
+    pacer: FramePacer,
+    cycle: usize,
+}
+
+impl ScriptedCpu {
+    pub fn new(
+        rom: &[u8],
+        config: &Config,
+        input_script: &InputScript,
+    ) -> Result<ScriptedCpu, LoadError> {
+        let mut mmu = Box::new(Chip8Mmu::new());
+        mmu.set_pad_byte(config.pad_byte);
+        mmu.load_program_bytes(rom)?;
+        crate::apply_pokes(mmu.as_mut(), config);
+        let window = HeadlessWindow::new();
+        let keys = window.keys();
+        let quirks = config.quirks.unwrap_or_default();
+        let mut cpu = Cpu::new(mmu, Box::new(window), Box::new(NullAudio), quirks);
+        crate::configure_cpu(&mut cpu, config, rom);
+
+        let mut scripted = ScriptedCpu {
+            cpu,
+            keys,
+            events: input_script.events.clone(),
+            next_event: 0,
+            // Every step runs an instruction, so there has to be at least one per second
+            frequency: config.frequency.max(1),
+            pacer: FramePacer::default(),
+            cycle: 0,
+        };
+        scripted.pacer.start_frame(scripted.frequency);
+        scripted.finish_frames();
+        Ok(scripted)
+    }
+
+    /// Apply the input events due before the next instruction, run it, then tick the timers if
+    /// that used up the frame's cycles.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        self.cycle += 1;
+        while let Some((_, key, is_down)) = self
+            .events
+            .get(self.next_event)
+            .filter(|(at, _, _)| *at < self.cycle)
+        {
+            if *is_down {
+                self.keys.fetch_or(1 << key, Ordering::Relaxed);
+            } else {
+                self.keys.fetch_and(!(1 << key), Ordering::Relaxed);
+            }
+            self.next_event += 1;
+        }
+        let cycles = self.cpu.stats().cycles;
+        self.cpu.step()?;
+        self.pacer.spend(self.cpu.stats().cycles - cycles);
+        self.finish_frames();
+        Ok(())
+    }
+
+    // End each frame that has no whole cycle left by ticking the timers, as `Chip8::run_frame`
+    // does. Below 60hz that is several frames per instruction
+    fn finish_frames(&mut self) {
+        while !self.pacer.has_cycle() {
+            self.cpu.run_60hz_cycle();
+            self.pacer.start_frame(self.frequency);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_input_script() {
+        let script = InputScript::parse("20 up a\n\n10 down a\n").unwrap();
+        assert_eq!(vec![(10, 0xA, true), (20, 0xA, false)], script.events);
+
+        assert_eq!(
+            Err("line 1: expected <cycle> down|up <key>".to_string()),
+            InputScript::parse("down a")
+        );
+        assert!(InputScript::parse("5 press a").is_err());
+    }
+
+    #[test]
+    fn ticks_timers_at_60hz_below_60hz() {
+        let config = Config {
+            frequency: 30,
+            ..Config::default()
+        };
+        // LD V0, 10; LD DT, V0; then spin
+        let rom = [0x60, 0x0A, 0xF0, 0x15, 0x12, 0x04];
+        let mut scripted = ScriptedCpu::new(&rom, &config, &InputScript::default()).unwrap();
+
+        for _ in 0..5 {
+            scripted.step().unwrap();
+        }
+
+        // Set to 10 by the second instruction, then ticked twice after it and each one since
+        assert_eq!(2, scripted.cpu.save_state().delay_timer);
+    }
+}
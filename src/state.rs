@@ -0,0 +1,320 @@
+use crate::cpu::Cpu;
+use crate::mmu::Chip8Mmu;
+use arbintrary::uint;
+use std::error::Error;
+use std::fmt;
+
+/// Why a save-state couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the save-state magic, so it isn't a save-state
+    BadMagic,
+    /// The save-state was written in a format this version can't read
+    UnsupportedVersion(u16),
+    /// The data ends before the save-state does
+    Truncated,
+    /// A field holds a value no CPU could be in, e.g. a stack deeper than 16
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save-state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save-state version {} is not supported, expected {}",
+                version,
+                MachineState::VERSION
+            ),
+            StateError::Truncated => write!(f, "save-state is truncated"),
+            StateError::Invalid(field) => write!(f, "save-state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Everything needed to resume a CPU where it left off, apart from the display.
+///
+/// On disk this is the magic `C8ST`, a version, then each field in declaration order. Multi-byte
+/// values are little-endian whatever the host, so a save-state loads on any machine. With the
+/// `serde` feature it can also go through any serde format, with addresses as plain numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineState {
+    pub registers: [u8; 16],
+    #[cfg_attr(feature = "serde", serde(with = "address"))]
+    pub index: uint<12>,
+    #[cfg_attr(feature = "serde", serde(with = "address"))]
+    pub program_counter: uint<12>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    #[cfg_attr(feature = "serde", serde(with = "addresses"))]
+    pub stack: Vec<uint<12>>,
+    pub key_latch: Option<u8>,
+    /// SCHIP's HP48 flag registers
+    pub flags: [u8; Cpu::FLAG_COUNT],
+    /// Whether the display is in SCHIP's 128x64 mode
+    pub hires: bool,
+    /// The XO-CHIP bit planes selected with FN01
+    pub selected_planes: u8,
+    pub memory: Vec<u8>,
+}
+
+impl MachineState {
+    const MAGIC: &'static [u8; 4] = b"C8ST";
+    const VERSION: u16 = 1;
+    const MAX_STACK: usize = 16;
+    // Stored in place of a key when none is latched
+    const NO_KEY: u8 = 0xFF;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 + self.memory.len());
+        bytes.extend_from_slice(Self::MAGIC);
+        bytes.extend_from_slice(&Self::VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.registers);
+        bytes.extend_from_slice(&u16::from(self.index).to_le_bytes());
+        bytes.extend_from_slice(&u16::from(self.program_counter).to_le_bytes());
+        bytes.push(self.delay_timer);
+        bytes.push(self.sound_timer);
+        bytes.push(self.stack.len() as u8);
+        for address in &self.stack {
+            bytes.extend_from_slice(&u16::from(*address).to_le_bytes());
+        }
+        bytes.push(self.key_latch.unwrap_or(Self::NO_KEY));
+        bytes.extend_from_slice(&self.flags);
+        bytes.push(self.hires as u8);
+        bytes.push(self.selected_planes);
+        bytes.extend_from_slice(&self.memory);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MachineState, StateError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4).map_err(|_| StateError::BadMagic)? != Self::MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != Self::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.take(16)?);
+        let index = reader.address("index")?;
+        let program_counter = reader.address("program counter")?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let depth = reader.u8()? as usize;
+        if depth > Self::MAX_STACK {
+            return Err(StateError::Invalid("stack depth"));
+        }
+        let stack = (0..depth)
+            .map(|_| reader.address("stack entry"))
+            .collect::<Result<_, _>>()?;
+        let key_latch = match reader.u8()? {
+            Self::NO_KEY => None,
+            key if key <= 0xF => Some(key),
+            _ => return Err(StateError::Invalid("latched key")),
+        };
+        let mut flags = [0; Cpu::FLAG_COUNT];
+        flags.copy_from_slice(reader.take(Cpu::FLAG_COUNT)?);
+        let hires = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::Invalid("resolution")),
+        };
+        let selected_planes = reader.u8()?;
+        if selected_planes > 0b11 {
+            return Err(StateError::Invalid("selected planes"));
+        }
+        let memory = reader.take(Chip8Mmu::MEM_SIZE)?.to_vec();
+
+        Ok(MachineState {
+            registers,
+            index,
+            program_counter,
+            delay_timer,
+            sound_timer,
+            stack,
+            key_latch,
+            flags,
+            hires,
+            selected_planes,
+            memory,
+        })
+    }
+}
+
+// A 12-bit address as serde sees it: a u16 no greater than 0xFFF
+#[cfg(feature = "serde")]
+mod address {
+    use arbintrary::uint;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(address: &uint<12>, serializer: S) -> Result<S::Ok, S::Error> {
+        u16::from(*address).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<uint<12>, D::Error> {
+        match u16::deserialize(deserializer)? {
+            address if address <= 0xFFF => Ok(uint::<12>::new(address)),
+            address => Err(D::Error::custom(format!(
+                "address {:#X} is past 0xFFF",
+                address
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod addresses {
+    use arbintrary::uint;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Address(#[serde(with = "super::address")] uint<12>);
+
+    pub fn serialize<S: Serializer>(
+        addresses: &[uint<12>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(addresses.iter().map(|address| Address(*address)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<uint<12>>, D::Error> {
+        let addresses = Vec::<Address>::deserialize(deserializer)?;
+        Ok(addresses
+            .into_iter()
+            .map(|Address(address)| address)
+            .collect())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn address(&mut self, field: &'static str) -> Result<uint<12>, StateError> {
+        match self.u16()? {
+            address if address <= 0xFFF => Ok(uint::<12>::new(address)),
+            _ => Err(StateError::Invalid(field)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> MachineState {
+        MachineState {
+            registers: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0xFF],
+            index: uint::<12>::new(0x123),
+            program_counter: uint::<12>::new(0x2A4),
+            delay_timer: 30,
+            sound_timer: 4,
+            stack: vec![uint::<12>::new(0x202), uint::<12>::new(0x3FE)],
+            key_latch: Some(0xA),
+            flags: [1, 2, 3, 4, 5, 6, 7, 0x80],
+            hires: true,
+            selected_planes: 0b10,
+            memory: (0..Chip8Mmu::MEM_SIZE).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let state = state();
+
+        assert_eq!(
+            Ok(state.clone()),
+            MachineState::from_bytes(&state.to_bytes())
+        );
+    }
+
+    #[test]
+    fn layout_is_little_endian() {
+        let bytes = state().to_bytes();
+
+        assert_eq!(b"C8ST", &bytes[..4]);
+        assert_eq!([0x01, 0x00], bytes[4..6]);
+        // Index then program counter, after the 16 registers
+        assert_eq!([0x23, 0x01, 0xA4, 0x02], bytes[22..26]);
+        // Flags, resolution and planes follow the stack and latched key
+        assert_eq!([1, 2, 3, 4, 5, 6, 7, 0x80, 1, 0b10], bytes[34..44]);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = state().to_bytes();
+        bytes[0] = b'X';
+
+        assert_eq!(Err(StateError::BadMagic), MachineState::from_bytes(&bytes));
+        assert_eq!(Err(StateError::BadMagic), MachineState::from_bytes(b"C8"));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = state().to_bytes();
+        bytes[4] = 2;
+
+        assert_eq!(
+            Err(StateError::UnsupportedVersion(2)),
+            MachineState::from_bytes(&bytes)
+        );
+    }
+
+    #[test]
+    fn rejects_unselectable_planes() {
+        let mut state = state();
+        state.selected_planes = 0b100;
+
+        assert_eq!(
+            Err(StateError::Invalid("selected planes")),
+            MachineState::from_bytes(&state.to_bytes())
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_state() {
+        let bytes = state().to_bytes();
+
+        assert_eq!(
+            Err(StateError::Truncated),
+            MachineState::from_bytes(&bytes[..bytes.len() - 1])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializing_rejects_addresses_past_memory() {
+        let mut json: serde_json::Value = serde_json::to_value(state()).unwrap();
+        json["index"] = 0x1000.into();
+
+        assert!(serde_json::from_value::<MachineState>(json).is_err());
+    }
+}
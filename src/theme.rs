@@ -0,0 +1,154 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+
+/// Named foreground/background colour presets for the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Theme {
+    #[default]
+    Amber,
+    /// Classic green phosphor
+    Green,
+    White,
+    /// Dark on light, like an old handheld LCD
+    Lcd,
+}
+
+impl Theme {
+    /// The (foreground, background) pair as 0RGB colours.
+    pub fn colors(self) -> (u32, u32) {
+        match self {
+            Theme::Amber => (0x00FFBF00, 0x00000000),
+            Theme::Green => (0x0033FF33, 0x00000000),
+            Theme::White => (0x00FFFFFF, 0x00000000),
+            Theme::Lcd => (0x000F380F, 0x009BBC0F),
+        }
+    }
+
+    /// The theme as an XO-CHIP palette, as `Palette::from_colors` extends it.
+    pub fn palette(self) -> Palette {
+        let (foreground, background) = self.colors();
+        Palette::from_colors(foreground, background)
+    }
+}
+
+/// The four 0RGB colours of an XO-CHIP display, one for each combination of its two bit planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    colors: [u32; 4],
+}
+
+impl Palette {
+    pub fn new(colors: [u32; 4]) -> Palette {
+        Palette { colors }
+    }
+
+    /// Extend a foreground and background pair to a palette: the background, the foreground for
+    /// plane 1, a blue for plane 2 and a blend of the two where both planes are lit.
+    pub fn from_colors(foreground: u32, background: u32) -> Palette {
+        let plane_2 = 0x0055AAFF;
+        let blend = [16, 8, 0].iter().fold(0, |blend, shift| {
+            let channel = (((foreground >> shift) & 0xFF) + ((plane_2 >> shift) & 0xFF)) / 2;
+            blend | (channel << shift)
+        });
+        Palette {
+            colors: [background, foreground, plane_2, blend],
+        }
+    }
+
+    /// The colour of a pixel whose plane 1 bit is bit 0 of `planes` and plane 2 bit is bit 1.
+    pub fn color(&self, planes: u8) -> u32 {
+        self.colors[(planes & 0b11) as usize]
+    }
+
+    /// This palette with the colour for `planes` replaced by `color`.
+    pub fn with_color(mut self, planes: u8, color: u32) -> Palette {
+        self.colors[(planes & 0b11) as usize] = color;
+        self
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Theme::default().palette()
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        let invalid = || "expected four RRGGBB colours separated by commas".to_string();
+        let mut colors = [0; 4];
+        let mut parts = s.split(',');
+        for color in colors.iter_mut() {
+            let hex = parts.next().ok_or_else(invalid)?.trim();
+            let hex = hex.strip_prefix('#').unwrap_or(hex);
+            if hex.len() != 6 {
+                return Err(invalid());
+            }
+            *color = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Palette { colors })
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [c0, c1, c2, c3] = self.colors;
+        write!(f, "{:06X},{:06X},{:06X},{:06X}", c0, c1, c2, c3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Theme::Amber, 0x00FFBF00, 0x00000000)]
+    #[case(Theme::Green, 0x0033FF33, 0x00000000)]
+    #[case(Theme::White, 0x00FFFFFF, 0x00000000)]
+    #[case(Theme::Lcd, 0x000F380F, 0x009BBC0F)]
+    fn theme_colors(#[case] theme: Theme, #[case] fg: u32, #[case] bg: u32) {
+        assert_eq!((fg, bg), theme.colors());
+    }
+
+    #[rstest]
+    #[case(0b00, 0x000000)]
+    #[case(0b01, 0xFF0000)]
+    #[case(0b10, 0x00FF00)]
+    #[case(0b11, 0x0000FF)]
+    fn palette_maps_plane_states(#[case] planes: u8, #[case] color: u32) {
+        let palette: Palette = "000000,FF0000,00ff00,#0000FF".parse().unwrap();
+
+        assert_eq!(color, palette.color(planes));
+    }
+
+    #[rstest]
+    #[case("000000,FF0000,00FF00")]
+    #[case("000000,FF0000,00FF00,0000FF,FFFFFF")]
+    #[case("000000,FF0000,00FF00,0000F")]
+    #[case("000000,FF0000,00FF00,GGGGGG")]
+    fn rejects_bad_palettes(#[case] palette: &str) {
+        assert!(palette.parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn theme_palette_extends_theme_colors() {
+        let palette = Theme::Green.palette();
+
+        assert_eq!(0x00000000, palette.color(0b00));
+        assert_eq!(0x0033FF33, palette.color(0b01));
+        assert_eq!(0x0044D499, palette.color(0b11));
+        assert_eq!("000000,33FF33,55AAFF,44D499", palette.to_string());
+    }
+
+    #[test]
+    fn default_is_amber() {
+        assert_eq!(Theme::Amber, Theme::default());
+    }
+}
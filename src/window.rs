@@ -1,125 +1,797 @@
-use minifb::WindowOptions;
+use crate::capture::FrameRecorder;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::theme::Palette;
+use minifb::{KeyRepeat, WindowOptions};
 #[cfg(test)]
 use mockall::{automock, predicate::*};
-use std::process;
+#[cfg(any(test, feature = "test-support"))]
+use std::cell::RefCell;
+use std::fmt;
+#[cfg(any(test, feature = "test-support"))]
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// Emulator controls bound to keys outside the CHIP-8 keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    /// F1
+    SoftReset,
+    /// Shift+F1
+    HardReset,
+    /// F12
+    Screenshot,
+    /// F2
+    Mute,
+}
 
 #[cfg_attr(test, automock)]
 pub trait Window {
     fn blank_screen(&mut self);
 
-    /// Draw a sprite on the screen. Return true if a collision has occurred.
-    fn draw(&mut self, x: u8, y: u8, sprite: Vec<u8>) -> bool;
+    /// Draw a sprite `width` pixels wide on the screen, 8 for CHIP-8 sprites and 16 for SCHIP's
+    /// large ones. Each row takes `width` rounded up to whole bytes, most significant bit
+    /// leftmost. Return true if a collision has occurred.
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool;
 
     fn render(&mut self);
 
+    /// False once the user has closed the window. Rendering a closed window does nothing.
+    fn is_open(&self) -> bool;
+
+    /// Make everything drawn so far visible and push it to the screen. With double buffering
+    /// this is the only way changes become visible; `render` calls it once per frame.
+    fn present(&mut self);
+
     fn is_key_pressed(&self, key: u8) -> bool;
 
     fn get_pressed_key(&self) -> Option<u8>;
+
+    /// The hotkey pressed since the last render, if any.
+    fn pressed_hotkey(&self) -> Option<Hotkey>;
+
+    /// A stable hash of the logical display, for comparing frames in tests.
+    fn frame_hash(&self) -> u64;
+
+    /// The logical display.
+    fn frame(&self) -> &FrameBuffer;
+
+    /// A copy of the display as shown, as 0RGB pixels at its logical resolution with
+    /// `frame().width()` pixels per row. Windows that show nothing use the default colours.
+    fn screenshot(&self) -> Vec<u32>;
+
+    /// Read a `width` by `height` region of the display back as sprite bytes, in the layout
+    /// `draw` takes, so drawing them at `x`, `y` on a blank screen reproduces the region.
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8>;
+
+    /// How many draws so far had pixels cut off at the edge of the screen.
+    fn clipped_draws(&self) -> u64;
+
+    /// Scroll the display up by `n` pixels, leaving blank rows at the bottom.
+    fn scroll_up(&mut self, n: u8);
+
+    /// Scroll the display down by `n` pixels, leaving blank rows at the top.
+    fn scroll_down(&mut self, n: u8);
+
+    /// Scroll the display left by `n` pixels, leaving blank columns at the right.
+    fn scroll_left(&mut self, n: u8);
+
+    /// Scroll the display right by `n` pixels, leaving blank columns at the left.
+    fn scroll_right(&mut self, n: u8);
+
+    /// Change the title of the window, if there is one.
+    fn set_title(&mut self, title: &str);
+
+    /// Restrict drawing and clearing to a rectangle of the screen.
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8);
+
+    /// Allow drawing to the whole screen again.
+    fn clear_clip(&mut self);
+
+    /// Wrap sprites that cross an edge round to the opposite edge instead of clipping them.
+    fn set_wrap(&mut self, wrap: bool);
+
+    /// Switch the display between 64x32 and SCHIP's 128x64, clearing it.
+    fn set_hires(&mut self, hires: bool);
+
+    /// Select the XO-CHIP bit planes that draws, clears and scrolls act on: bit 0 for plane 1 and
+    /// bit 1 for plane 2.
+    fn set_planes(&mut self, planes: u8);
+}
+
+/// Decides when changes to the logical display become visible.
+///
+/// Every change (draw, clear or scroll) normally presents straight away, so each render shows the
+/// latest display. With a limit, only the first `limit` changes in a 60hz frame present. Later
+/// changes still update the logical display, and are coalesced into a single present at the start
+/// of the next frame, which counts toward that frame's limit.
+///
+/// When double buffered, changes never present by themselves and wait for an explicit `present`.
+///
+/// Only a limit or double buffering can hold changes back, so without either the logical display
+/// is shown as is and no copy of it is kept.
+#[derive(Debug, Default)]
+struct Presenter {
+    presented: FrameBuffer,
+    limit: Option<u32>,
+    double_buffered: bool,
+    changes_this_frame: u32,
+    pending: bool,
+    presents: u64,
+}
+
+impl Presenter {
+    fn set_limit(&mut self, limit: Option<u32>, frame: &FrameBuffer) {
+        self.limit = limit;
+        self.presented.clone_from(frame);
+    }
+
+    /// Whether changes can be held back, so the shown display needs its own copy.
+    fn keeps_copy(&self) -> bool {
+        self.limit.is_some() || self.double_buffered
+    }
+
+    /// The display as currently shown, given the logical display `frame`.
+    fn shown<'a>(&'a self, frame: &'a FrameBuffer) -> &'a FrameBuffer {
+        if self.keeps_copy() {
+            &self.presented
+        } else {
+            frame
+        }
+    }
+
+    fn set_double_buffered(&mut self, double_buffered: bool, frame: &FrameBuffer) {
+        self.double_buffered = double_buffered;
+        self.presented.clone_from(frame);
+    }
+
+    /// Record a change to `frame`. Return true if it was presented.
+    fn changed(&mut self, frame: &FrameBuffer) -> bool {
+        if self.double_buffered {
+            self.pending = true;
+            return false;
+        }
+        if let Some(limit) = self.limit {
+            if self.changes_this_frame >= limit {
+                self.pending = true;
+                return false;
+            }
+        }
+
+        self.changes_this_frame += 1;
+        if self.keeps_copy() {
+            self.presented.clone_from(frame);
+        }
+        self.presents += 1;
+        true
+    }
+
+    /// Start a new frame once the presented display has been shown. Return true if a coalesced
+    /// change was presented.
+    fn end_frame(&mut self, frame: &FrameBuffer) -> bool {
+        self.changes_this_frame = 0;
+        if !self.pending {
+            return false;
+        }
+        self.pending = false;
+        self.changed(frame)
+    }
+
+    /// Flip the back buffer `frame` to the front if double buffered. Return true if anything
+    /// changed.
+    fn present(&mut self, frame: &FrameBuffer) -> bool {
+        if !self.double_buffered || !self.pending {
+            return false;
+        }
+        self.pending = false;
+        self.presented.clone_from(frame);
+        self.presents += 1;
+        true
+    }
+}
+
+/// A window that keeps the display in memory, for running without a screen. No keys are pressed
+/// unless set through `keys`.
+#[derive(Debug, Default)]
+pub struct HeadlessWindow {
+    frame: FrameBuffer,
+    presenter: Presenter,
+    // Bit N is set while key N is held
+    keys: Arc<AtomicU16>,
+}
+
+impl HeadlessWindow {
+    pub fn new() -> HeadlessWindow {
+        HeadlessWindow::default()
+    }
+
+    /// Present at most `limit` display changes per 60hz frame. See
+    /// `MiniFbWindow::set_present_limit`.
+    pub fn set_present_limit(&mut self, limit: Option<u32>) {
+        self.presenter.set_limit(limit, &self.frame);
+    }
+
+    /// Only show changes when `present` is called. See `MiniFbWindow::set_double_buffered`.
+    pub fn set_double_buffered(&mut self, double_buffered: bool) {
+        self.presenter
+            .set_double_buffered(double_buffered, &self.frame);
+    }
+
+    /// The held keys, bit N for key N. Shared so that keys can still be pressed once the window
+    /// has been handed to a `Cpu`.
+    pub fn keys(&self) -> Arc<AtomicU16> {
+        Arc::clone(&self.keys)
+    }
+
+    /// How many times the display has been presented.
+    pub fn presents(&self) -> u64 {
+        self.presenter.presents
+    }
+
+    /// Load a display directly, as `FrameBuffer::WIDTH * FrameBuffer::HEIGHT` pixels in
+    /// row-major order.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn set_framebuffer(&mut self, pixels: &[bool]) {
+        self.frame.set_pixels(pixels);
+    }
+
+    /// The display as text, see `FrameBuffer::text`.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn framebuffer_text(&self) -> String {
+        self.frame.text()
+    }
+}
+
+impl Window for HeadlessWindow {
+    fn blank_screen(&mut self) {
+        self.frame.clear();
+        self.presenter.changed(&self.frame);
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        let collision = self.frame.draw(x, y, width, &sprite);
+        self.presenter.changed(&self.frame);
+        collision
+    }
+
+    fn render(&mut self) {
+        self.present();
+        self.presenter.end_frame(&self.frame);
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    fn present(&mut self) {
+        self.presenter.present(&self.frame);
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        key <= 0xF && self.keys.load(Ordering::Relaxed) & (1 << key) != 0
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        (0..=0xF).find(|key| self.is_key_pressed(*key))
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        None
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.frame.hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        DisplayColors::default().paint(self.presenter.shown(&self.frame), 1)
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.frame.sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        self.frame.clipped_draws()
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.frame.scroll_up(n as usize);
+        self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.frame.scroll_down(n as usize);
+        self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.frame.scroll_left(n as usize);
+        self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.frame.scroll_right(n as usize);
+        self.presenter.changed(&self.frame);
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8) {
+        self.frame
+            .set_clip(x as usize, y as usize, width as usize, height as usize);
+    }
+
+    fn clear_clip(&mut self) {
+        self.frame.clear_clip();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.frame.set_wrap(wrap);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.frame.set_hires(hires);
+        self.presenter.changed(&self.frame);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.frame.set_planes(planes);
+    }
+}
+
+/// A window operation recorded by `RecordingWindow`.
+#[cfg(any(test, feature = "test-support"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowCall {
+    BlankScreen,
+    Draw {
+        x: u8,
+        y: u8,
+        width: u8,
+        sprite: Vec<u8>,
+    },
+    ScrollUp(u8),
+    ScrollDown(u8),
+    ScrollLeft(u8),
+    ScrollRight(u8),
+    SetHires(bool),
+    SetPlanes(u8),
+}
+
+/// A window that only logs the drawing operations made on it, so tests can assert exactly what a
+/// ROM did to the screen. There is no real display: draws never collide and `frame` stays blank.
+#[cfg(any(test, feature = "test-support"))]
+#[derive(Debug, Default)]
+pub struct RecordingWindow {
+    calls: Rc<RefCell<Vec<WindowCall>>>,
+    frame: FrameBuffer,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl RecordingWindow {
+    pub fn new() -> RecordingWindow {
+        RecordingWindow::default()
+    }
+
+    /// The log of calls, shared so that it can still be read once the window has been handed to
+    /// a `Cpu`.
+    pub fn calls(&self) -> Rc<RefCell<Vec<WindowCall>>> {
+        Rc::clone(&self.calls)
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl Window for RecordingWindow {
+    fn blank_screen(&mut self) {
+        self.calls.borrow_mut().push(WindowCall::BlankScreen);
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        self.calls.borrow_mut().push(WindowCall::Draw {
+            x,
+            y,
+            width,
+            sprite,
+        });
+        false
+    }
+
+    fn render(&mut self) {}
+
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    fn present(&mut self) {}
+
+    fn is_key_pressed(&self, _key: u8) -> bool {
+        false
+    }
+
+    fn get_pressed_key(&self) -> Option<u8> {
+        None
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        None
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.frame.hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        DisplayColors::default().paint(&self.frame, 1)
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.frame.sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        0
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.calls.borrow_mut().push(WindowCall::ScrollUp(n));
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.calls.borrow_mut().push(WindowCall::ScrollDown(n));
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.calls.borrow_mut().push(WindowCall::ScrollLeft(n));
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.calls.borrow_mut().push(WindowCall::ScrollRight(n));
+    }
+
+    fn set_title(&mut self, _title: &str) {}
+
+    fn set_clip(&mut self, _x: u8, _y: u8, _width: u8, _height: u8) {}
+
+    fn clear_clip(&mut self) {}
+
+    fn set_wrap(&mut self, _wrap: bool) {}
+
+    fn set_hires(&mut self, hires: bool) {
+        self.calls.borrow_mut().push(WindowCall::SetHires(hires));
+        self.frame.set_hires(hires);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.calls.borrow_mut().push(WindowCall::SetPlanes(planes));
+        self.frame.set_planes(planes);
+    }
+}
+
+/// Scale each channel of a 0RGB colour down by `amount`, from 0 (unchanged) to 1 (black).
+fn darken(color: u32, amount: f32) -> u32 {
+    let scale = 1.0 - amount.clamp(0.0, 1.0);
+    [16, 8, 0].iter().fold(0, |darkened, shift| {
+        let channel = ((color >> shift) & 0xFF) as f32 * scale;
+        darkened | ((channel.round() as u32) << shift)
+    })
+}
+
+/// The shape of each presented CHIP-8 pixel, as width:height. Some original displays had pixels
+/// that were wider than they were tall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelAspect {
+    width: usize,
+    height: usize,
+}
+
+impl PixelAspect {
+    const MAX: usize = 4;
+
+    pub fn new(width: usize, height: usize) -> Option<PixelAspect> {
+        if (1..=Self::MAX).contains(&width) && (1..=Self::MAX).contains(&height) {
+            Some(PixelAspect { width, height })
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for PixelAspect {
+    fn default() -> PixelAspect {
+        PixelAspect {
+            width: 1,
+            height: 1,
+        }
+    }
+}
+
+impl FromStr for PixelAspect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PixelAspect, String> {
+        let invalid = || format!("expected W:H with each side 1 to {}", Self::MAX);
+        let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+        let width = width.trim().parse().map_err(|_| invalid())?;
+        let height = height.trim().parse().map_err(|_| invalid())?;
+        PixelAspect::new(width, height).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for PixelAspect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+/// How many screen pixels the window uses for each 64x32 CHIP-8 pixel, before any pixel aspect
+/// is applied. Defaults to 8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowScale(u8);
+
+impl WindowScale {
+    /// The window's size in screen pixels, for 64x32 pixels of shape `pixel_aspect`.
+    fn window_size(self, pixel_aspect: PixelAspect) -> (usize, usize) {
+        let scale = usize::from(self.0);
+        (
+            FrameBuffer::WIDTH * pixel_aspect.width * scale,
+            FrameBuffer::HEIGHT * pixel_aspect.height * scale,
+        )
+    }
+}
+
+impl Default for WindowScale {
+    fn default() -> WindowScale {
+        WindowScale(8)
+    }
+}
+
+impl FromStr for WindowScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WindowScale, String> {
+        match s.trim().parse() {
+            Ok(scale @ (1 | 2 | 4 | 8 | 16)) => Ok(WindowScale(scale)),
+            _ => Err(format!(
+                "unsupported scale {:?}, expected 1, 2, 4, 8 or 16",
+                s
+            )),
+        }
+    }
+}
+
+/// Paint `frame` into `buffer`, which is `width` output pixels wide. Each logical pixel becomes a
+/// block of output pixels, smaller in high resolution mode so the display fills the same area;
+/// `pixel_map` gives, for even and odd rows, the cleared colour followed by the colours of the
+/// four plane states from unlit to lit on both planes. A pixel is cleared until a sprite draws over
+/// it.
+fn paint(
+    buffer: &mut [u32],
+    width: usize,
+    frame: &FrameBuffer,
+    pixel_map: &[[u32; 5]; 2],
+    block: (usize, usize),
+) {
+    let (block_width, block_height) = block;
+    for (y, row) in buffer.chunks_mut(width).enumerate() {
+        let colors = pixel_map[y % 2];
+        let start = (y / block_height) * frame.width();
+        let pixels = (start..start + frame.width()).zip(&frame.drawn()[start..]);
+        for (output, (i, drawn)) in row.chunks_mut(block_width).zip(pixels) {
+            let color = match frame.plane_states(i) {
+                0 => *drawn as usize,
+                planes => 1 + planes as usize,
+            };
+            output.fill(colors[color]);
+        }
+    }
+}
+
+// The colours `paint` takes for `palette`, with every other row darkened by `scanlines`
+fn pixel_map(palette: Palette, scanlines: f32) -> [[u32; 5]; 2] {
+    let colors = [
+        palette.color(0b00),
+        palette.color(0b00),
+        palette.color(0b01),
+        palette.color(0b10),
+        palette.color(0b11),
+    ];
+    [colors, colors.map(|color| darken(color, scanlines))]
+}
+
+/// The colours the display is shown in: a palette colour for each combination of the XO-CHIP bit
+/// planes, the colour of pixels nothing has drawn on since the last clear, and how much scanlines
+/// darken every other row of output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayColors {
+    // Colours for cleared pixels and each plane state, on even and odd output rows
+    pixel_map: [[u32; 5]; 2],
+}
+
+impl DisplayColors {
+    /// Cleared pixels take `clear_color` if given, otherwise the palette's background.
+    /// `scanlines` is from 0 (off) to 1 (black).
+    pub fn new(palette: Palette, clear_color: Option<u32>, scanlines: f32) -> DisplayColors {
+        let mut pixel_map = pixel_map(palette, scanlines);
+        if let Some(color) = clear_color {
+            pixel_map[0][0] = color;
+            pixel_map[1][0] = darken(color, scanlines);
+        }
+        DisplayColors { pixel_map }
+    }
+
+    /// Paint `frame` with each logical pixel as a `scale` by `scale` block, returning
+    /// `frame.width() * scale` pixels per row. Scanlines need at least two output rows per pixel,
+    /// so they are left out at a scale of 1.
+    pub fn paint(&self, frame: &FrameBuffer, scale: usize) -> Vec<u32> {
+        let scale = scale.max(1);
+        let width = frame.width() * scale;
+        let mut buffer = vec![0; width * frame.height() * scale];
+        let pixel_map = if scale == 1 {
+            [self.pixel_map[0]; 2]
+        } else {
+            self.pixel_map
+        };
+        paint(&mut buffer, width, frame, &pixel_map, (scale, scale));
+        buffer
+    }
+}
+
+impl Default for DisplayColors {
+    fn default() -> DisplayColors {
+        DisplayColors::new(Palette::default(), None, 0.0)
+    }
 }
 
 pub struct MiniFbWindow {
     window: minifb::Window,
+    frame: FrameBuffer,
     buffer: Vec<u32>,
+    presenter: Presenter,
     is_dirty: bool,
+    keypad: Keypad,
+    colors: DisplayColors,
+    recorder: Option<FrameRecorder>,
+    // Output pixels per logical pixel at 64x32, horizontally and vertically. Always even, so
+    // 128x64 gets half as many
+    block: (usize, usize),
 }
 
 impl MiniFbWindow {
-    const SPRITE_WIDTH: usize = 8;
-    const WIDTH: usize = 64;
-    const HEIGHT: usize = 32;
-    const BUFFER_SIZE: usize = Self::WIDTH * Self::HEIGHT;
-
-    const PIXEL_HI: u32 = 0x00FFBF00u32;
-    const PIXEL_LO: u32 = 0x00000000u32;
-    const PIXEL_MAP: [u32; 2] = [Self::PIXEL_LO, Self::PIXEL_HI];
-    const KEY_MAP: [minifb::Key; 16] = [
-        minifb::Key::X,    // 0
-        minifb::Key::Key1, // 1
-        minifb::Key::Key2, // 2
-        minifb::Key::Key3, // 3
-        minifb::Key::Q,    // 4
-        minifb::Key::W,    // 5
-        minifb::Key::E,    // 6
-        minifb::Key::A,    // 7
-        minifb::Key::S,    // 8
-        minifb::Key::D,    // 9
-        minifb::Key::Z,    // A
-        minifb::Key::C,    // B
-        minifb::Key::Key4, // C
-        minifb::Key::R,    // D
-        minifb::Key::F,    // E
-        minifb::Key::V,    // F
-    ];
-
-    pub fn new() -> MiniFbWindow {
+    /// Open a window showing the display in `colors`; programs that never select plane 2 only
+    /// show plane states 00 and 01. `pixel_aspect` stretches each presented pixel, leaving the
+    /// logical display unaffected. The window opens at `scale` screen pixels per 64x32 pixel,
+    /// stretched by the pixel aspect. Fails if there is no display to open it on.
+    pub fn new(
+        colors: DisplayColors,
+        pixel_aspect: PixelAspect,
+        scale: WindowScale,
+    ) -> Result<MiniFbWindow, minifb::Error> {
+        // Each logical pixel is at least two output pixels tall so that alternate rows can be
+        // scanlines
+        let block = (pixel_aspect.width * 2, pixel_aspect.height * 2);
+        let (width, height) = (FrameBuffer::WIDTH * block.0, FrameBuffer::HEIGHT * block.1);
+        // The buffer is stretched to fill the window, so the window's size sets the scale
+        let (window_width, window_height) = scale.window_size(pixel_aspect);
         let mut window = minifb::Window::new(
             "Chip8",
-            Self::WIDTH,
-            Self::HEIGHT,
+            window_width,
+            window_height,
             WindowOptions {
-                scale: minifb::Scale::X8,
                 scale_mode: minifb::ScaleMode::AspectRatioStretch,
                 resize: true,
                 ..WindowOptions::default()
             },
-        )
-        .expect("Unable to open Window");
+        )?;
 
         // Set update rate to 60fps
         window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
         window.update();
-        let buffer = vec![0; Self::BUFFER_SIZE];
-        MiniFbWindow {
+        let buffer = vec![colors.pixel_map[0][0]; width * height];
+        Ok(MiniFbWindow {
             window,
+            frame: FrameBuffer::new(),
+            presenter: Presenter::default(),
             buffer,
-            is_dirty: false,
-        }
+            // Paint the background on the first render
+            is_dirty: true,
+            keypad: Keypad::default(),
+            colors,
+            recorder: None,
+            block,
+        })
+    }
+
+    /// Present at most `limit` display changes per 60hz frame, as some interpreters do to reduce
+    /// flicker. Unlike waiting for vblank this never stalls the CPU: later changes update the
+    /// logical display (and so collisions and `frame_hash`) immediately but only become visible
+    /// in the next frame. Clears and scrolls count toward the limit like draws, and a 00E0
+    /// ignored by `--ignore-clear` does not count at all.
+    pub fn set_present_limit(&mut self, limit: Option<u32>) {
+        self.presenter.set_limit(limit, &self.frame);
+    }
+
+    /// Draw into a back buffer that only becomes visible when `present` is called, so embedders
+    /// that manage their own vsync decide when the screen flips. `render` presents once per
+    /// frame. The logical display (collisions, `frame_hash`) is always the back buffer.
+    pub fn set_double_buffered(&mut self, double_buffered: bool) {
+        self.presenter
+            .set_double_buffered(double_buffered, &self.frame);
+    }
+
+    /// Choose which keyboard keys stand in for the hex keypad.
+    pub fn set_keypad(&mut self, keypad: Keypad) {
+        self.keypad = keypad;
+    }
+
+    /// Save every rendered frame that differs from the last one.
+    pub fn set_frame_recorder(&mut self, recorder: FrameRecorder) {
+        self.recorder = Some(recorder);
     }
 }
 
 impl Window for MiniFbWindow {
     fn blank_screen(&mut self) {
-        for i in 0..Self::BUFFER_SIZE {
-            self.buffer[i] = Self::PIXEL_LO;
-        }
-        self.is_dirty = true;
-    }
-
-    fn draw(&mut self, x: u8, y: u8, sprite: Vec<u8>) -> bool {
-        let (x, y) = (x as usize, y as usize);
-        let mut collision = false;
-        for (y_offset, row) in sprite.iter().enumerate() {
-            for x_offset in 0..Self::SPRITE_WIDTH {
-                if (x_offset + x) >= Self::WIDTH || (y_offset + y) >= Self::HEIGHT {
-                    continue;
-                }
-
-                let pixel =
-                    Self::PIXEL_MAP[((row >> (Self::SPRITE_WIDTH - x_offset - 1)) & 0x1) as usize];
-                let pixel_index = (x + x_offset + ((y + y_offset) * Self::WIDTH)) as usize;
-                if pixel == Self::PIXEL_HI {
-                    if self.buffer[pixel_index] == Self::PIXEL_HI {
-                        self.buffer[pixel_index] = Self::PIXEL_LO;
-                        collision = true;
-                    } else {
-                        self.buffer[pixel_index] = Self::PIXEL_HI;
-                    }
-                }
-            }
-        }
-        self.is_dirty = true;
+        self.frame.clear();
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn draw(&mut self, x: u8, y: u8, width: u8, sprite: Vec<u8>) -> bool {
+        let collision = self.frame.draw(x, y, width, &sprite);
+        self.is_dirty |= self.presenter.changed(&self.frame);
         collision
     }
 
     fn render(&mut self) {
-        if !self.window.is_open() {
-            process::exit(0);
+        if !self.is_open() {
+            return;
         }
 
+        self.present();
+        self.is_dirty |= self.presenter.end_frame(&self.frame);
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn present(&mut self) {
+        self.is_dirty |= self.presenter.present(&self.frame);
         if self.is_dirty {
+            if let Some(recorder) = &self.recorder {
+                recorder.record(self.presenter.shown(&self.frame));
+            }
+            let (width, height) = (
+                FrameBuffer::WIDTH * self.block.0,
+                FrameBuffer::HEIGHT * self.block.1,
+            );
+            let frame = self.presenter.shown(&self.frame);
+            let block = if frame.is_hires() {
+                (self.block.0 / 2, self.block.1 / 2)
+            } else {
+                self.block
+            };
+            paint(
+                &mut self.buffer,
+                width,
+                frame,
+                &self.colors.pixel_map,
+                block,
+            );
+            self.is_dirty = false;
             self.window
-                .update_with_buffer(&self.buffer, Self::WIDTH, Self::HEIGHT)
+                .update_with_buffer(&self.buffer, width, height)
                 .expect("Failed to update window");
         } else {
             self.window.update();
@@ -127,15 +799,354 @@ impl Window for MiniFbWindow {
     }
 
     fn is_key_pressed(&self, key: u8) -> bool {
-        self.window.is_key_down(Self::KEY_MAP[key as usize])
+        self.window.is_key_down(self.keypad.chip8_to_physical(key))
     }
 
     fn get_pressed_key(&self) -> Option<u8> {
-        for (key_val, key) in Self::KEY_MAP.iter().enumerate() {
-            if self.window.is_key_down(*key) {
-                return Some(key_val as u8);
-            }
+        self.window
+            .get_keys()
+            .into_iter()
+            .filter_map(|key| self.keypad.physical_to_chip8(key))
+            .min()
+    }
+
+    fn pressed_hotkey(&self) -> Option<Hotkey> {
+        if self.window.is_key_pressed(minifb::Key::F12, KeyRepeat::No) {
+            return Some(Hotkey::Screenshot);
         }
-        None
+        if self.window.is_key_pressed(minifb::Key::F2, KeyRepeat::No) {
+            return Some(Hotkey::Mute);
+        }
+        if !self.window.is_key_pressed(minifb::Key::F1, KeyRepeat::No) {
+            return None;
+        }
+
+        if self.window.is_key_down(minifb::Key::LeftShift)
+            || self.window.is_key_down(minifb::Key::RightShift)
+        {
+            Some(Hotkey::HardReset)
+        } else {
+            Some(Hotkey::SoftReset)
+        }
+    }
+
+    fn frame_hash(&self) -> u64 {
+        self.frame.hash()
+    }
+
+    fn frame(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    fn screenshot(&self) -> Vec<u32> {
+        self.colors.paint(self.presenter.shown(&self.frame), 1)
+    }
+
+    fn export_sprite(&self, x: u8, y: u8, width: u8, height: u8) -> Vec<u8> {
+        self.frame.sprite(x, y, width, height)
+    }
+
+    fn clipped_draws(&self) -> u64 {
+        self.frame.clipped_draws()
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.frame.scroll_up(n as usize);
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.frame.scroll_down(n as usize);
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_left(&mut self, n: u8) {
+        self.frame.scroll_left(n as usize);
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn scroll_right(&mut self, n: u8) {
+        self.frame.scroll_right(n as usize);
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_clip(&mut self, x: u8, y: u8, width: u8, height: u8) {
+        self.frame
+            .set_clip(x as usize, y as usize, width as usize, height as usize);
+    }
+
+    fn clear_clip(&mut self) {
+        self.frame.clear_clip();
+    }
+
+    fn set_wrap(&mut self, wrap: bool) {
+        self.frame.set_wrap(wrap);
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.frame.set_hires(hires);
+        self.is_dirty |= self.presenter.changed(&self.frame);
+    }
+
+    fn set_planes(&mut self, planes: u8) {
+        self.frame.set_planes(planes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{Chip8Mmu, Mmu};
+    use arbintrary::uint;
+
+    #[test]
+    fn headless_window_draws_to_frame() {
+        let mut window = HeadlessWindow::new();
+
+        assert!(!window.draw(0, 0, 8, vec![0x80]));
+        assert!(window.draw(0, 0, 8, vec![0x80]));
+        assert_eq!(FrameBuffer::new().hash(), window.frame_hash());
+    }
+
+    #[test]
+    fn exports_drawn_glyph_as_font_data() {
+        let mmu = Chip8Mmu::new();
+        // The font glyph for 8
+        let glyph: Vec<u8> = (40..45)
+            .map(|address| mmu.read_u8(uint::<12>::new(address)))
+            .collect();
+        let mut window = HeadlessWindow::new();
+
+        window.draw(10, 4, 8, glyph.clone());
+
+        assert_eq!(glyph, window.export_sprite(10, 4, 8, 5));
+        assert_eq!(vec![0x0F, 0x09], window.export_sprite(6, 4, 8, 2));
+    }
+
+    #[test]
+    fn headless_window_reports_shared_keys() {
+        let window = HeadlessWindow::new();
+        assert_eq!(None, window.get_pressed_key());
+
+        window.keys().store(0b1010_0000, Ordering::Relaxed);
+
+        assert!(window.is_key_pressed(0x5));
+        assert!(!window.is_key_pressed(0x6));
+        assert_eq!(Some(0x5), window.get_pressed_key());
+    }
+
+    #[test]
+    fn headless_window_ignores_keys_past_0xf() {
+        let window = HeadlessWindow::new();
+        window.keys().store(0xFFFF, Ordering::Relaxed);
+
+        assert!(window.is_key_pressed(0xF));
+        assert!(!window.is_key_pressed(0x10));
+        assert!(!window.is_key_pressed(0xFF));
+    }
+
+    #[test]
+    fn darken_scales_each_channel() {
+        assert_eq!(0x00FFBF00, darken(0x00FFBF00, 0.0));
+        assert_eq!(0x00806000, darken(0x00FFBF00, 0.5));
+        assert_eq!(0x00000000, darken(0x00FFBF00, 1.0));
+        assert_eq!(0x00000000, darken(0x00FFBF00, 1.5));
+    }
+
+    #[test]
+    fn double_buffered_draws_stay_hidden_until_present() {
+        let mut window = HeadlessWindow::new();
+        window.set_double_buffered(true);
+
+        window.draw(0, 0, 8, vec![0x80]);
+        window.blank_screen();
+        window.draw(8, 0, 8, vec![0x80]);
+
+        assert_eq!(&FrameBuffer::new(), window.presenter.shown(&window.frame));
+        assert_eq!(0, window.presents());
+
+        window.present();
+
+        assert_eq!(&window.frame, window.presenter.shown(&window.frame));
+        assert_eq!(1, window.presents());
+    }
+
+    #[test]
+    fn double_buffered_render_presents_once() {
+        let mut window = HeadlessWindow::new();
+        window.set_double_buffered(true);
+        window.draw(0, 0, 8, vec![0x80]);
+        window.draw(8, 0, 8, vec![0x80]);
+
+        window.render();
+        window.render();
+
+        assert_eq!(&window.frame, window.presenter.shown(&window.frame));
+        assert_eq!(1, window.presents());
+    }
+
+    #[test]
+    fn parses_pixel_aspect() {
+        assert_eq!(Ok(PixelAspect::new(2, 1).unwrap()), "2:1".parse());
+        assert_eq!(Ok(PixelAspect::default()), "1:1".parse());
+        assert!("2".parse::<PixelAspect>().is_err());
+        assert!("0:1".parse::<PixelAspect>().is_err());
+        assert!("5:1".parse::<PixelAspect>().is_err());
+        assert!("a:b".parse::<PixelAspect>().is_err());
+    }
+
+    #[test]
+    fn parses_window_scale() {
+        assert_eq!(Ok(WindowScale(1)), "1".parse());
+        assert_eq!(Ok(WindowScale(16)), "16".parse());
+        assert_eq!(
+            Err("unsupported scale \"3\", expected 1, 2, 4, 8 or 16".to_string()),
+            "3".parse::<WindowScale>()
+        );
+        assert!("x".parse::<WindowScale>().is_err());
+    }
+
+    #[test]
+    fn window_scale_counts_screen_pixels_per_chip8_pixel() {
+        assert_eq!(
+            (512, 256),
+            WindowScale::default().window_size(PixelAspect::default())
+        );
+        assert_eq!((64, 32), WindowScale(1).window_size(PixelAspect::default()));
+        assert_eq!(
+            (256, 64),
+            WindowScale(2).window_size(PixelAspect::new(2, 1).unwrap())
+        );
+    }
+
+    #[test]
+    fn paint_stretches_pixels_into_blocks() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(1, 0, 8, &[0x80]);
+        let width = FrameBuffer::WIDTH * 4;
+        let mut buffer = vec![0; width * FrameBuffer::HEIGHT * 2];
+
+        paint(
+            &mut buffer,
+            width,
+            &frame,
+            &[[0, 0, 1, 0, 0], [0, 0, 2, 0, 0]],
+            (4, 2),
+        );
+
+        assert_eq!([0, 0, 0, 0, 1, 1, 1, 1, 0], buffer[..9]);
+        assert_eq!([0, 0, 0, 0, 2, 2, 2, 2, 0], buffer[width..][..9]);
+        assert_eq!(0, buffer[width * 2 + 4]);
+    }
+
+    #[test]
+    fn paint_fills_same_area_in_hires() {
+        let mut frame = FrameBuffer::new();
+        frame.set_hires(true);
+        frame.draw(127, 63, 8, &[0x80]);
+        let width = FrameBuffer::WIDTH * 2;
+        let mut buffer = vec![0; width * FrameBuffer::HEIGHT * 2];
+
+        paint(&mut buffer, width, &frame, &[[0, 0, 1, 0, 0]; 2], (1, 1));
+
+        assert_eq!(1, *buffer.last().unwrap());
+        assert_eq!(1, buffer.iter().sum::<u32>());
+    }
+
+    #[test]
+    fn paint_uses_clear_color_until_drawn() {
+        let mut frame = FrameBuffer::new();
+        frame.draw(0, 0, 8, &[0xC0]);
+        frame.draw(0, 0, 8, &[0x80]);
+        let mut buffer = vec![0; FrameBuffer::WIDTH * FrameBuffer::HEIGHT];
+        let pixel_map = [[7, 8, 9, 0, 0]; 2];
+
+        paint(&mut buffer, FrameBuffer::WIDTH, &frame, &pixel_map, (1, 1));
+        assert_eq!([8, 9, 7], buffer[..3]);
+
+        frame.clear();
+        paint(&mut buffer, FrameBuffer::WIDTH, &frame, &pixel_map, (1, 1));
+        assert!(buffer.iter().all(|color| *color == 7));
+    }
+
+    #[test]
+    fn paint_maps_plane_states_to_palette() {
+        let mut frame = FrameBuffer::new();
+        frame.set_planes(0b11);
+        frame.draw(0, 0, 8, &[0b1010_0000, 0b0110_0000]);
+        let mut buffer = vec![0; FrameBuffer::WIDTH * FrameBuffer::HEIGHT];
+        let pixel_map = [[1, 2, 3, 4, 5]; 2];
+
+        paint(&mut buffer, FrameBuffer::WIDTH, &frame, &pixel_map, (1, 1));
+
+        assert_eq!([3, 4, 5, 1], buffer[..4]);
+    }
+
+    #[test]
+    fn presents_every_draw_without_limit() {
+        let mut window = HeadlessWindow::new();
+
+        for _ in 0..5 {
+            window.draw(0, 0, 8, vec![0x80]);
+        }
+
+        assert_eq!(5, window.presents());
+    }
+
+    #[test]
+    fn present_limit_coalesces_draws_into_next_frame() {
+        let mut window = HeadlessWindow::new();
+        window.set_present_limit(Some(2));
+
+        for _ in 0..5 {
+            window.draw(0, 0, 8, vec![0x80]);
+        }
+        assert_eq!(2, window.presents());
+        assert_ne!(FrameBuffer::new().hash(), window.frame_hash());
+
+        window.render();
+        assert_eq!(3, window.presents());
+
+        // The coalesced present counts toward the new frame's limit
+        window.draw(0, 0, 8, vec![0x80]);
+        window.draw(0, 0, 8, vec![0x80]);
+        assert_eq!(4, window.presents());
+    }
+
+    #[test]
+    fn presents_live_display_without_copying_it() {
+        let mut window = HeadlessWindow::new();
+
+        window.draw(0, 0, 8, vec![0x80]);
+
+        assert!(std::ptr::eq(
+            &window.frame,
+            window.presenter.shown(&window.frame)
+        ));
+        assert_eq!(FrameBuffer::new(), window.presenter.presented);
+        assert_eq!(1, window.presents());
+    }
+
+    #[test]
+    fn scroll_down_moves_loaded_pattern() {
+        let mut pixels = vec![false; FrameBuffer::WIDTH * FrameBuffer::HEIGHT];
+        pixels[0] = true;
+        pixels[FrameBuffer::WIDTH + 1] = true;
+        let mut window = HeadlessWindow::new();
+        window.set_framebuffer(&pixels);
+
+        window.scroll_down(4);
+
+        let text = window.framebuffer_text();
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(FrameBuffer::HEIGHT, rows.len());
+        assert!(rows[..4].iter().all(|row| !row.contains('#')));
+        assert!(rows[4].starts_with("#."));
+        assert!(rows[5].starts_with(".#"));
+        assert_eq!(2, text.matches('#').count());
     }
 }